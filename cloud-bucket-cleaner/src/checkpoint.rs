@@ -0,0 +1,52 @@
+//! The last day whose downloaded files were cleaned, stored in the bucket next to the files,
+//! so the next run can catch up on the days a failed or skipped run left behind.
+
+use chrono::NaiveDate;
+use tracing::warn;
+use wave_store::{StoreErr, WaveStore};
+
+/// Key of the checkpoint in the bucket. It doesn't look like a file id, so it is never deleted by the cleaner.
+pub const CHECKPOINT_KEY: &str = "cleaner/last_cleaned_date";
+
+const DATE_FORMAT: &str = "%F";
+
+/// Returns the last day whose downloaded files were all deleted, if the cleaner ran before.
+/// A checkpoint that can't be read as a date is ignored, as if the cleaner never ran.
+pub async fn load(store: &dyn WaveStore) -> Result<Option<NaiveDate>, StoreErr> {
+    let body = match store.get(CHECKPOINT_KEY).await? {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+
+    let date = std::str::from_utf8(&body)
+        .ok()
+        .and_then(|date| NaiveDate::parse_from_str(date.trim(), DATE_FORMAT).ok());
+    if date.is_none() {
+        warn!("Ignoring invalid checkpoint: {:?}", String::from_utf8_lossy(&body));
+    }
+    Ok(date)
+}
+
+pub async fn save(date: NaiveDate, store: &dyn WaveStore) -> Result<(), StoreErr> {
+    store.put(CHECKPOINT_KEY, date.format(DATE_FORMAT).to_string().into_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wave_store::InMemoryStore;
+
+    #[tokio::test]
+    async fn test_save_and_load() {
+        let store = InMemoryStore::new();
+        assert_eq!(load(&store).await.unwrap(), None);
+
+        let date = NaiveDate::from_ymd(2022, 7, 26);
+        save(date, &store).await.unwrap();
+        assert_eq!(store.get(CHECKPOINT_KEY).await.unwrap(), Some(b"2022-07-26".to_vec()));
+        assert_eq!(load(&store).await.unwrap(), Some(date));
+
+        store.put(CHECKPOINT_KEY, b"yesterday".to_vec()).await.unwrap();
+        assert_eq!(load(&store).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,608 @@
+//! Deletes files in batches, several of them at the same time.
+//!
+//! All requests share a token bucket, which limits the number of objects deleted per second, and the slots of
+//! the concurrency limit. The copies into the trash and the marks of the items of the deleted files are sent
+//! as requests of their own, within the same limits.
+//! Failed requests and throttled keys are retried with a jittered backoff. Once the deadline of the invocation
+//! comes close, no new batches are started; the files left behind are deleted by the next run.
+
+use std::{collections::HashSet, future::Future, pin::Pin, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use chrono::{DateTime, Utc};
+use futures_util::{stream::{self, FuturesUnordered}, StreamExt};
+use tokio::sync::Semaphore;
+use tracing::{info, warn, error};
+use wave_store::{backoff, BatchDeleteResult, StoreErr, WaveStore, MAX_BATCH_SIZE};
+use wave_table::WaveRepository;
+
+use crate::{mark_items_of_file, trash, CleanupReport, DeleteReason};
+
+/// Determines how fast files are deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineConfig {
+    /// Number of requests, which are sent at the same time.
+    /// Copying a file into the trash and marking the items of a file count as one request each.
+    pub concurrency: usize,
+    /// Number of objects deleted, or files whose items are marked, per second at most.
+    /// Up to a second worth of objects can be deleted at once.
+    pub objects_per_second: u32,
+    /// Number of times a request is retried, before its keys are reported as failed.
+    pub max_retries: u32,
+    /// Delay before the first retry, which is doubled for each following retry.
+    pub base_delay: Duration,
+    /// Time left before the deadline, at which no new batches are started.
+    pub deadline_reserve: Duration,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            concurrency: 4,
+            objects_per_second: 3000,
+            max_retries: 3,
+            base_delay: Duration::from_millis(200),
+            deadline_reserve: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Hands out the objects a request may delete, refilled at a constant rate.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(objects_per_second: u32, now: Instant) -> Self {
+        let rate = objects_per_second.max(1) as f64;
+        TokenBucket { rate, capacity: rate, tokens: rate, updated: now }
+    }
+
+    /// Takes the tokens of `objects` and returns how long to wait before they can be deleted.
+    /// The tokens can go below zero, so requests waiting at the same time are sent one after the other.
+    fn reserve(&mut self, objects: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = self.updated.max(now);
+        self.tokens -= objects as f64;
+        match self.tokens < 0. {
+            true => Duration::from_secs_f64(-self.tokens / self.rate),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+/// S3 answers with `SlowDown` or `503 Service Unavailable` when too many requests are sent to a prefix.
+fn is_throttling(err: &StoreErr) -> bool {
+    let message = err.to_string();
+    ["SlowDown", "Throttl", "503", "RequestLimitExceeded"].iter().any(|code| message.contains(code))
+}
+
+/// A batch, after the engine tried to delete it.
+struct Outcome {
+    deleted: Vec<(String, DeleteReason)>,
+    /// Bytes of the deleted files, files moved into the trash still take up their space.
+    reclaimed_bytes: i64,
+    failed: Vec<(String, StoreErr)>,
+    marked_deleted: Vec<String>,
+}
+
+type Batch<'a> = Pin<Box<dyn Future<Output = Outcome> + Send + 'a>>;
+
+/// Sends the delete requests of a run, at most `concurrency` at the same time.
+///
+/// Submitting waits until a slot is free. The batches are only deleted while the engine is awaited, which is
+/// while waiting for a slot, in `while_deleting` and in `finish`. Their outcome is added to the report.
+pub struct DeletionEngine<'a> {
+    pub repository: &'a dyn WaveRepository,
+    pub store: &'a dyn WaveStore,
+    config: Arc<EngineConfig>,
+    bucket: Arc<Mutex<TokenBucket>>,
+    slots: Arc<Semaphore>,
+    deadline: Option<DateTime<Utc>>,
+    in_flight: FuturesUnordered<Batch<'a>>,
+}
+
+impl<'a> DeletionEngine<'a> {
+    pub fn new(config: &EngineConfig, deadline: Option<DateTime<Utc>>, repository: &'a dyn WaveRepository, store: &'a dyn WaveStore) -> Self {
+        DeletionEngine {
+            repository,
+            store,
+            config: Arc::new(config.clone()),
+            bucket: Arc::new(Mutex::new(TokenBucket::new(config.objects_per_second, Instant::now()))),
+            slots: Arc::new(Semaphore::new(config.concurrency.max(1))),
+            deadline,
+            in_flight: FuturesUnordered::new(),
+        }
+    }
+
+    /// Whether there is enough time left to start another batch.
+    pub fn has_time(&self) -> bool {
+        let reserve = chrono::Duration::from_std(self.config.deadline_reserve).unwrap_or_else(|_| chrono::Duration::zero());
+        match self.deadline {
+            Some(deadline) => Utc::now() + reserve < deadline,
+            None => true,
+        }
+    }
+
+    /// Starts deleting the files in batches of at most `MAX_BATCH_SIZE` keys. Each file is given with its size in bytes.
+    /// The items of the deleted files are marked as deleted. In a dry run, the files are only added to the report.
+    /// With soft deletes, the files are moved into the trash instead of being deleted.
+    ///
+    /// Without time left, the files are skipped and the report is marked as timed out.
+    pub async fn submit(&mut self, files: Vec<(String, DeleteReason, i64)>, report: &mut CleanupReport) {
+        if files.is_empty() {
+            return;
+        }
+        if report.dry_run {
+            info!("Would delete {} objects", files.len());
+            for (key, reason, size) in files {
+                if !report.soft_delete {
+                    report.reclaimed_bytes += size;
+                }
+                report.deleted.push((key, reason));
+            }
+            return;
+        }
+
+        let mut files = files.into_iter().peekable();
+        while files.peek().is_some() {
+            let batch: Vec<(String, DeleteReason, i64)> = files.by_ref().take(MAX_BATCH_SIZE).collect();
+            while self.in_flight.len() >= self.config.concurrency.max(1) {
+                self.wait_for_batch(report).await;
+            }
+            if !self.has_time() {
+                warn!("Stopping before the deadline, skipping {} objects", batch.len() + files.len());
+                report.timed_out = true;
+                return;
+            }
+
+            let limits = Limits { config: self.config.clone(), bucket: self.bucket.clone(), slots: self.slots.clone() };
+            let (repository, store, soft_delete) = (self.repository, self.store, report.soft_delete);
+            self.in_flight.push(Box::pin(async move {
+                delete_batch(batch, soft_delete, &limits, repository, store).await
+            }));
+        }
+    }
+
+    /// Runs `future`, e.g. the listing of the next page, while the submitted batches keep being deleted.
+    pub async fn while_deleting<F: Future>(&mut self, future: F, report: &mut CleanupReport) -> F::Output {
+        tokio::pin!(future);
+        loop {
+            tokio::select! {
+                output = &mut future => return output,
+                Some(outcome) = self.in_flight.next(), if !self.in_flight.is_empty() => add_outcome(outcome, report),
+            }
+        }
+    }
+
+    /// Waits for all batches, which are still being deleted.
+    pub async fn finish(&mut self, report: &mut CleanupReport) {
+        while !self.in_flight.is_empty() {
+            self.wait_for_batch(report).await;
+        }
+    }
+
+    async fn wait_for_batch(&mut self, report: &mut CleanupReport) {
+        if let Some(outcome) = self.in_flight.next().await {
+            add_outcome(outcome, report);
+        }
+    }
+}
+
+fn add_outcome(outcome: Outcome, report: &mut CleanupReport) {
+    match report.soft_delete {
+        true => info!("Moved {} objects to the trash", outcome.deleted.len()),
+        false => info!("Deleted {} objects", outcome.deleted.len()),
+    }
+    for (key, e) in &outcome.failed {
+        error!("Unable to delete {}: {}", key, e);
+    }
+    report.deleted.extend(outcome.deleted);
+    report.reclaimed_bytes += outcome.reclaimed_bytes;
+    report.failed.extend(outcome.failed);
+    report.marked_deleted.extend(outcome.marked_deleted);
+}
+
+/// The limits shared by all requests of the engine.
+struct Limits {
+    config: Arc<EngineConfig>,
+    bucket: Arc<Mutex<TokenBucket>>,
+    slots: Arc<Semaphore>,
+}
+
+impl Limits {
+    /// Waits until `objects` may be deleted.
+    async fn reserve(&self, objects: usize) {
+        // a poisoned lock only means another batch panicked, the bucket itself is still valid
+        let wait = self.bucket.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).reserve(objects, Instant::now());
+        if wait > Duration::ZERO {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Waits until a slot is free, the request is sent while the permit is held.
+    async fn slot(&self) -> tokio::sync::SemaphorePermit<'_> {
+        // the semaphore is never closed
+        self.slots.acquire().await.expect("closed semaphore")
+    }
+}
+
+/// Deletes a batch, retrying the whole request when it fails and the keys S3 throttled.
+/// Other errors of single keys are final, e.g. missing permissions.
+async fn delete_batch(
+    files: Vec<(String, DeleteReason, i64)>,
+    soft_delete: bool,
+    limits: &Limits,
+    repository: &dyn WaveRepository,
+    store: &dyn WaveStore)
+-> Outcome {
+    let config = &limits.config;
+    let mut pending: Vec<String> = files.iter().map(|(key, _, _)| key.clone()).collect();
+    let mut deleted: HashSet<String> = HashSet::new();
+    let mut failed = vec![];
+    let mut attempt = 0;
+    while !pending.is_empty() {
+        limits.reserve(pending.len()).await;
+        let result = match soft_delete {
+            true => move_to_trash(&pending, limits, store).await,
+            false => {
+                let _slot = limits.slot().await;
+                store.delete_batch(&pending).await
+            },
+        };
+        let retry = match result {
+            Ok(BatchDeleteResult { deleted: batch_deleted, failed: batch_failed }) => {
+                deleted.extend(batch_deleted);
+                let (throttled, other): (Vec<_>, Vec<_>) = batch_failed.into_iter().partition(|(_, e)| is_throttling(e));
+                failed.extend(other);
+                throttled
+            },
+            Err(e) => pending.iter().map(|key| (key.clone(), StoreErr::new(&e.to_string()))).collect(),
+        };
+
+        if retry.is_empty() {
+            break;
+        }
+        if attempt >= config.max_retries {
+            failed.extend(retry);
+            break;
+        }
+        let delay = backoff(config.base_delay, attempt);
+        warn!("Retrying {} objects in {:?}: {}", retry.len(), delay, retry[0].1);
+        tokio::time::sleep(delay).await;
+        pending = retry.into_iter().map(|(key, _)| key).collect();
+        attempt += 1;
+    }
+
+    let files: Vec<(String, DeleteReason, i64)> = files.into_iter().filter(|(key, _, _)| deleted.contains(key)).collect();
+    let reclaimed_bytes = match soft_delete {
+        true => 0,
+        false => files.iter().map(|(_, _, size)| size).sum(),
+    };
+    let files: Vec<(String, DeleteReason)> = files.into_iter().map(|(key, reason, _)| (key, reason)).collect();
+    let deleted_at = Utc::now().timestamp();
+    let marks: Vec<_> = files
+        .iter()
+        .filter(|(_, reason)| !matches!(reason, DeleteReason::Orphaned { .. }))
+        .map(|(key, _)| mark_items(key, deleted_at, limits, repository))
+        .collect();
+    let marked_deleted = stream::iter(marks).buffer_unordered(config.concurrency.max(1)).concat().await;
+    Outcome { deleted: files, reclaimed_bytes, failed, marked_deleted }
+}
+
+/// Moves objects into the trash by copying each of them, and deleting the copied objects in a batch.
+/// Every copy is a request of its own, which waits for a slot like the other requests.
+/// Objects that can't be copied stay where they are and are reported as failed, like objects that can't be deleted.
+async fn move_to_trash(keys: &[String], limits: &Limits, store: &dyn WaveStore) -> Result<BatchDeleteResult, StoreErr> {
+    let copies: Vec<_> = keys.iter().map(|key| copy_to_trash(key, limits, store)).collect();
+    let copies: Vec<(&String, Result<(), StoreErr>)> = stream::iter(copies).buffered(limits.config.concurrency.max(1)).collect().await;
+    let mut copied = vec![];
+    let mut failed = vec![];
+    for (key, result) in copies {
+        match result {
+            Ok(()) => copied.push(key.clone()),
+            Err(e) => failed.push((key.clone(), e)),
+        }
+    }
+
+    let mut result = {
+        let _slot = limits.slot().await;
+        store.delete_batch(&copied).await?
+    };
+    result.failed.extend(failed);
+    Ok(result)
+}
+
+async fn copy_to_trash<'k>(key: &'k String, limits: &Limits, store: &dyn WaveStore) -> (&'k String, Result<(), StoreErr>) {
+    let _slot = limits.slot().await;
+    (key, trash::copy_to_trash(key, store).await)
+}
+
+/// Marks the items of a deleted file, as a request of the engine.
+async fn mark_items(key: &str, deleted_at: i64, limits: &Limits, repository: &dyn WaveRepository) -> Vec<String> {
+    limits.reserve(1).await;
+    let _slot = limits.slot().await;
+    mark_items_of_file(key, deleted_at, repository).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use chrono::NaiveDate;
+    use wave_store::{InMemoryStore, ListPage, ObjectInfo, ObjectUpload};
+    use wave_table::{FileRef, InMemoryRepository, RepositoryErr, WaveItem};
+
+    /// A store, which throttles the first deletes of each key and counts the requests in flight.
+    #[derive(Default)]
+    struct ThrottlingStore {
+        inner: InMemoryStore,
+        /// Number of times each key is throttled, before it is deleted
+        throttle_times: usize,
+        attempts: Mutex<Vec<String>>,
+        /// Number of requests being sent right now, and the most there ever were at the same time
+        active: Mutex<(usize, usize)>,
+    }
+
+    impl ThrottlingStore {
+        /// Takes a while, so the requests sent at the same time overlap.
+        async fn send_request(&self) {
+            {
+                let mut active = self.active.lock().unwrap();
+                active.0 += 1;
+                active.1 = active.1.max(active.0);
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            self.active.lock().unwrap().0 -= 1;
+        }
+    }
+
+    #[async_trait]
+    impl WaveStore for ThrottlingStore {
+        async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), StoreErr> {
+            self.inner.put(key, body).await
+        }
+
+        async fn start_upload(&self, key: &str) -> Result<Box<dyn ObjectUpload + '_>, StoreErr> {
+            self.inner.start_upload(key).await
+        }
+
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreErr> {
+            self.inner.get(key).await
+        }
+
+        async fn head(&self, key: &str) -> Result<Option<ObjectInfo>, StoreErr> {
+            self.inner.head(key).await
+        }
+
+        async fn list(&self, prefix: Option<&str>, continuation_token: Option<&str>) -> Result<ListPage, StoreErr> {
+            self.inner.list(prefix, continuation_token).await
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), StoreErr> {
+            self.inner.delete(key).await
+        }
+
+        async fn copy(&self, from: &str, to: &str) -> Result<(), StoreErr> {
+            self.send_request().await;
+            self.inner.copy(from, to).await
+        }
+
+        async fn delete_batch(&self, keys: &[String]) -> Result<BatchDeleteResult, StoreErr> {
+            self.send_request().await;
+
+            let (throttled, allowed): (Vec<String>, Vec<String>) = {
+                let mut attempts = self.attempts.lock().unwrap();
+                let split = keys
+                    .iter()
+                    .cloned()
+                    .partition(|key| attempts.iter().filter(|attempt| *attempt == key).count() < self.throttle_times);
+                attempts.extend(keys.iter().cloned());
+                split
+            };
+
+            let mut result = self.inner.delete_batch(&allowed).await?;
+            result.failed.extend(throttled.into_iter().map(|key| (key, StoreErr::new("SlowDown: Please reduce your request rate."))));
+            Ok(result)
+        }
+    }
+
+    /// A repository, which counts the lookups of the items of a file in flight.
+    #[derive(Default)]
+    struct CountingRepository {
+        inner: InMemoryRepository,
+        /// Number of lookups being sent right now, and the most there ever were at the same time
+        active: Mutex<(usize, usize)>,
+    }
+
+    #[async_trait]
+    impl WaveRepository for CountingRepository {
+        async fn put(&self, item: WaveItem) -> Result<(), RepositoryErr> {
+            self.inner.put(item).await
+        }
+
+        async fn get(&self, id: &str) -> Result<Option<WaveItem>, RepositoryErr> {
+            self.inner.get(id).await
+        }
+
+        async fn query_by_date(&self, date: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+            self.inner.query_by_date(date).await
+        }
+
+        async fn query_by_content_hash(&self, content_hash: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+            self.inner.query_by_content_hash(content_hash).await
+        }
+
+        async fn query_by_object_key(&self, object_key: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+            {
+                let mut active = self.active.lock().unwrap();
+                active.0 += 1;
+                active.1 = active.1.max(active.0);
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            self.active.lock().unwrap().0 -= 1;
+            self.inner.query_by_object_key(object_key).await
+        }
+
+        async fn query_by_batch(&self, batch_id: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+            self.inner.query_by_batch(batch_id).await
+        }
+    }
+
+    fn config() -> EngineConfig {
+        EngineConfig { base_delay: Duration::from_millis(1), ..EngineConfig::default() }
+    }
+
+    /// Files of a single byte, which were downloaded.
+    fn downloaded(keys: &[&str]) -> Vec<(String, DeleteReason, i64)> {
+        let date = NaiveDate::from_ymd(2022, 7, 26);
+        keys.iter().map(|key| (key.to_string(), DeleteReason::Downloaded { date }, 1)).collect()
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10, now);
+        assert_eq!(bucket.reserve(10, now), Duration::ZERO);
+        assert_eq!(bucket.reserve(5, now), Duration::from_millis(500));
+        // waiting requests queue up behind each other
+        assert_eq!(bucket.reserve(5, now), Duration::from_secs(1));
+        assert_eq!(bucket.reserve(1, now + Duration::from_secs(2)), Duration::ZERO);
+        // the bucket never holds more than a second worth of objects
+        assert_eq!(bucket.reserve(11, now + Duration::from_secs(10)), Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_retry_throttled_keys() {
+        let repository = InMemoryRepository::new();
+        let store = ThrottlingStore { throttle_times: 2, ..Default::default() };
+        for key in ["a.wav", "b.wav"] {
+            store.put(key, vec![0]).await.unwrap();
+        }
+
+        let mut report = CleanupReport::default();
+        let mut engine = DeletionEngine::new(&config(), None, &repository, &store);
+        engine.submit(downloaded(&["a.wav", "b.wav"]), &mut report).await;
+        engine.finish(&mut report).await;
+        let date = NaiveDate::from_ymd(2022, 7, 26);
+        assert_eq!(report.deleted, vec![("a.wav".to_owned(), DeleteReason::Downloaded { date }), ("b.wav".to_owned(), DeleteReason::Downloaded { date })]);
+        assert_eq!(report.reclaimed_bytes, 2);
+        assert!(report.failed.is_empty());
+        assert_eq!(store.attempts.lock().unwrap().len(), 6);
+
+        // keys throttled more often than the retries allow are reported as failed
+        store.put("c.wav", vec![0]).await.unwrap();
+        let mut report = CleanupReport::default();
+        let config = EngineConfig { max_retries: 1, ..config() };
+        let mut engine = DeletionEngine::new(&config, None, &repository, &store);
+        engine.submit(downloaded(&["c.wav"]), &mut report).await;
+        engine.finish(&mut report).await;
+        assert!(report.deleted.is_empty());
+        assert_eq!(report.failed.len(), 1);
+        assert!(store.head("c.wav").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_bounded_concurrency() {
+        let repository = InMemoryRepository::new();
+        let store = ThrottlingStore::default();
+        let keys: Vec<String> = (0..MAX_BATCH_SIZE * 5).map(|i| format!("{}.wav", i)).collect();
+        for key in &keys {
+            store.put(key, vec![0]).await.unwrap();
+        }
+
+        let mut report = CleanupReport::default();
+        let config = EngineConfig { concurrency: 2, objects_per_second: 1_000_000, ..config() };
+        let mut engine = DeletionEngine::new(&config, None, &repository, &store);
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        engine.submit(downloaded(&keys), &mut report).await;
+        engine.finish(&mut report).await;
+        assert_eq!(report.deleted.len(), keys.len());
+        assert_eq!(store.active.lock().unwrap().1, 2);
+    }
+
+    #[tokio::test]
+    async fn test_move_to_trash() {
+        let repository = InMemoryRepository::new();
+        let store = ThrottlingStore::default();
+        let keys: Vec<String> = (0..10).map(|i| format!("{}.wav", i)).collect();
+        for key in &keys {
+            store.put(key, vec![1, 2]).await.unwrap();
+        }
+
+        // the copies are sent at the same time, within the concurrency of the engine
+        let mut report = CleanupReport { soft_delete: true, ..Default::default() };
+        let config = EngineConfig { concurrency: 2, ..config() };
+        let mut engine = DeletionEngine::new(&config, None, &repository, &store);
+        let keys: Vec<&str> = keys.iter().map(String::as_str).chain(["missing.wav"]).collect();
+        engine.submit(downloaded(&keys), &mut report).await;
+        engine.finish(&mut report).await;
+        assert_eq!(report.deleted.len(), 10);
+        assert_eq!(report.reclaimed_bytes, 0);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, "missing.wav");
+        assert_eq!(store.get("0.wav").await.unwrap(), None);
+        assert_eq!(store.get("trash/0.wav").await.unwrap(), Some(vec![1, 2]));
+        assert_eq!(store.active.lock().unwrap().1, 2);
+    }
+
+    #[tokio::test]
+    async fn test_mark_items_with_bounded_concurrency() {
+        let repository = CountingRepository::default();
+        let store = InMemoryStore::new();
+        let keys: Vec<String> = (0..10).map(|i| format!("{}.wav", i)).collect();
+        for key in &keys {
+            store.put(key, vec![0]).await.unwrap();
+        }
+
+        let mut report = CleanupReport::default();
+        let config = EngineConfig { concurrency: 2, ..config() };
+        let mut engine = DeletionEngine::new(&config, None, &repository, &store);
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        engine.submit(downloaded(&keys), &mut report).await;
+        engine.finish(&mut report).await;
+        assert_eq!(report.deleted.len(), keys.len());
+        assert_eq!(repository.active.lock().unwrap().1, 2);
+    }
+
+    #[tokio::test]
+    async fn test_delete_while_listing() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        store.put("a.wav", vec![0]).await.unwrap();
+
+        // the batch is deleted while the engine waits for something else, e.g. the next page of the bucket
+        let mut report = CleanupReport::default();
+        let mut engine = DeletionEngine::new(&config(), None, &repository, &store);
+        engine.submit(downloaded(&["a.wav"]), &mut report).await;
+        let listing = async {
+            while store.head("a.wav").await.unwrap().is_some() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(1), engine.while_deleting(listing, &mut report)).await.unwrap();
+        engine.finish(&mut report).await;
+        assert_eq!(report.deleted.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stop_before_deadline() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        store.put("a.wav", vec![0]).await.unwrap();
+
+        let mut report = CleanupReport::default();
+        let deadline = Utc::now() + chrono::Duration::seconds(5);
+        let mut engine = DeletionEngine::new(&config(), Some(deadline), &repository, &store);
+        assert!(!engine.has_time());
+        engine.submit(downloaded(&["a.wav"]), &mut report).await;
+        engine.finish(&mut report).await;
+        assert!(report.timed_out);
+        assert!(report.deleted.is_empty());
+        assert!(store.head("a.wav").await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,1379 @@
+//! The logic of the bucket cleaner lambda, which deletes downloaded and expired files once a day.
+
+mod checkpoint;
+mod engine;
+pub mod policy;
+mod time;
+mod trash;
+mod usage;
+
+use std::{collections::{HashMap, HashSet}, fmt::Display, error};
+
+use chrono::{DateTime, NaiveDate, TimeZone, Duration, Utc};
+use cloud_config::Config;
+use engine::DeletionEngine;
+use lambda_runtime::Error;
+use policy::{PolicyErr, Retention, RetentionPolicy, RetentionRule};
+use serde_json::{json, Value};
+use sine_generator::data_formats::parse_legacy_id;
+use tracing::{info, debug, error, warn};
+use ulid::Ulid;
+use usage::StorageUsage;
+use wave_store::{BundleFormat, ObjectInfo, StoreErr, WaveStore, MAX_BATCH_SIZE};
+
+pub use engine::EngineConfig;
+use wave_table::{StatsRepository, StorageStats, WaveItem, WaveRepository, RepositoryErr, FILE_EXTENSION};
+
+/// Number of missed days the scheduled run catches up on.
+/// Files of older days are past the maximum retention, so `delete_old` removes them anyway.
+const MAX_CATCH_UP_DAYS: i64 = 30;
+
+#[derive(Debug, PartialEq)]
+pub struct EventErr(String);
+
+impl Display for EventErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for EventErr {}
+
+/// A range of days, both ends included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DateRange {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl DateRange {
+    fn days(&self) -> Vec<NaiveDate> {
+        let mut days = vec![];
+        let mut day = self.from;
+        while day <= self.to {
+            days.push(day);
+            day += Duration::days(1);
+        }
+        days
+    }
+}
+
+/// The events the cleaner is invoked with.
+#[derive(Debug, PartialEq)]
+pub enum CleanerEvent {
+    /// The daily run, triggered by the CloudWatch event rule at `time`.
+    Scheduled { time: DateTime<Utc> },
+    /// A manual invocation, which deletes the downloaded files of the days in the range.
+    Backfill(DateRange),
+}
+
+impl CleanerEvent {
+    /// Reads the payload of an invocation, which is either a CloudWatch event, whose `time` is the time of the run,
+    /// or a manual backfill of the form `{ "backfill": { "from": "yyyy-mm-dd", "to": "yyyy-mm-dd" } }`.
+    pub fn from_value(event: &Value) -> Result<Self, EventErr> {
+        if let Some(backfill) = event.get("backfill") {
+            let date = |name: &str| backfill
+                .get(name)
+                .and_then(Value::as_str)
+                .and_then(|date| NaiveDate::parse_from_str(date, "%F").ok())
+                .ok_or_else(|| EventErr(format!("backfill.{} needs to be a date of the form yyyy-mm-dd", name)));
+            let range = DateRange { from: date("from")?, to: date("to")? };
+            if range.from > range.to {
+                return Err(EventErr("backfill.from needs to be before backfill.to".to_owned()));
+            }
+            return Ok(CleanerEvent::Backfill(range));
+        }
+
+        let time = event
+            .get("time")
+            .and_then(Value::as_str)
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .ok_or_else(|| EventErr("event contains neither a valid time nor a backfill".to_owned()))?;
+        Ok(CleanerEvent::Scheduled { time: time.with_timezone(&Utc) })
+    }
+}
+
+/// An event together with the options of the invocation.
+#[derive(Debug, PartialEq)]
+pub struct CleanerInvocation {
+    pub event: CleanerEvent,
+    /// Only reports the files that would be deleted, without deleting them or moving the checkpoint.
+    pub dry_run: bool,
+}
+
+impl CleanerInvocation {
+    /// Reads the payload like `CleanerEvent::from_value`, and an optional `dry_run` flag.
+    /// A custom event like `{ "dry_run": true }` contains no time, it runs as if it was triggered now.
+    pub fn from_value(event: &Value) -> Result<Self, EventErr> {
+        let dry_run = match event.get("dry_run") {
+            Some(dry_run) => dry_run.as_bool().ok_or_else(|| EventErr("dry_run needs to be a boolean".to_owned()))?,
+            None => false,
+        };
+        let event = match (event.get("dry_run"), event.get("time"), event.get("backfill")) {
+            (Some(_), None, None) => CleanerEvent::Scheduled { time: Utc::now() },
+            _ => CleanerEvent::from_value(event)?,
+        };
+        Ok(CleanerInvocation { event, dry_run })
+    }
+}
+
+/// The formats of file ids, which can be found in the bucket.
+#[derive(Debug, PartialEq)]
+enum FileId {
+    /// Ids generated by the main lambda as ULIDs
+    Ulid(Ulid),
+    /// Ids of the form `prefix_channels_rate_bits`, which were used before ULIDs
+    Legacy(String),
+    /// Bundles of batch requests, which are stored under the id of the batch
+    Bundle(Ulid),
+}
+
+/// Why a file is deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeleteReason {
+    /// The file was downloaded, its item was created on `date`.
+    Downloaded { date: NaiveDate },
+    /// The retention of the file ended at `expires_at`, in seconds since the epoch.
+    Expired { expires_at: i64 },
+    /// The file has no expiry date and was last modified more than `days` days ago.
+    OlderThan { days: i64 },
+    /// No item points to the file, and it was last modified more than `days` days ago.
+    Orphaned { days: i64 },
+}
+
+impl DeleteReason {
+    fn to_json(&self) -> Value {
+        match self {
+            DeleteReason::Downloaded { date } => json!({ "reason": "downloaded", "date": date.to_string() }),
+            DeleteReason::Expired { expires_at } => json!({ "reason": "expired", "expires_at": expires_at }),
+            DeleteReason::OlderThan { days } => json!({ "reason": "older_than", "days": days }),
+            DeleteReason::Orphaned { days } => json!({ "reason": "orphaned", "days": days }),
+        }
+    }
+}
+
+/// The files deleted during a run of the cleaner, or the files that would be deleted in a dry run.
+#[derive(Debug, Default, PartialEq)]
+pub struct CleanupReport {
+    pub dry_run: bool,
+    /// Whether deleted files were moved into the trash, instead of being deleted right away.
+    pub soft_delete: bool,
+    /// The deleted files together with the reason why they were deleted.
+    pub deleted: Vec<(String, DeleteReason)>,
+    /// Files that should have been deleted, together with the reason why they weren't.
+    /// They are still in the bucket, so the next run tries again.
+    pub failed: Vec<(String, StoreErr)>,
+    /// The days whose downloaded files were looked up.
+    pub cleaned_dates: Vec<NaiveDate>,
+    /// Ids of the items, whose files were deleted during the run and which were marked as deleted.
+    pub marked_deleted: Vec<String>,
+    /// Ids of the items, whose files were missing from the bucket after their retention ended.
+    /// They are marked as deleted as well, in a dry run they are only reported.
+    pub orphaned_items: Vec<String>,
+    /// Keys of the files, which were deleted from the trash for good.
+    pub purged: Vec<String>,
+    /// Whether the run stopped before the deadline of the invocation. The files left behind are deleted by the next run.
+    pub timed_out: bool,
+    /// Bytes of the deleted files, in a dry run the bytes that would be freed. Files moved into the trash free nothing,
+    /// until they are purged.
+    pub reclaimed_bytes: i64,
+    /// The storage of the bucket after a complete scheduled run, `None` for backfills and runs that timed out.
+    pub storage: Option<StorageStats>,
+}
+
+impl CleanupReport {
+    fn new(dry_run: bool, settings: &Settings) -> Self {
+        CleanupReport { dry_run, soft_delete: settings.trash_retention.is_some(), ..Default::default() }
+    }
+
+    /// Returns the number of files deleted because they were downloaded, and because their retention ended.
+    pub fn counts(&self) -> (usize, usize) {
+        let downloaded = self.deleted.iter().filter(|(_, reason)| matches!(reason, DeleteReason::Downloaded { .. })).count();
+        (downloaded, self.deleted.len() - downloaded)
+    }
+
+    /// The report as returned by the lambda, e.g.
+    /// `{ "dry_run": false, "deleted": [{ "key": "<id>.wav", "reason": "downloaded", "date": "2022-07-26" }], "failed": [], "cleaned_dates": ["2022-07-26"] }`
+    pub fn to_json(&self) -> Value {
+        let deleted: Vec<Value> = self.deleted
+            .iter()
+            .map(|(key, reason)| {
+                let mut entry = reason.to_json();
+                entry["key"] = json!(key);
+                entry
+            })
+            .collect();
+        let failed: Vec<Value> = self.failed.iter().map(|(key, e)| json!({ "key": key, "error": e.to_string() })).collect();
+        let cleaned_dates: Vec<String> = self.cleaned_dates.iter().map(|date| date.to_string()).collect();
+        json!({
+            "dry_run": self.dry_run,
+            "soft_delete": self.soft_delete,
+            "deleted": deleted,
+            "failed": failed,
+            "cleaned_dates": cleaned_dates,
+            "marked_deleted": self.marked_deleted,
+            "orphaned_items": self.orphaned_items,
+            "purged": self.purged,
+            "timed_out": self.timed_out,
+            "reclaimed_bytes": self.reclaimed_bytes,
+            "storage": self.storage,
+        })
+    }
+}
+
+/// Settings of the cleaner, read from the configuration during the cold start.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// The longest retention a request may ask for.
+    pub max_retention_days: i64,
+    pub policy: RetentionPolicy,
+    /// How long deleted files stay in the trash, `None` deletes them right away.
+    pub trash_retention: Option<Duration>,
+    pub deletion: EngineConfig,
+}
+
+impl Settings {
+    pub fn from_config(config: &Config) -> Result<Self, PolicyErr> {
+        let policy = match &config.retention_policy {
+            Some(json) => RetentionPolicy::from_json(json)?,
+            None => RetentionPolicy::default(),
+        };
+        let trash_retention = config.trash_retention_hours.map(|hours| Duration::hours(hours as i64));
+        let deletion = EngineConfig {
+            concurrency: config.delete_concurrency as usize,
+            objects_per_second: config.delete_rate.min(u32::MAX as u64) as u32,
+            ..EngineConfig::default()
+        };
+        Ok(Settings { max_retention_days: config.max_retention_days, policy, trash_retention, deletion })
+    }
+}
+
+/// Handles an invocation of the cleaner. The run stops before `deadline`, e.g. the end of the lambda execution time.
+///
+/// The storage stats of a scheduled run are logged, and written to the stats table unless it is a dry run.
+/// Failing to write them doesn't fail the run, the files are deleted already.
+pub async fn handle_event(
+    invocation: CleanerInvocation,
+    settings: &Settings,
+    deadline: Option<DateTime<Utc>>,
+    repository: &dyn WaveRepository,
+    store: &dyn WaveStore,
+    stats: &dyn StatsRepository)
+-> Result<CleanupReport, Error> {
+    let dry_run = invocation.dry_run;
+    let report = match invocation.event {
+        CleanerEvent::Scheduled { time } => clean(time, dry_run, settings, deadline, repository, store).await?,
+        CleanerEvent::Backfill(range) => backfill(&range, dry_run, settings, deadline, repository, store).await?,
+    };
+
+    if let Some(storage) = &report.storage {
+        info!("{}", json!({ "storage_stats": storage }));
+        if !dry_run {
+            if let Err(e) = stats.put_stats(storage.clone()).await {
+                warn!("Unable to store the storage stats: {}", e);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Deletes all files that were downloaded on the day before `time`, 
+/// as well as all files that are expired at `time`.
+///
+/// If previous runs failed or were skipped, the files downloaded on the days since the last cleaned day are deleted as well.
+/// The last cleaned day is stored as a checkpoint in the bucket.
+///
+/// How long files without an expiry date, and downloaded files are kept, is decided by the retention policy.
+///
+/// The items of deleted files are marked as deleted. Items created `max_retention_days` before the cleaned days
+/// are expired, if their file is missing they are marked as deleted as well.
+///
+/// With soft deletes, files are moved into the trash instead, and files that were in the trash
+/// for longer than its retention are purged.
+///
+/// Files are deleted by the `DeletionEngine`. When the run gets close to the `deadline`, it stops deleting
+/// and leaves the checkpoint at the last day it finished, so the next run continues from there.
+///
+/// In a dry run, nothing is deleted or marked and the checkpoint stays where it is.
+pub async fn clean(
+    time: DateTime<Utc>,
+    dry_run: bool,
+    settings: &Settings,
+    deadline: Option<DateTime<Utc>>,
+    repository: &dyn WaveRepository,
+    store: &dyn WaveStore)
+-> Result<CleanupReport, Error> {
+    let mut report = CleanupReport::new(dry_run, settings);
+    let mut engine = DeletionEngine::new(&settings.deletion, deadline, repository, store);
+
+    // we are interested in the items from the previous day, and all days since the last run
+    let yesterday = time::previous_day(time)?;
+    let first_day = match checkpoint::load(store).await? {
+        Some(last_cleaned) => (last_cleaned + Duration::days(1)).max(yesterday - Duration::days(MAX_CATCH_UP_DAYS - 1)),
+        None => yesterday,
+    };
+    let range = DateRange { from: first_day, to: yesterday };
+    if range.from < range.to {
+        info!("Catching up on the days from {} to {}", range.from, range.to);
+    }
+
+    // delete all files that are marked as downloaded and where created at the days of the range
+    delete_downloaded_in_range(&range, true, time, &settings.policy, &mut engine, &mut report).await?;
+
+    // the trash is purged first, so the purged files are not counted in the storage stats
+    if !report.timed_out {
+        if let Some(retention) = settings.trash_retention {
+            purge_trash(time, retention, store, &mut report).await?;
+        }
+    }
+
+    // delete all files that are expired, or older than the max age of their retention if they have no expiry date
+    let mut usage = None;
+    if !report.timed_out {
+        usage = Some(delete_old(&mut engine, time, &settings.policy, &mut report).await?);
+    }
+    if report.timed_out {
+        warn!("Stopped before the deadline, the remaining files are deleted by the next run");
+        return Ok(report);
+    }
+
+    // items whose file is gone without being marked, e.g. files that were never created or deleted by hand
+    let expired_offset = Duration::days(settings.max_retention_days + 1);
+    for day in range.days() {
+        mark_orphaned_items(day - expired_offset, time, repository, store, &mut report).await?;
+    }
+
+    report.storage = usage.map(|usage| usage.finish(report.reclaimed_bytes));
+    Ok(report)
+}
+
+/// Deletes the files downloaded on the days of the range, without touching the checkpoint or the expired files.
+pub async fn backfill(
+    range: &DateRange,
+    dry_run: bool,
+    settings: &Settings,
+    deadline: Option<DateTime<Utc>>,
+    repository: &dyn WaveRepository,
+    store: &dyn WaveStore)
+-> Result<CleanupReport, Error> {
+    info!("Backfilling the days from {} to {}", range.from, range.to);
+    let mut report = CleanupReport::new(dry_run, settings);
+    let mut engine = DeletionEngine::new(&settings.deletion, deadline, repository, store);
+    delete_downloaded_in_range(range, false, Utc::now(), &settings.policy, &mut engine, &mut report).await?;
+    Ok(report)
+}
+
+/// Deletes the downloaded files of each day of the range in order.
+/// If `update_checkpoint` is set, the checkpoint is moved forward after each day without failed deletes,
+/// a day with failed deletes and all days after it are cleaned again by the next run.
+/// Days are finished one after the other, so the checkpoint never passes a day whose files are still being deleted.
+async fn delete_downloaded_in_range(
+    range: &DateRange,
+    update_checkpoint: bool,
+    time: DateTime<Utc>,
+    policy: &RetentionPolicy,
+    engine: &mut DeletionEngine<'_>,
+    report: &mut CleanupReport)
+-> Result<(), Error> {
+    let mut update_checkpoint = update_checkpoint && !report.dry_run;
+    for day in range.days() {
+        if !engine.has_time() {
+            report.timed_out = true;
+            break;
+        }
+        let failed_before = report.failed.len();
+        delete_downloaded(engine, day, time, policy, report).await?;
+        engine.finish(report).await;
+        if report.timed_out {
+            break;
+        }
+        report.cleaned_dates.push(day);
+
+        if report.failed.len() > failed_before {
+            update_checkpoint = false;
+        }
+        if update_checkpoint {
+            checkpoint::save(day, engine.store).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the files downloaded on `day`, unless their retention keeps them for a while after the download.
+/// Files that are kept are deleted by `delete_old` once the time has passed.
+async fn delete_downloaded (
+    engine: &mut DeletionEngine<'_>,
+    day: NaiveDate,
+    time: DateTime<Utc>,
+    policy: &RetentionPolicy,
+    report: &mut CleanupReport)
+-> Result<(), Error> {
+    let (repository, store) = (engine.repository, engine.store);
+    let date = day.format("%F").to_string();
+    info!("Date: {}", date);
+
+    // only the downloaded items are returned, on all pages of the query
+    let query_results = repository.query_downloaded_by_date(&date).await?;
+    debug!("Query results: {:?}", query_results);
+    info!("Found {} downloaded items for querying date.", query_results.len());
+    
+    // items created from a deduplicated request point to the file of another item
+    let files: Vec<(String, String, Option<String>)> = query_results
+                        .into_iter()
+                        .map(|file| (file.id, file.object_key, file.content_hash))
+                        .collect();
+
+    debug!("Found files: {:?}", files);
+
+    let mut to_delete = vec![];
+    for (id, file_name, content_hash) in files {
+        // several downloaded items can point to the same file
+        if to_delete.iter().any(|(key, _, _)| *key == file_name) {
+            continue;
+        }
+        if policy.needs_items() {
+            let item = repository.get(&id).await?;
+            let retention = retention_of(&file_name, item.as_ref(), policy, store).await?.map_or(&policy.default, |rule| &rule.retention);
+            if !is_kept_long_enough(item.as_ref(), retention, time) {
+                info!("File is kept for {} hours after the download, skipping: {}", retention.keep_after_download_hours, file_name);
+                continue;
+            }
+        }
+        // only files of requests with a content hash can be reused
+        if content_hash.is_some() && is_still_referenced(&file_name, None, time, repository).await? {
+            info!("File is still referenced by an item which was not downloaded, skipping: {}", file_name);
+            continue;
+        }
+        // the size is only needed for the reclaimed bytes, a missing file is reported as failed by the delete
+        let size = store.head(&file_name).await?.map_or(0, |object| object.size);
+        to_delete.push((file_name, DeleteReason::Downloaded { date: day }, size));
+    }
+
+    // delete found files from bucket
+    engine.submit(to_delete, report).await;
+    Ok(())
+}
+
+/// Walks through all pages of the bucket and deletes the expired files,
+/// in batches of `MAX_BATCH_SIZE` keys while the listing and the lookups of the next files continue.
+/// The listing stops when the run gets close to its deadline.
+///
+/// Returns the storage of the objects, which stay in the bucket. Files that failed to be deleted are counted as well.
+async fn delete_old (
+    engine: &mut DeletionEngine<'_>,
+    time: DateTime<Utc>,
+    policy: &RetentionPolicy,
+    report: &mut CleanupReport)
+-> Result<StorageUsage, Error> {
+    let (repository, store) = (engine.repository, engine.store);
+    info!("Deleting everything that expired before {:?}, files without expiry date older than: {:?} days", time, policy.default.max_age_days);
+
+    // in a dry run the downloaded and purged files are still listed, they are only reported once
+    let reported: HashSet<String> = report.deleted.iter().map(|(key, _)| key.clone()).chain(report.purged.iter().cloned()).collect();
+    let mut usage = StorageUsage::new(time);
+    let mut pending = HashMap::new();
+    let mut to_delete = vec![];
+    let mut token = None;
+    let mut pages = 0;
+    loop {
+        // deleting listed keys doesn't affect the continuation token, which points behind the last key of the page
+        let page = engine.while_deleting(store.list(None, token.as_deref()), report).await?;
+        pages += 1;
+        for file in page.objects {
+            if reported.contains(&file.key) {
+                continue;
+            }
+            // the trash is purged on its own, see `purge_trash`
+            if file.key == checkpoint::CHECKPOINT_KEY || file.key.starts_with(trash::TRASH_PREFIX) {
+                usage.add(&file, None);
+                continue;
+            }
+            match engine.while_deleting(deletion_reason(&file, time, policy, repository, store), report).await? {
+                (Some(reason), sample_rate) => {
+                    to_delete.push((file.key.clone(), reason, file.size));
+                    pending.insert(file.key.clone(), (file, sample_rate));
+                }
+                (None, sample_rate) => usage.add(&file, sample_rate),
+            }
+            if to_delete.len() == MAX_BATCH_SIZE {
+                engine.submit(std::mem::take(&mut to_delete), report).await;
+            }
+        }
+        match page.next_token {
+            Some(_) if report.timed_out => break,
+            Some(next) => token = Some(next),
+            None => break,
+        }
+    }
+    engine.submit(to_delete, report).await;
+    engine.finish(report).await;
+    for (key, _) in &report.failed {
+        if let Some((file, sample_rate)) = pending.get(key) {
+            usage.add(file, *sample_rate);
+        }
+    }
+
+    info!("Listed {} pages of the bucket", pages);
+    Ok(usage)
+}
+
+/// Checks if the file can be deleted: its retention is over, and no newer request that wasn't downloaded points to it.
+/// Returns why the file can be deleted, or `None` if it needs to stay,
+/// along with the sample rate of the item of the file for the storage stats.
+async fn deletion_reason(
+    file: &ObjectInfo,
+    time: DateTime<Utc>,
+    policy: &RetentionPolicy,
+    repository: &dyn WaveRepository,
+    store: &dyn WaveStore)
+-> Result<(Option<DeleteReason>, Option<u32>), Error> {
+    // only touch files created by the sine generator, the retention is stored with the item,
+    // files created before that fall back to their age
+    let file_id = parse_file_key(&file.key);
+    let FileItems { item, reused_until, is_orphaned } = match &file_id {
+        Some(FileId::Bundle(batch_id)) => FileItems { item: query_bundle_item(&batch_id.to_string(), repository).await, ..Default::default() },
+        Some(_) => query_file_items(&file.key, repository).await,
+        None => FileItems::default(),
+    };
+    // the item of a bundle is the one of its batch, the files in it can have different rates
+    let sample_rate = match file_id {
+        Some(FileId::Ulid(_) | FileId::Legacy(_)) => item.as_ref().map(|item| item.wav_spec.sample_rate),
+        _ => None,
+    };
+
+    // other files are only deleted if a rule explicitly covers them
+    let rule = retention_of(&file.key, item.as_ref(), policy, store).await?;
+    let retention = match (&file_id, rule) {
+        (_, Some(rule)) => &rule.retention,
+        (Some(_), None) => &policy.default,
+        (None, None) => {
+            warn!("Found file with unknown key format, skipping: {:?}", file.key);
+            return Ok((None, sample_rate));
+        },
+    };
+
+    // files without a last modification are never deleted by age
+    let is_old = |days: i64| match time::is_older_than(file.last_modified, time, days) {
+        Ok(is_old) => is_old,
+        Err(e) => {
+            warn!("Unable to tell the age of {}, skipping: {}", file.key, e);
+            false
+        },
+    };
+    let is_file = matches!(file_id, Some(FileId::Ulid(_) | FileId::Legacy(_)));
+    let expires_at = item.as_ref().and_then(|item| item.expires_at).or(reused_until);
+    // a rule with its own max age also applies to files with an expiry date, `null` keeps them past it
+    let rule_max_age = rule.filter(|rule| rule.sets_max_age).map(|rule| rule.retention.max_age_days);
+    let reason = match (expires_at, retention.max_age_days) {
+        (Some(expires_at), _) if expires_at <= time.timestamp() && rule_max_age != Some(None) => Some(DeleteReason::Expired { expires_at }),
+        // files kept after their download are left behind by `delete_downloaded`
+        _ if is_file && retention.keep_after_download_hours > 0 && is_kept_long_enough(item.as_ref(), retention, time) => item
+            .as_ref()
+            .and_then(|item| NaiveDate::parse_from_str(&item.date, "%F").ok())
+            .map(|date| DeleteReason::Downloaded { date }),
+        (Some(_), _) => match rule_max_age {
+            Some(Some(days)) if is_old(days) => Some(DeleteReason::OlderThan { days }),
+            _ => None,
+        },
+        (None, None) => None,
+        (None, Some(days)) if is_old(days) && is_orphaned => Some(DeleteReason::Orphaned { days }),
+        (None, Some(days)) if is_old(days) => Some(DeleteReason::OlderThan { days }),
+        (None, Some(_)) => None,
+    };
+    if reason.is_none() {
+        info!("file not expired yet, skipping: {:?}", file.key); // todo change to debug
+        return Ok((None, sample_rate));
+    }
+
+    // files that were reused by a newer request need to stay until that one is downloaded,
+    // the item of the file itself is covered by the retention above
+    if is_file && is_still_referenced(&file.key, item.as_ref().map(|item| item.id.as_str()), time, repository).await? {
+        info!("file is still referenced, skipping: {:?}", file.key);
+        return Ok((None, sample_rate));
+    }
+
+    Ok((reason, sample_rate))
+}
+
+/// Returns the most specific rule matching the file, reading its tags only if a rule needs them.
+async fn retention_of<'a>(key: &str, item: Option<&WaveItem>, policy: &'a RetentionPolicy, store: &dyn WaveStore)
+-> Result<Option<&'a RetentionRule>, Error> {
+    if policy.rules.is_empty() {
+        return Ok(None);
+    }
+    let tags = match policy.uses_tags() {
+        true => store.get_tags(key).await?,
+        false => HashMap::new(),
+    };
+    let item = item.map(serde_json::to_value).transpose()?;
+    Ok(policy.matching_rule(key, &tags, item.as_ref()))
+}
+
+/// Checks if a downloaded file was kept for the hours its retention asks for.
+/// The download is assumed to happen when the item is created, items that weren't downloaded are always kept.
+fn is_kept_long_enough(item: Option<&WaveItem>, retention: &Retention, time: DateTime<Utc>) -> bool {
+    let item = match item {
+        Some(item) if item.is_downloaded => item,
+        _ => return false,
+    };
+    if retention.keep_after_download_hours == 0 {
+        return true;
+    }
+    let created = match item.created_at_ms {
+        Some(ms) => Utc.timestamp_millis(ms),
+        None => match DateTime::parse_from_rfc3339(&format!("{}T{}Z", item.date, item.time)) {
+            Ok(created) => created.with_timezone(&Utc),
+            Err(_) => return true,
+        },
+    };
+    created + Duration::hours(retention.keep_after_download_hours) <= time
+}
+
+/// Checks if any item, except the item `except_id`, points to the file and has not been downloaded yet.
+/// Files of deduplicated requests are shared between several items, so they may only be deleted once all of them
+/// are downloaded. Items that are marked as deleted or expired at `time` don't keep the file, their retention is over.
+async fn is_still_referenced(
+    object_key: &str,
+    except_id: Option<&str>,
+    time: DateTime<Utc>,
+    repository: &dyn WaveRepository)
+-> Result<bool, RepositoryErr> {
+    let pending = repository
+        .query_by_object_key(object_key).await?
+        .iter()
+        .filter(|file| Some(file.id.as_str()) != except_id)
+        .filter(|file| !file.is_downloaded && file.deleted_at.is_none())
+        .filter(|file| matches!(file.expires_at, Some(expires_at) if expires_at > time.timestamp()))
+        .count();
+    debug!("File {} has {} references that were not downloaded", object_key, pending);
+
+    Ok(pending > 0)
+}
+
+/// The items a file in the bucket belongs to.
+#[derive(Debug, Default)]
+struct FileItems {
+    /// The item that created the file, or an item of the batch a bundle belongs to.
+    item: Option<WaveItem>,
+    /// Latest expiry of the items reusing the file, only looked up once the item that created it is gone.
+    reused_until: Option<i64>,
+    /// No item points to the file anymore.
+    is_orphaned: bool,
+}
+
+/// Looks up the item that originally created the file. Once that item expired and was removed by dynamoDB,
+/// items of identical requests may still point to the file, so the file is only orphaned if none of them is left.
+/// Items that can't be read are treated like items without an expiry date, so they fall back to their age.
+async fn query_file_items(object_key: &str, repository: &dyn WaveRepository) -> FileItems {
+    match repository.get_by_object_key(object_key).await {
+        Ok(Some(item)) => return FileItems { item: Some(item), ..Default::default() },
+        Ok(None) => (),
+        Err(e) => {
+            warn!("Unable to read item of {}: {}", object_key, e);
+            return FileItems::default();
+        },
+    }
+
+    match repository.query_by_object_key(object_key).await {
+        Ok(files) => {
+            let remaining: Vec<_> = files.into_iter().filter(|file| file.deleted_at.is_none()).collect();
+            FileItems {
+                item: None,
+                reused_until: remaining.iter().filter_map(|file| file.expires_at).max(),
+                is_orphaned: remaining.is_empty(),
+            }
+        },
+        Err(e) => {
+            warn!("Unable to query items of {}: {}", object_key, e);
+            FileItems::default()
+        },
+    }
+}
+
+/// Looks up an item of the batch the bundle belongs to.
+/// All items of a batch expire at the same time, so the bundle is deleted together with them.
+async fn query_bundle_item(batch_id: &str, repository: &dyn WaveRepository) -> Option<WaveItem> {
+    let id = match repository.query_by_batch(batch_id).await {
+        Ok(files) => files.into_iter().next()?.id,
+        Err(e) => {
+            warn!("Unable to query items of batch {}: {}", batch_id, e);
+            return None;
+        },
+    };
+    match repository.get(&id).await {
+        Ok(item) => item,
+        Err(e) => {
+            warn!("Unable to read item {}: {}", id, e);
+            None
+        },
+    }
+}
+
+/// Parses the key of a file in the bucket into its id.
+/// Supports both ULIDs and legacy ids, so files created before the switch are still cleaned.
+fn parse_file_key(key: &str) -> Option<FileId> {
+    if let Some((batch_id, _)) = BundleFormat::parse_key(key) {
+        return Ulid::from_string(batch_id).ok().map(FileId::Bundle);
+    }
+    let id = key.strip_suffix(FILE_EXTENSION)?;
+    if let Ok(ulid) = Ulid::from_string(id) {
+        return Some(FileId::Ulid(ulid));
+    }
+    parse_legacy_id(id).map(|_| FileId::Legacy(id.to_owned()))
+}
+
+/// Deletes the files, which were in the trash for longer than `retention`, for good.
+async fn purge_trash(time: DateTime<Utc>, retention: Duration, store: &dyn WaveStore, report: &mut CleanupReport) -> Result<(), Error> {
+    let objects = trash::expired(time, retention, store).await?;
+    if objects.is_empty() {
+        return Ok(());
+    }
+    if report.dry_run {
+        info!("Would purge {} objects from the trash", objects.len());
+        report.reclaimed_bytes += objects.iter().map(|object| object.size).sum::<i64>();
+        report.purged.extend(objects.into_iter().map(|object| object.key));
+        return Ok(());
+    }
+
+    let keys: Vec<String> = objects.iter().map(|object| object.key.clone()).collect();
+    let sizes: HashMap<&str, i64> = objects.iter().map(|object| (object.key.as_str(), object.size)).collect();
+    let result = store.delete_batch(&keys).await?;
+    info!("Purged {} objects from the trash", result.deleted.len());
+    for (key, e) in &result.failed {
+        error!("Unable to purge {}: {}", key, e);
+    }
+    report.reclaimed_bytes += result.deleted.iter().map(|key| sizes.get(key.as_str()).copied().unwrap_or(0)).sum::<i64>();
+    report.failed.extend(result.failed);
+    report.purged.extend(result.deleted);
+    Ok(())
+}
+
+/// Marks all items pointing to the deleted file as deleted, bundles have no items of their own.
+/// Items that can't be marked are only logged, they are found again by `mark_orphaned_items` once they expired.
+/// Returns the ids of the marked items.
+async fn mark_items_of_file(key: &str, deleted_at: i64, repository: &dyn WaveRepository) -> Vec<String> {
+    if matches!(parse_file_key(key), Some(FileId::Bundle(_))) {
+        return vec![];
+    }
+
+    // deduplicated items point to the file of the item that created it, also once that item is gone
+    let mut ids: Vec<String> = match repository.query_by_object_key(key).await {
+        Ok(files) => files.into_iter().map(|file| file.id).collect(),
+        Err(e) => {
+            warn!("Unable to query items of {}: {}", key, e);
+            vec![]
+        },
+    };
+    // items created before the object key was stored are missing from the index
+    match repository.get_by_object_key(key).await {
+        Ok(Some(item)) if !ids.contains(&item.id) => ids.push(item.id),
+        Ok(_) => (),
+        Err(e) => warn!("Unable to read item of {}: {}", key, e),
+    }
+
+    let mut marked = vec![];
+    for id in ids {
+        match repository.mark_deleted(&id, deleted_at).await {
+            Ok(()) => marked.push(id),
+            Err(e) => warn!("Unable to mark item {} as deleted: {}", id, e),
+        }
+    }
+    marked
+}
+
+/// Marks the items created on `day`, whose file is missing from the bucket, as deleted.
+/// The day needs to be past the maximum retention, so the files of its items can't be created anymore.
+/// The items are marked with the time of the run.
+async fn mark_orphaned_items(
+    day: NaiveDate,
+    time: DateTime<Utc>,
+    repository: &dyn WaveRepository,
+    store: &dyn WaveStore,
+    report: &mut CleanupReport)
+-> Result<(), Error> {
+    let files = repository.query_by_date(&day.format("%F").to_string()).await?;
+    let deleted_at = time.timestamp();
+
+    // several items can point to the same file
+    let mut exists: HashMap<String, bool> = HashMap::new();
+    for file in files.into_iter().filter(|file| file.deleted_at.is_none()) {
+        let file_exists = match exists.get(&file.object_key) {
+            Some(file_exists) => *file_exists,
+            None => {
+                let file_exists = store.head(&file.object_key).await?.is_some();
+                exists.insert(file.object_key.clone(), file_exists);
+                file_exists
+            },
+        };
+        if file_exists {
+            continue;
+        }
+
+        info!("File of item {} is missing, marking the item as deleted", file.id);
+        if !report.dry_run {
+            repository.mark_deleted(&file.id, deleted_at).await?;
+        }
+        report.orphaned_items.push(file.id);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_file_key() {
+    let ulid = Ulid::from_string("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E").unwrap();
+    assert_eq!(parse_file_key("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E.wav"), Some(FileId::Ulid(ulid)));
+    assert_eq!(parse_file_key("567fab82_2_23000_16.wav"), Some(FileId::Legacy("567fab82_2_23000_16".to_owned())));
+    assert_eq!(parse_file_key("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E.zip"), Some(FileId::Bundle(ulid)));
+    assert_eq!(parse_file_key("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E.tar.gz"), Some(FileId::Bundle(ulid)));
+    assert_eq!(parse_file_key("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E"), None);
+    assert_eq!(parse_file_key("backup.zip"), None);
+    assert_eq!(parse_file_key("index.html"), None);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sine_generator::data_formats::{WavSpec, WavData};
+    use wave_store::InMemoryStore;
+    use wave_table::{CreationTime, InMemoryRepository, InMemoryStatsRepository};
+
+    const MAX_RETENTION_DAYS: i64 = 30;
+
+    fn settings() -> Settings {
+        Settings { max_retention_days: MAX_RETENTION_DAYS, policy: RetentionPolicy::default(), trash_retention: None, deletion: EngineConfig::default() }
+    }
+
+    #[tokio::test]
+    async fn test_delete_expired_bundle() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+
+        let batches = [
+            ("01GB6X3KQ8ZJ1V2WJZ4N4T2S9A", "01GB6X3KQ8ZJ1V2WJZ4N4T2S9C", now - Duration::hours(1)),
+            ("01GB6X3KQ8ZJ1V2WJZ4N4T2S9B", "01GB6X3KQ8ZJ1V2WJZ4N4T2S9D", now + Duration::hours(1)),
+        ];
+        for (batch_id, id, expires_at) in batches {
+            let spec = WavSpec::new(1, 8000, 8).unwrap();
+            let data = WavData { frequencies: vec![440], duration: 1, volume: 1. };
+            let mut item = WaveItem::new(id, "request", spec, data, CreationTime::from(now), expires_at.timestamp(), ("hash", &format!("{}.wav", id)));
+            item.batch_id = Some(batch_id.to_owned());
+            repository.put(item).await.unwrap();
+            store.put(&format!("{}.zip", batch_id), vec![0]).await.unwrap();
+        }
+
+        let mut report = CleanupReport::default();
+        delete_old(&mut DeletionEngine::new(&EngineConfig::default(), None, &repository, &store), now, &RetentionPolicy::default(), &mut report).await.unwrap();
+        let expires_at = (now - Duration::hours(1)).timestamp();
+        assert_eq!(report.deleted, vec![("01GB6X3KQ8ZJ1V2WJZ4N4T2S9A.zip".to_owned(), DeleteReason::Expired { expires_at })]);
+        assert!(store.head("01GB6X3KQ8ZJ1V2WJZ4N4T2S9B.zip").await.unwrap().is_some());
+    }
+
+    /// Puts a downloaded item with its file, which expires one hour before or after `now`.
+    async fn put_file(repository: &InMemoryRepository, store: &dyn WaveStore, id: &str, now: DateTime<Utc>, expired: bool) {
+        let expires_at = if expired { now - Duration::hours(1) } else { now + Duration::hours(1) };
+        put_file_created_at(repository, store, id, now, expires_at).await;
+    }
+
+    async fn put_file_created_at(repository: &InMemoryRepository, store: &dyn WaveStore, id: &str, created: DateTime<Utc>, expires_at: DateTime<Utc>) {
+        let spec = WavSpec::new(1, 8000, 8).unwrap();
+        let data = WavData { frequencies: vec![440], duration: 1, volume: 1. };
+        let key = format!("{}.wav", id);
+        let mut item = WaveItem::new(id, "request", spec, data, CreationTime::from(created), expires_at.timestamp(), (id, &key));
+        item.is_downloaded = true;
+        repository.put(item).await.unwrap();
+        store.put(&key, vec![0]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_old_on_all_pages() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new().with_page_size(2);
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+
+        let ids: Vec<String> = (0..7).map(|_| Ulid::new().to_string()).collect();
+        for (index, id) in ids.iter().enumerate() {
+            put_file(&repository, &store, id, now, index != 3).await;
+        }
+
+        let mut report = CleanupReport::default();
+        delete_old(&mut DeletionEngine::new(&EngineConfig::default(), None, &repository, &store), now, &RetentionPolicy::default(), &mut report).await.unwrap();
+        assert_eq!(report.deleted.len(), 6);
+        let remaining: Vec<String> = store.list_all(None).await.unwrap().into_iter().map(|file| file.key).collect();
+        assert_eq!(remaining, vec![format!("{}.wav", ids[3])]);
+    }
+
+    /// Fails to delete the keys in `locked`, like S3 reports single keys of a `DeleteObjects` request.
+    struct LockedStore {
+        inner: InMemoryStore,
+        locked: Vec<String>,
+        batches: std::sync::Mutex<Vec<usize>>,
+    }
+
+    #[async_trait::async_trait]
+    impl WaveStore for LockedStore {
+        async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), StoreErr> {
+            self.inner.put(key, body).await
+        }
+
+        async fn start_upload(&self, key: &str) -> Result<Box<dyn wave_store::ObjectUpload + '_>, StoreErr> {
+            self.inner.start_upload(key).await
+        }
+
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreErr> {
+            self.inner.get(key).await
+        }
+
+        async fn head(&self, key: &str) -> Result<Option<ObjectInfo>, StoreErr> {
+            self.inner.head(key).await
+        }
+
+        async fn list(&self, prefix: Option<&str>, continuation_token: Option<&str>) -> Result<wave_store::ListPage, StoreErr> {
+            self.inner.list(prefix, continuation_token).await
+        }
+
+        async fn delete(&self, _key: &str) -> Result<(), StoreErr> {
+            panic!("files are only deleted in batches");
+        }
+
+        async fn delete_batch(&self, keys: &[String]) -> Result<wave_store::BatchDeleteResult, StoreErr> {
+            self.batches.lock().unwrap().push(keys.len());
+            let (locked, unlocked): (Vec<String>, Vec<String>) = keys.iter().cloned().partition(|key| self.locked.contains(key));
+            let mut result = self.inner.delete_batch(&unlocked).await?;
+            result.failed = locked.into_iter().map(|key| (key, StoreErr::new("AccessDenied: Access Denied"))).collect();
+            Ok(result)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_report_failed_deletes() {
+        let repository = InMemoryRepository::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+        let ids: Vec<String> = (0..MAX_BATCH_SIZE + 1).map(|_| Ulid::new().to_string()).collect();
+        let store = LockedStore {
+            inner: InMemoryStore::new().with_page_size(300),
+            locked: vec![format!("{}.wav", ids[5])],
+            batches: Default::default(),
+        };
+        for id in &ids {
+            put_file(&repository, &store, id, now, true).await;
+        }
+
+        let result = clean(now, false, &settings(), None, &repository, &store).await.unwrap();
+        assert_eq!(result.counts(), (0, MAX_BATCH_SIZE));
+        assert_eq!(result.failed, vec![(format!("{}.wav", ids[5]), StoreErr::new("AccessDenied: Access Denied"))]);
+        assert_eq!(*store.batches.lock().unwrap(), vec![MAX_BATCH_SIZE, 1]);
+        assert!(store.head(&format!("{}.wav", ids[5])).await.unwrap().is_some());
+
+        // only the items of deleted files are marked
+        assert_eq!(result.marked_deleted.len(), MAX_BATCH_SIZE);
+        assert_eq!(repository.get(&ids[5]).await.unwrap().unwrap().deleted_at, None);
+        assert!(repository.get(&ids[6]).await.unwrap().unwrap().deleted_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_mark_deduplicated_items() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+
+        // a second, downloaded request for the same content points to the file of the first one
+        let mut ids: Vec<String> = (0..2).map(|_| Ulid::new().to_string()).collect();
+        ids.sort();
+        put_file(&repository, &store, &ids[0], now, true).await;
+        let mut duplicate = repository.get(&ids[0]).await.unwrap().unwrap();
+        duplicate.id = ids[1].clone();
+        repository.put(duplicate).await.unwrap();
+
+        let result = clean(now, false, &settings(), None, &repository, &store).await.unwrap();
+        assert_eq!(result.deleted.len(), 1);
+        assert_eq!(result.marked_deleted, ids);
+        for item in repository.items() {
+            assert!(item.deleted_at.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_expired_not_downloaded() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+
+        // expired files, whose requests never downloaded them
+        let mut ids: Vec<String> = (0..3).map(|_| Ulid::new().to_string()).collect();
+        ids.sort();
+        for id in &ids {
+            put_file(&repository, &store, id, now, true).await;
+            let item = repository.get(id).await.unwrap().unwrap();
+            repository.put(WaveItem { is_downloaded: false, ..item }).await.unwrap();
+        }
+        // a newer request for the content of the second file keeps it, an expired one doesn't keep the third
+        let original = repository.get(&ids[1]).await.unwrap().unwrap();
+        let expires_at = Some((now + Duration::hours(1)).timestamp());
+        repository.put(WaveItem { id: Ulid::new().to_string(), expires_at, ..original }).await.unwrap();
+        let original = repository.get(&ids[2]).await.unwrap().unwrap();
+        repository.put(WaveItem { id: Ulid::new().to_string(), ..original }).await.unwrap();
+
+        let result = clean(now, false, &settings(), None, &repository, &store).await.unwrap();
+        let expired = DeleteReason::Expired { expires_at: (now - Duration::hours(1)).timestamp() };
+        assert_eq!(result.deleted, vec![(format!("{}.wav", ids[0]), expired.clone()), (format!("{}.wav", ids[2]), expired)]);
+        assert!(store.head(&format!("{}.wav", ids[1])).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_keep_file_of_removed_item() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+
+        // the item that created the file expired and was removed by dynamoDB,
+        // a newer request that wasn't downloaded yet still points to the file
+        let mut ids: Vec<String> = (0..2).map(|_| Ulid::new().to_string()).collect();
+        ids.sort();
+        let removed = InMemoryRepository::new();
+        put_file_created_at(&removed, &store, &ids[0], now - Duration::days(3), now - Duration::days(2)).await;
+        let key = format!("{}.wav", ids[0]);
+        store.put_with_last_modified(&key, vec![0], now - Duration::days(3));
+        let original = removed.get(&ids[0]).await.unwrap().unwrap();
+        let expires_at = now + Duration::hours(1);
+        repository.put(WaveItem { id: ids[1].clone(), is_downloaded: false, expires_at: Some(expires_at.timestamp()), ..original }).await.unwrap();
+
+        let result = clean(now, false, &settings(), None, &repository, &store).await.unwrap();
+        assert!(result.deleted.is_empty());
+        assert!(store.head(&key).await.unwrap().is_some());
+
+        // once the newer request expired as well, the file is deleted together with it
+        let later = expires_at + Duration::hours(1);
+        let result = clean(later, false, &settings(), None, &repository, &store).await.unwrap();
+        assert_eq!(result.deleted, vec![(key.clone(), DeleteReason::Expired { expires_at: expires_at.timestamp() })]);
+        assert_eq!(result.marked_deleted, vec![ids[1].clone()]);
+    }
+
+    #[tokio::test]
+    async fn test_mark_orphaned_items() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+        let created = now - Duration::days(MAX_RETENTION_DAYS + 2);
+
+        // the first item never got a file, the file of the second one still exists
+        let ids: Vec<String> = (0..2).map(|_| Ulid::new().to_string()).collect();
+        put_file_created_at(&repository, &store, &ids[0], created, created + Duration::days(1)).await;
+        store.delete(&format!("{}.wav", ids[0])).await.unwrap();
+        put_file_created_at(&repository, &store, &ids[1], created, now + Duration::days(1)).await;
+
+        let report = clean(now, true, &settings(), None, &repository, &store).await.unwrap();
+        assert_eq!(report.orphaned_items, vec![ids[0].clone()]);
+        assert_eq!(repository.get(&ids[0]).await.unwrap().unwrap().deleted_at, None);
+
+        let result = clean(now, false, &settings(), None, &repository, &store).await.unwrap();
+        assert_eq!(result.orphaned_items, vec![ids[0].clone()]);
+        assert!(result.marked_deleted.is_empty());
+        assert_eq!(repository.get(&ids[0]).await.unwrap().unwrap().deleted_at, Some(now.timestamp()));
+        assert_eq!(repository.get(&ids[1]).await.unwrap().unwrap().deleted_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_from_checkpoint() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+
+        // one file downloaded on each day from 07-22 to 07-26, none of them expired
+        let ids: Vec<String> = (0..5).map(|_| Ulid::new().to_string()).collect();
+        for (index, id) in ids.iter().enumerate() {
+            let created = now - Duration::days(5 - index as i64);
+            put_file_created_at(&repository, &store, id, created, now + Duration::days(1)).await;
+        }
+        checkpoint::save(NaiveDate::from_ymd(2022, 7, 23), &store).await.unwrap();
+
+        let result = clean(now, false, &settings(), None, &repository, &store).await.unwrap();
+        let days: Vec<NaiveDate> = (24..=26).map(|day| NaiveDate::from_ymd(2022, 7, day)).collect();
+        assert_eq!(result.cleaned_dates, days);
+        let expected: Vec<(String, DeleteReason)> = ids[2..]
+            .iter()
+            .zip(&days)
+            .map(|(id, day)| (format!("{}.wav", id), DeleteReason::Downloaded { date: *day }))
+            .collect();
+        assert_eq!(result.deleted, expected);
+        assert_eq!(checkpoint::load(&store).await.unwrap(), Some(NaiveDate::from_ymd(2022, 7, 26)));
+
+        // the checkpoint is up to date, so a second run on the same day has nothing to catch up on
+        let result = clean(now, false, &settings(), None, &repository, &store).await.unwrap();
+        assert!(result.cleaned_dates.is_empty());
+        assert!(result.deleted.is_empty());
+
+        // the files before the checkpoint and the checkpoint itself are left alone
+        assert!(store.head(&format!("{}.wav", ids[0])).await.unwrap().is_some());
+        assert!(store.head(checkpoint::CHECKPOINT_KEY).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_first_run_cleans_yesterday() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+
+        let result = clean(now, false, &settings(), None, &repository, &store).await.unwrap();
+        assert_eq!(result.cleaned_dates, vec![NaiveDate::from_ymd(2022, 7, 26)]);
+        assert_eq!(checkpoint::load(&store).await.unwrap(), Some(NaiveDate::from_ymd(2022, 7, 26)));
+
+        // catching up is limited to MAX_CATCH_UP_DAYS
+        let later = now + Duration::days(100);
+        let result = clean(later, false, &settings(), None, &repository, &store).await.unwrap();
+        assert_eq!(result.cleaned_dates.len(), MAX_CATCH_UP_DAYS as usize);
+        assert_eq!(result.cleaned_dates.last(), Some(&NaiveDate::from_ymd(2022, 11, 3)));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+
+        // downloaded yesterday and expired, expired, not expired, and a file without an item older than the default max age
+        let ids: Vec<String> = (0..3).map(|_| Ulid::new().to_string()).collect();
+        put_file_created_at(&repository, &store, &ids[0], now - Duration::days(1), now - Duration::hours(1)).await;
+        put_file(&repository, &store, &ids[1], now, true).await;
+        put_file(&repository, &store, &ids[2], now, false).await;
+        store.put_with_last_modified("567fab82_2_23000_16.wav", vec![0], now - Duration::days(3));
+
+        let report = clean(now, true, &settings(), None, &repository, &store).await.unwrap();
+        let yesterday = NaiveDate::from_ymd(2022, 7, 26);
+        let mut expected = vec![
+            (format!("{}.wav", ids[0]), DeleteReason::Downloaded { date: yesterday }),
+            (format!("{}.wav", ids[1]), DeleteReason::Expired { expires_at: (now - Duration::hours(1)).timestamp() }),
+            ("567fab82_2_23000_16.wav".to_owned(), DeleteReason::Orphaned { days: policy::DEFAULT_MAX_AGE_DAYS }),
+        ];
+        expected[1..].sort_by(|a, b| a.0.cmp(&b.0));
+        assert!(report.dry_run);
+        assert_eq!(report.deleted, expected);
+        assert_eq!(report.counts(), (1, 2));
+
+        // nothing was deleted and the checkpoint wasn't written
+        assert_eq!(store.list_all(None).await.unwrap().len(), 4);
+        assert_eq!(checkpoint::load(&store).await.unwrap(), None);
+
+        // the real run deletes the reported files
+        let result = clean(now, false, &settings(), None, &repository, &store).await.unwrap();
+        assert_eq!(result.deleted, report.deleted);
+        assert_eq!(result.to_json()["deleted"][0], json!({ "key": expected[0].0, "reason": "downloaded", "date": "2022-07-26" }));
+        assert_eq!(result.to_json()["dry_run"], json!(false));
+    }
+
+    #[tokio::test]
+    async fn test_retention_policy() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+        let policy = RetentionPolicy::from_json(r#"{ "rules": [
+            { "tag": { "key": "retention", "value": "keep" }, "keep_after_download_hours": 48 },
+            { "prefix": "legacy/", "max_age_days": 7 }
+        ] }"#).unwrap();
+        let settings = Settings { policy, ..settings() };
+
+        // downloaded yesterday with and without the tag, and files with keys the cleaner doesn't know
+        let ids: Vec<String> = (0..2).map(|_| Ulid::new().to_string()).collect();
+        for id in &ids {
+            put_file_created_at(&repository, &store, id, now - Duration::days(1), now + Duration::days(5)).await;
+        }
+        store.set_tags(&format!("{}.wav", ids[0]), &[("retention", "keep")]);
+        store.put_with_last_modified("legacy/old.wav", vec![0], now - Duration::days(8));
+        store.put_with_last_modified("legacy/new.wav", vec![0], now - Duration::days(6));
+        store.put_with_last_modified("other/old.wav", vec![0], now - Duration::days(30));
+
+        let result = clean(now, false, &settings, None, &repository, &store).await.unwrap();
+        let yesterday = NaiveDate::from_ymd(2022, 7, 26);
+        assert_eq!(result.deleted, vec![
+            (format!("{}.wav", ids[1]), DeleteReason::Downloaded { date: yesterday }),
+            ("legacy/old.wav".to_owned(), DeleteReason::OlderThan { days: 7 }),
+        ]);
+
+        // the tagged file is deleted once it was kept for two days
+        let later = now + Duration::days(2);
+        let result = clean(later, false, &settings, None, &repository, &store).await.unwrap();
+        assert_eq!(result.deleted, vec![
+            (format!("{}.wav", ids[0]), DeleteReason::Downloaded { date: yesterday }),
+            ("legacy/new.wav".to_owned(), DeleteReason::OlderThan { days: 7 }),
+        ]);
+        assert!(store.head("other/old.wav").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rules_with_expiry_date() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+        let policy = RetentionPolicy::from_json(r#"{ "rules": [
+            { "attribute": { "name": "batch_id" }, "max_age_days": null },
+            { "attribute": { "name": "wav_spec.sample_rate", "value": 8000 }, "max_age_days": 1 },
+            { "attribute": { "name": "wav_spec.sample_rate", "value": 16000 }, "keep_after_download_hours": 1 }
+        ] }"#).unwrap();
+        let settings = Settings { policy, ..settings() };
+
+        // files at 8 kHz are deleted after a day, before or after their expiry date,
+        // files of batches are kept past it, and a rule without a max age leaves the expiry date alone
+        let mut ids: Vec<String> = (0..4).map(|_| Ulid::new().to_string()).collect();
+        ids.sort();
+        let files = [
+            (now + Duration::days(10), now - Duration::days(2)),
+            (now - Duration::hours(1), now - Duration::days(2)),
+            (now - Duration::hours(1), now - Duration::hours(2)),
+            (now + Duration::days(10), now - Duration::days(5)),
+        ];
+        for (id, (expires_at, last_modified)) in ids.iter().zip(files) {
+            put_file_created_at(&repository, &store, id, last_modified, expires_at).await;
+            store.put_with_last_modified(&format!("{}.wav", id), vec![0], last_modified);
+            let item = repository.get(id).await.unwrap().unwrap();
+            repository.put(WaveItem { is_downloaded: false, ..item }).await.unwrap();
+        }
+        let mut batch_item = repository.get(&ids[1]).await.unwrap().unwrap();
+        batch_item.batch_id = Some("batch".to_owned());
+        repository.put(batch_item).await.unwrap();
+        let mut item = repository.get(&ids[3]).await.unwrap().unwrap();
+        item.wav_spec = WavSpec::new(1, 16000, 8).unwrap();
+        repository.put(item).await.unwrap();
+
+        let result = clean(now, false, &settings, None, &repository, &store).await.unwrap();
+        assert_eq!(result.deleted, vec![
+            (format!("{}.wav", ids[0]), DeleteReason::OlderThan { days: 1 }),
+            (format!("{}.wav", ids[2]), DeleteReason::Expired { expires_at: (now - Duration::hours(1)).timestamp() }),
+        ]);
+        assert!(store.head(&format!("{}.wav", ids[1])).await.unwrap().is_some());
+        assert!(store.head(&format!("{}.wav", ids[3])).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_soft_delete() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+        let settings = Settings { trash_retention: Some(Duration::hours(24)), ..settings() };
+        let id = Ulid::new().to_string();
+        put_file(&repository, &store, &id, now, true).await;
+
+        let result = clean(now, false, &settings, None, &repository, &store).await.unwrap();
+        let key = format!("{}.wav", id);
+        assert!(result.soft_delete);
+        assert_eq!(result.deleted, vec![(key.clone(), DeleteReason::Expired { expires_at: (now - Duration::hours(1)).timestamp() })]);
+        assert_eq!(store.head(&key).await.unwrap(), None);
+        assert!(store.head(&format!("trash/{}", key)).await.unwrap().is_some());
+        assert!(repository.get(&id).await.unwrap().unwrap().deleted_at.is_some());
+        assert!(result.purged.is_empty());
+
+        // the file is moved into the trash now, it is purged once it was there for a day
+        let later = Utc::now() + Duration::hours(25);
+        let result = clean(later, false, &settings, None, &repository, &store).await.unwrap();
+        assert!(result.deleted.is_empty());
+        assert_eq!(result.purged, vec![format!("trash/{}", key)]);
+        assert_eq!(store.list_all(Some(trash::TRASH_PREFIX)).await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_stop_before_deadline() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+        let id = Ulid::new().to_string();
+        put_file_created_at(&repository, &store, &id, now - Duration::days(1), now - Duration::hours(1)).await;
+
+        // less time left than the reserve of the engine
+        let deadline = Utc::now() + Duration::seconds(1);
+        let result = clean(now, false, &settings(), Some(deadline), &repository, &store).await.unwrap();
+        assert!(result.timed_out);
+        assert!(result.deleted.is_empty());
+        assert!(result.cleaned_dates.is_empty());
+        assert_eq!(checkpoint::load(&store).await.unwrap(), None);
+
+        // the next run continues where the previous one stopped
+        let result = clean(now, false, &settings(), None, &repository, &store).await.unwrap();
+        assert!(!result.timed_out);
+        assert_eq!(result.deleted, vec![(format!("{}.wav", id), DeleteReason::Downloaded { date: NaiveDate::from_ymd(2022, 7, 26) })]);
+        assert_eq!(checkpoint::load(&store).await.unwrap(), Some(NaiveDate::from_ymd(2022, 7, 26)));
+    }
+
+    #[tokio::test]
+    async fn test_backfill_keeps_checkpoint() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+        let id = Ulid::new().to_string();
+        put_file_created_at(&repository, &store, &id, now - Duration::days(10), now + Duration::days(1)).await;
+        checkpoint::save(NaiveDate::from_ymd(2022, 7, 26), &store).await.unwrap();
+
+        let invocation = CleanerInvocation::from_value(&json!({ "backfill": { "from": "2022-07-16", "to": "2022-07-18" } })).unwrap();
+        let stats = InMemoryStatsRepository::new();
+        let result = handle_event(invocation, &settings(), None, &repository, &store, &stats).await.unwrap();
+        assert_eq!(result.deleted, vec![(format!("{}.wav", id), DeleteReason::Downloaded { date: NaiveDate::from_ymd(2022, 7, 17) })]);
+        assert_eq!(result.cleaned_dates.len(), 3);
+        assert_eq!(checkpoint::load(&store).await.unwrap(), Some(NaiveDate::from_ymd(2022, 7, 26)));
+        assert_eq!(result.storage, None);
+        assert!(stats.stats().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_storage_stats() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let stats = InMemoryStatsRepository::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+        let (downloaded, kept) = (Ulid::new().to_string(), Ulid::new().to_string());
+        put_file_created_at(&repository, &store, &downloaded, now - Duration::days(1), now + Duration::days(1)).await;
+        put_file(&repository, &store, &kept, now, false).await;
+        store.put("other.zip", vec![0; 10]).await.unwrap();
+        let invocation = |dry_run| CleanerInvocation { event: CleanerEvent::Scheduled { time: now }, dry_run };
+
+        // a dry run only logs the stats, the downloaded file counts as deleted
+        let report = handle_event(invocation(true), &settings(), None, &repository, &store, &stats).await.unwrap();
+        let storage = report.storage.unwrap();
+        assert_eq!((storage.object_count, storage.total_bytes, storage.reclaimed_bytes), (2, 11, 1));
+        assert_eq!(storage.bytes_by_format["zip"], 10);
+        // the sample rate of the kept file is taken from its item
+        assert_eq!((storage.bytes_by_sample_rate["8000"], storage.bytes_by_sample_rate["unknown"]), (1, 10));
+        assert!(stats.stats().is_empty());
+
+        // the checkpoint is stored in the bucket as well
+        let report = handle_event(invocation(false), &settings(), None, &repository, &store, &stats).await.unwrap();
+        let checkpoint_size = store.head(checkpoint::CHECKPOINT_KEY).await.unwrap().unwrap().size;
+        assert_eq!(report.reclaimed_bytes, 1);
+        assert_eq!(stats.stats(), vec![StorageStats {
+            date: "2022-07-27".to_owned(),
+            created_at: "2022-07-27T12:00:00.000Z".to_owned(),
+            object_count: 3,
+            total_bytes: 11 + checkpoint_size,
+            reclaimed_bytes: 1,
+            ..report.storage.unwrap()
+        }]);
+    }
+}
+
+#[test]
+fn test_parse_cleaner_event() {
+    let scheduled = json!({ "source": "aws.events", "time": "2022-07-27T00:00:00Z", "detail": {} });
+    assert_eq!(CleanerEvent::from_value(&scheduled), Ok(CleanerEvent::Scheduled { time: Utc.ymd(2022, 7, 27).and_hms(0, 0, 0) }));
+
+    let backfill = json!({ "backfill": { "from": "2022-07-01", "to": "2022-07-03" } });
+    let range = DateRange { from: NaiveDate::from_ymd(2022, 7, 1), to: NaiveDate::from_ymd(2022, 7, 3) };
+    assert_eq!(range.days().len(), 3);
+    assert_eq!(CleanerEvent::from_value(&backfill), Ok(CleanerEvent::Backfill(range)));
+
+    assert!(CleanerEvent::from_value(&json!({ "backfill": { "from": "2022-07-03", "to": "2022-07-01" } })).is_err());
+    assert!(CleanerEvent::from_value(&json!({ "backfill": { "from": "July 1st", "to": "2022-07-01" } })).is_err());
+    assert!(CleanerEvent::from_value(&json!({})).is_err());
+}
+
+#[test]
+fn test_parse_dry_run() {
+    let invocation = CleanerInvocation::from_value(&json!({ "dry_run": true })).unwrap();
+    assert!(invocation.dry_run);
+    assert!(matches!(invocation.event, CleanerEvent::Scheduled { .. }));
+
+    let backfill = json!({ "dry_run": true, "backfill": { "from": "2022-07-01", "to": "2022-07-03" } });
+    assert!(matches!(CleanerInvocation::from_value(&backfill).unwrap().event, CleanerEvent::Backfill(_)));
+
+    let scheduled = CleanerInvocation::from_value(&json!({ "time": "2022-07-27T00:00:00Z" })).unwrap();
+    assert!(!scheduled.dry_run);
+
+    assert!(CleanerInvocation::from_value(&json!({ "dry_run": "yes" })).is_err());
+}
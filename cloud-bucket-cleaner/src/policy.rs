@@ -0,0 +1,287 @@
+//! Retention policies, which decide how long the files in the bucket are kept.
+//!
+//! The policy decides how long files without an expiry date are kept, and how long a file is kept after it was downloaded.
+//! The expiry date of an item applies as well, unless a matching rule sets its own `max_age_days`: the file is then
+//! deleted at the earlier of both, or kept past its expiry date if the rule's `max_age_days` is `null`.
+
+use std::{collections::HashMap, fmt::Display, error};
+
+use serde_json::Value;
+
+/// Age in days after which files without an expiry date are deleted, if the policy doesn't say otherwise.
+pub const DEFAULT_MAX_AGE_DAYS: i64 = 2;
+
+#[derive(Debug, PartialEq)]
+pub struct PolicyErr(String);
+
+impl Display for PolicyErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for PolicyErr {}
+
+/// How long a file is kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retention {
+    /// Age in days after which a file without an expiry date is deleted, `None` keeps it until it is downloaded.
+    pub max_age_days: Option<i64>,
+    /// Hours a file is kept after it was downloaded. The download is assumed to happen when the item is created,
+    /// since the client polls for the file right after its request.
+    pub keep_after_download_hours: i64,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention { max_age_days: Some(DEFAULT_MAX_AGE_DAYS), keep_after_download_hours: 0 }
+    }
+}
+
+/// What a rule is matched against.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// Keys starting with the prefix.
+    Prefix(String),
+    /// Objects, which have the tag with the given value.
+    Tag { key: String, value: String },
+    /// Files whose item has the attribute, with the given value if it is set.
+    /// Nested attributes are separated by dots, e.g. `wav_spec.sample_rate`.
+    Attribute { name: String, value: Option<Value> },
+}
+
+impl Condition {
+    /// Rules with more specific conditions take precedence: attributes over tags over prefixes,
+    /// and longer prefixes over shorter ones.
+    fn precedence(&self) -> (u8, usize) {
+        match self {
+            Condition::Attribute { .. } => (3, 0),
+            Condition::Tag { .. } => (2, 0),
+            Condition::Prefix(prefix) => (1, prefix.len()),
+        }
+    }
+
+    fn matches(&self, key: &str, tags: &HashMap<String, String>, item: Option<&Value>) -> bool {
+        match self {
+            Condition::Prefix(prefix) => key.starts_with(prefix.as_str()),
+            Condition::Tag { key, value } => tags.get(key) == Some(value),
+            Condition::Attribute { name, value } => {
+                let attribute = item.and_then(|item| name.split('.').try_fold(item, |value, name| value.get(name)));
+                match (attribute, value) {
+                    (None | Some(Value::Null), _) => false,
+                    (Some(_), None) => true,
+                    (Some(attribute), Some(value)) => attribute == value,
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetentionRule {
+    pub condition: Condition,
+    pub retention: Retention,
+    /// Whether the rule sets `max_age_days` itself, instead of taking it from the default.
+    /// Only then the max age also applies to files with an expiry date.
+    pub sets_max_age: bool,
+}
+
+/// The rules of the cleaner, together with the retention of files no rule matches.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RetentionPolicy {
+    pub default: Retention,
+    pub rules: Vec<RetentionRule>,
+}
+
+impl RetentionPolicy {
+    /// Reads a policy of the form
+    /// ```json
+    /// {
+    ///     "default": { "max_age_days": 2, "keep_after_download_hours": 0 },
+    ///     "rules": [
+    ///         { "prefix": "legacy/", "max_age_days": 7 },
+    ///         { "tag": { "key": "retention", "value": "archive" }, "max_age_days": null },
+    ///         { "attribute": { "name": "batch_id" }, "keep_after_download_hours": 24 }
+    ///     ]
+    /// }
+    /// ```
+    /// Each rule has exactly one condition. Settings missing from a rule are taken from the default,
+    /// a `max_age_days` of `null` disables the deletion by age.
+    pub fn from_json(json: &str) -> Result<Self, PolicyErr> {
+        let policy: Value = serde_json::from_str(json).map_err(|e| PolicyErr(format!("retention policy is no valid json: {}", e)))?;
+
+        let default = match policy.get("default") {
+            Some(default) => read_retention(default, &Retention::default(), "default")?,
+            None => Retention::default(),
+        };
+        let rules = match policy.get("rules") {
+            Some(Value::Array(rules)) => rules
+                .iter()
+                .enumerate()
+                .map(|(i, rule)| read_rule(rule, &default, &format!("rules[{}]", i)))
+                .collect::<Result<Vec<RetentionRule>, PolicyErr>>()?,
+            Some(_) => return Err(PolicyErr("rules needs to be an array".to_owned())),
+            None => vec![],
+        };
+
+        Ok(RetentionPolicy { default, rules })
+    }
+
+    /// Whether any rule matches on tags, which need an additional request per file.
+    pub fn uses_tags(&self) -> bool {
+        self.rules.iter().any(|rule| matches!(rule.condition, Condition::Tag { .. }))
+    }
+
+    /// Whether the cleaner needs to read the item of a downloaded file, to find its retention.
+    pub fn needs_items(&self) -> bool {
+        !self.rules.is_empty() || self.default.keep_after_download_hours > 0
+    }
+
+    /// Returns the most specific rule matching the file, see `Condition::precedence`.
+    /// Rules with the same precedence are checked in the order they are configured.
+    /// `item` is the item of the file as json, with the attributes named like in the WaveTable.
+    pub fn matching_rule(&self, key: &str, tags: &HashMap<String, String>, item: Option<&Value>) -> Option<&RetentionRule> {
+        let mut matching: Option<&RetentionRule> = None;
+        for rule in self.rules.iter().filter(|rule| rule.condition.matches(key, tags, item)) {
+            match matching {
+                Some(best) if best.condition.precedence() >= rule.condition.precedence() => {},
+                _ => matching = Some(rule),
+            }
+        }
+        matching
+    }
+
+    /// Returns the retention of the most specific rule matching the file, or the default retention.
+    pub fn retention_for(&self, key: &str, tags: &HashMap<String, String>, item: Option<&Value>) -> &Retention {
+        self.matching_rule(key, tags, item).map_or(&self.default, |rule| &rule.retention)
+    }
+}
+
+fn read_rule(rule: &Value, default: &Retention, path: &str) -> Result<RetentionRule, PolicyErr> {
+    let string = |value: Option<&Value>, name: &str| value
+        .and_then(Value::as_str)
+        .map(str::to_owned)
+        .ok_or_else(|| PolicyErr(format!("{}.{} needs to be a string", path, name)));
+
+    let mut conditions = vec![];
+    if let Some(prefix) = rule.get("prefix") {
+        conditions.push(Condition::Prefix(string(Some(prefix), "prefix")?));
+    }
+    if let Some(tag) = rule.get("tag") {
+        conditions.push(Condition::Tag { key: string(tag.get("key"), "tag.key")?, value: string(tag.get("value"), "tag.value")? });
+    }
+    if let Some(attribute) = rule.get("attribute") {
+        conditions.push(Condition::Attribute { name: string(attribute.get("name"), "attribute.name")?, value: attribute.get("value").cloned() });
+    }
+    if conditions.len() != 1 {
+        return Err(PolicyErr(format!("{} needs exactly one of prefix, tag or attribute", path)));
+    }
+
+    Ok(RetentionRule {
+        condition: conditions.remove(0),
+        retention: read_retention(rule, default, path)?,
+        sets_max_age: rule.get("max_age_days").is_some(),
+    })
+}
+
+fn read_retention(value: &Value, default: &Retention, path: &str) -> Result<Retention, PolicyErr> {
+    let max_age_days = match value.get("max_age_days") {
+        None => default.max_age_days,
+        Some(Value::Null) => None,
+        Some(days) => Some(days
+            .as_i64()
+            .filter(|days| *days >= 1)
+            .ok_or_else(|| PolicyErr(format!("{}.max_age_days needs to be at least 1 or null", path)))?),
+    };
+    let keep_after_download_hours = match value.get("keep_after_download_hours") {
+        None => default.keep_after_download_hours,
+        Some(hours) => hours
+            .as_i64()
+            .filter(|hours| *hours >= 0)
+            .ok_or_else(|| PolicyErr(format!("{}.keep_after_download_hours needs to be a number of hours", path)))?,
+    };
+    Ok(Retention { max_age_days, keep_after_download_hours })
+}
+
+#[test]
+fn test_parse_policy() {
+    let policy = RetentionPolicy::from_json(r#"{
+        "default": { "max_age_days": 3 },
+        "rules": [
+            { "prefix": "legacy/", "keep_after_download_hours": 12 },
+            { "tag": { "key": "retention", "value": "archive" }, "max_age_days": null },
+            { "attribute": { "name": "wav_spec.sample_rate", "value": 48000 }, "max_age_days": 1 }
+        ]
+    }"#).unwrap();
+
+    assert_eq!(policy.default, Retention { max_age_days: Some(3), keep_after_download_hours: 0 });
+    assert_eq!(policy.rules[0].retention, Retention { max_age_days: Some(3), keep_after_download_hours: 12 });
+    assert!(!policy.rules[0].sets_max_age);
+    assert!(policy.rules[1].sets_max_age);
+    assert_eq!(policy.rules[1].condition, Condition::Tag { key: "retention".to_owned(), value: "archive".to_owned() });
+    assert_eq!(policy.rules[1].retention.max_age_days, None);
+    assert_eq!(policy.rules[2].condition, Condition::Attribute { name: "wav_spec.sample_rate".to_owned(), value: Some(Value::from(48000)) });
+    assert!(policy.uses_tags());
+
+    assert_eq!(RetentionPolicy::from_json("{}").unwrap(), RetentionPolicy::default());
+}
+
+#[test]
+fn test_reject_invalid_policy() {
+    let invalid = [
+        "rules",
+        r#"{ "rules": {} }"#,
+        r#"{ "rules": [{ "max_age_days": 3 }] }"#,
+        r#"{ "rules": [{ "prefix": "a/", "tag": { "key": "k", "value": "v" } }] }"#,
+        r#"{ "rules": [{ "tag": { "key": "k" } }] }"#,
+        r#"{ "rules": [{ "prefix": "a/", "max_age_days": 0 }] }"#,
+        r#"{ "default": { "keep_after_download_hours": -1 } }"#,
+    ];
+    for json in invalid {
+        assert!(RetentionPolicy::from_json(json).is_err(), "{} should be invalid", json);
+    }
+}
+
+#[test]
+fn test_rule_precedence() {
+    let policy = RetentionPolicy::from_json(r#"{
+        "rules": [
+            { "prefix": "a", "max_age_days": 1 },
+            { "prefix": "a/b/", "max_age_days": 2 },
+            { "tag": { "key": "retention", "value": "long" }, "max_age_days": 3 },
+            { "tag": { "key": "team", "value": "audio" }, "max_age_days": 4 },
+            { "attribute": { "name": "batch_id" }, "max_age_days": 5 },
+            { "prefix": "a/b/c/", "max_age_days": 6 }
+        ]
+    }"#).unwrap();
+    let max_age = |key: &str, tags: &[(&str, &str)], item: Option<Value>| {
+        let tags = tags.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        policy.retention_for(key, &tags, item.as_ref()).max_age_days
+    };
+    let batch_item = serde_json::json!({ "id": "a", "batch_id": "batch" });
+    let single_item = serde_json::json!({ "id": "a", "batch_id": null });
+
+    // no rule matches
+    assert_eq!(max_age("b.wav", &[], None), Some(DEFAULT_MAX_AGE_DAYS));
+    // the longest prefix wins, regardless of the order
+    assert_eq!(max_age("a.wav", &[], None), Some(1));
+    assert_eq!(max_age("a/b/x.wav", &[], None), Some(2));
+    assert_eq!(max_age("a/b/c/x.wav", &[], None), Some(6));
+    // tags win over prefixes, the first matching tag rule wins
+    assert_eq!(max_age("a/b/c/x.wav", &[("retention", "long")], None), Some(3));
+    assert_eq!(max_age("a/b/c/x.wav", &[("team", "audio"), ("retention", "long")], None), Some(3));
+    assert_eq!(max_age("a.wav", &[("retention", "short")], None), Some(1));
+    // attributes win over tags, an attribute set to null doesn't match
+    assert_eq!(max_age("a.wav", &[("retention", "long")], Some(batch_item)), Some(5));
+    assert_eq!(max_age("a.wav", &[("retention", "long")], Some(single_item)), Some(3));
+}
+
+#[test]
+fn test_attribute_values() {
+    let condition = Condition::Attribute { name: "wav_spec.sample_rate".to_owned(), value: Some(Value::from(48000)) };
+    let item = |rate: u32| serde_json::json!({ "wav_spec": { "sample_rate": rate } });
+
+    assert!(condition.matches("a.wav", &HashMap::new(), Some(&item(48000))));
+    assert!(!condition.matches("a.wav", &HashMap::new(), Some(&item(44100))));
+    assert!(!condition.matches("a.wav", &HashMap::new(), None));
+}
@@ -0,0 +1,89 @@
+//! Date and time calculations of the cleaner. All times are in UTC, which has no daylight saving time,
+//! so a day is always 24 hours long.
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use wave_store::time::TimeErr;
+
+/// The day before `time`, whose downloaded files are deleted by the run at `time`.
+pub fn previous_day(time: DateTime<Utc>) -> Result<NaiveDate, TimeErr> {
+    time.naive_utc()
+        .date()
+        .pred_opt()
+        .ok_or_else(|| TimeErr::new(&format!("no day before {}", time)))
+}
+
+/// Whether an object was last modified at least `days` days before `time`.
+/// Objects without a last modification have no age, so they fail instead of being kept or deleted silently.
+/// A max age reaching past the earliest representable time is never exceeded.
+pub fn is_older_than(last_modified: Option<DateTime<Utc>>, time: DateTime<Utc>, days: i64) -> Result<bool, TimeErr> {
+    let last_modified = last_modified.ok_or_else(|| TimeErr::new("the object has no last modification"))?;
+    let cutoff = days
+        .checked_mul(24 * 60 * 60 * 1000)
+        .and_then(|millis| time.checked_sub_signed(Duration::milliseconds(millis)));
+    Ok(match cutoff {
+        Some(cutoff) => last_modified <= cutoff,
+        None => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(date_time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(date_time).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_previous_day() {
+        let cases = [
+            ("2022-07-27T12:00:00Z", "2022-07-26"),
+            ("2022-07-27T00:00:00Z", "2022-07-26"),
+            ("2022-07-27T23:59:59.999999999Z", "2022-07-26"),
+            // times with an offset are converted to UTC first
+            ("2022-07-27T01:00:00+02:00", "2022-07-25"),
+            // no daylight saving time in UTC, e.g. at the switch in central europe
+            ("2022-03-27T01:30:00Z", "2022-03-26"),
+            ("2022-10-30T01:30:00Z", "2022-10-29"),
+            ("2023-01-01T00:00:00Z", "2022-12-31"),
+            // leap days, 2000 is a leap year and 2100 is not
+            ("2020-03-01T00:00:00Z", "2020-02-29"),
+            ("2021-03-01T00:00:00Z", "2021-02-28"),
+            ("2000-03-01T00:00:00Z", "2000-02-29"),
+            ("2100-03-01T00:00:00Z", "2100-02-28"),
+        ];
+        for (time, expected) in cases {
+            assert_eq!(previous_day(utc(time)).unwrap(), NaiveDate::parse_from_str(expected, "%F").unwrap(), "{}", time);
+        }
+
+        assert!(previous_day(DateTime::<Utc>::MIN_UTC).is_err());
+    }
+
+    #[test]
+    fn test_is_older_than() {
+        let cases = [
+            // last modified, time, days, expected
+            ("2022-07-25T12:00:00Z", "2022-07-27T12:00:00Z", 2, true),
+            ("2022-07-25T12:00:00.000000001Z", "2022-07-27T12:00:00Z", 2, false),
+            ("2022-07-25T11:59:59.999999999Z", "2022-07-27T12:00:00Z", 2, true),
+            ("2022-07-26T23:59:59Z", "2022-07-27T00:00:00Z", 1, false),
+            // a day is always 24 hours, also when other time zones switch to daylight saving time
+            ("2022-03-26T12:00:00Z", "2022-03-27T12:00:00Z", 1, true),
+            ("2022-10-29T12:30:00Z", "2022-10-30T12:00:00Z", 1, false),
+            // leap days count as a day
+            ("2020-02-28T12:00:00Z", "2020-03-01T12:00:00Z", 2, true),
+            ("2020-02-28T12:00:00Z", "2020-03-01T12:00:00Z", 3, false),
+            ("2021-02-28T12:00:00Z", "2021-03-01T12:00:00Z", 1, true),
+            ("2100-02-28T12:00:00Z", "2100-03-01T12:00:00Z", 2, false),
+            // past the range of nanoseconds in an i64
+            ("2300-01-01T00:00:00Z", "2300-01-31T00:00:00Z", 30, true),
+            // a max age longer than time itself
+            ("2022-07-25T12:00:00Z", "2022-07-27T12:00:00Z", i64::MAX, false),
+        ];
+        for (last_modified, time, days, expected) in cases {
+            assert_eq!(is_older_than(Some(utc(last_modified)), utc(time), days).unwrap(), expected, "{} {} {}", last_modified, time, days);
+        }
+
+        assert!(is_older_than(None, utc("2022-07-27T12:00:00Z"), 2).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+//! The trash of the cleaner. With soft deletes enabled, deleted files are moved below a prefix of the bucket
+//! and purged in a later run, so files deleted by a misconfigured retention can still be restored.
+
+use chrono::{DateTime, Duration, Utc};
+use wave_store::{ObjectInfo, StoreErr, WaveStore};
+
+/// Prefix of the files in the trash. Files keep their key below it, e.g. `trash/<id>.wav`.
+pub const TRASH_PREFIX: &str = "trash/";
+
+pub fn trash_key(key: &str) -> String {
+    format!("{}{}", TRASH_PREFIX, key)
+}
+
+/// Copies an object into the trash, the engine deletes the original once it was copied.
+pub async fn copy_to_trash(key: &str, store: &dyn WaveStore) -> Result<(), StoreErr> {
+    store.copy(key, &trash_key(key)).await
+}
+
+/// Returns the objects, which were moved into the trash at least `retention` before `time`.
+/// The copy into the trash counts as a new object, so its last modification is the time it was moved.
+pub async fn expired(time: DateTime<Utc>, retention: Duration, store: &dyn WaveStore) -> Result<Vec<ObjectInfo>, StoreErr> {
+    let purge_before = time - retention;
+    Ok(store
+        .list_all(Some(TRASH_PREFIX))
+        .await?
+        .into_iter()
+        .filter(|object| matches!(object.last_modified, Some(moved) if moved <= purge_before))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use wave_store::InMemoryStore;
+
+    #[tokio::test]
+    async fn test_expired() {
+        let store = InMemoryStore::new();
+        let now = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+        store.put_with_last_modified("trash/old.wav", vec![0], now - Duration::hours(25));
+        store.put_with_last_modified("trash/new.wav", vec![0], now - Duration::hours(23));
+        store.put_with_last_modified("old.wav", vec![0], now - Duration::days(3));
+
+        let keys: Vec<String> = expired(now, Duration::hours(24), &store).await.unwrap().into_iter().map(|object| object.key).collect();
+        assert_eq!(keys, vec!["trash/old.wav".to_owned()]);
+    }
+}
@@ -0,0 +1,112 @@
+//! Sums up the objects listed by the cleaner into the storage stats of the bucket.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use sine_generator::data_formats::parse_legacy_id;
+use wave_store::ObjectInfo;
+use wave_table::StorageStats;
+
+/// Upper bounds of the age buckets in days, older objects are counted as `OLDEST_AGE`.
+const AGE_BUCKETS: [(i64, &str); 4] = [(1, "0-1d"), (2, "1-2d"), (7, "2-7d"), (30, "7-30d")];
+const OLDEST_AGE: &str = "30d+";
+const UNKNOWN: &str = "unknown";
+
+/// The storage of the objects, which stay in the bucket after a run.
+#[derive(Debug)]
+pub struct StorageUsage {
+    time: DateTime<Utc>,
+    stats: StorageStats,
+}
+
+impl StorageUsage {
+    pub fn new(time: DateTime<Utc>) -> Self {
+        let stats = StorageStats {
+            date: time.format("%F").to_string(),
+            created_at: time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            ..Default::default()
+        };
+        StorageUsage { time, stats }
+    }
+
+    /// Adds an object that stays in the bucket, `sample_rate` is the rate of the item of a file if it has one.
+    pub fn add(&mut self, object: &ObjectInfo, sample_rate: Option<u32>) {
+        let stats = &mut self.stats;
+        stats.object_count += 1;
+        stats.total_bytes += object.size;
+        *stats.bytes_by_age.entry(age_bucket(object.last_modified, self.time).to_owned()).or_default() += object.size;
+        *stats.bytes_by_format.entry(format_of(&object.key).to_owned()).or_default() += object.size;
+        let sample_rate = sample_rate.map_or_else(|| sample_rate_of(&object.key), |rate| rate.to_string());
+        *stats.bytes_by_sample_rate.entry(sample_rate).or_default() += object.size;
+    }
+
+    pub fn finish(self, reclaimed_bytes: i64) -> StorageStats {
+        StorageStats { reclaimed_bytes, ..self.stats }
+    }
+}
+
+fn age_bucket(last_modified: Option<DateTime<Utc>>, time: DateTime<Utc>) -> &'static str {
+    let age = match last_modified {
+        Some(last_modified) => time - last_modified,
+        None => return UNKNOWN,
+    };
+    AGE_BUCKETS
+        .iter()
+        .find(|(days, _)| age < chrono::Duration::days(*days))
+        .map_or(OLDEST_AGE, |(_, name)| name)
+}
+
+/// The extension of the key, bundles can have the double extension `tar.gz`.
+fn format_of(key: &str) -> &str {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    if name.ends_with(".tar.gz") {
+        return "tar.gz";
+    }
+    match name.rsplit_once('.') {
+        Some((_, extension)) if !extension.is_empty() => extension,
+        _ => "none",
+    }
+}
+
+/// The sample rate of an object without an item, only legacy keys contain the spec of the file,
+/// e.g. the trashed file `trash/567fab82_2_23000_16.wav`.
+fn sample_rate_of(key: &str) -> String {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    let id = name.split('.').next().unwrap_or(name);
+    match parse_legacy_id(id) {
+        Some((_, spec)) => spec.sample_rate.to_string(),
+        None => UNKNOWN.to_owned(),
+    }
+}
+
+#[test]
+fn test_storage_usage() {
+    use chrono::{Duration, TimeZone};
+
+    let time = Utc.ymd(2022, 7, 27).and_hms(12, 0, 0);
+    let objects = [
+        ("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E.wav", 100, Some(time - Duration::hours(1)), Some(44100)),
+        ("01GB6X3KQ8ZJ1V2WJZ4N4T2S9F.wav", 1000, Some(time - Duration::hours(2)), None),
+        ("567fab82_2_23000_16.wav", 200, Some(time - Duration::days(3)), None),
+        ("trash/567fab82_1_8000_8.wav", 300, Some(time - Duration::days(40)), None),
+        ("01GB6X3KQ8ZJ1V2WJZ4N4T2S9A.tar.gz", 400, Some(time - Duration::days(1)), None),
+        ("cleaner/last_cleaned_date", 10, None, None),
+    ];
+    let mut usage = StorageUsage::new(time);
+    for (key, size, last_modified, sample_rate) in objects {
+        usage.add(&ObjectInfo { key: key.to_owned(), size, last_modified }, sample_rate);
+    }
+
+    let stats = usage.finish(50);
+    assert_eq!(stats.date, "2022-07-27");
+    assert_eq!(stats.created_at, "2022-07-27T12:00:00.000Z");
+    assert_eq!(stats.object_count, 6);
+    assert_eq!(stats.total_bytes, 2010);
+    assert_eq!(stats.reclaimed_bytes, 50);
+    let bytes = |map: &std::collections::BTreeMap<String, i64>| map.iter().map(|(name, bytes)| (name.clone(), *bytes)).collect::<Vec<_>>();
+    assert_eq!(bytes(&stats.bytes_by_age), vec![
+        ("0-1d".to_owned(), 1100), ("1-2d".to_owned(), 400), ("2-7d".to_owned(), 200), ("30d+".to_owned(), 300), ("unknown".to_owned(), 10),
+    ]);
+    assert_eq!(bytes(&stats.bytes_by_format), vec![("none".to_owned(), 10), ("tar.gz".to_owned(), 400), ("wav".to_owned(), 1600)]);
+    assert_eq!(bytes(&stats.bytes_by_sample_rate), vec![
+        ("23000".to_owned(), 200), ("44100".to_owned(), 100), ("8000".to_owned(), 300), ("unknown".to_owned(), 1410),
+    ]);
+}
@@ -0,0 +1,261 @@
+use std::{fmt::Display, error, env};
+
+/// Names of the environment variables, which are set by terraform on each lambda.
+pub const TABLE_NAME: &str = "TF_VAR_TABLE_NAME";
+pub const GLOBAL_INDEX: &str = "TF_VAR_GLOBAL_INDEX";
+pub const CONTENT_INDEX: &str = "TF_VAR_CONTENT_INDEX";
+pub const BATCH_INDEX: &str = "TF_VAR_BATCH_INDEX";
+pub const OBJECT_KEY_INDEX: &str = "TF_VAR_OBJECT_KEY_INDEX";
+pub const STATS_TABLE: &str = "TF_VAR_STATS_TABLE";
+pub const BUCKET_NAME: &str = "TF_VAR_BUCKET_NAME";
+pub const GENERATOR_LAMBDA: &str = "TF_VAR_GENERATOR_LAMBDA";
+pub const MAX_RETENTION_DAYS: &str = "TF_VAR_MAX_RETENTION_DAYS";
+pub const MAX_RENDER_OPERATIONS: &str = "TF_VAR_MAX_RENDER_OPERATIONS";
+pub const MAX_FILE_SIZE: &str = "TF_VAR_MAX_FILE_SIZE";
+pub const RETENTION_POLICY: &str = "TF_VAR_RETENTION_POLICY";
+pub const TRASH_RETENTION_HOURS: &str = "TF_VAR_TRASH_RETENTION_HOURS";
+pub const DELETE_CONCURRENCY: &str = "TF_VAR_DELETE_CONCURRENCY";
+pub const DELETE_RATE: &str = "TF_VAR_DELETE_RATE";
+
+const TABLE_NAME_FALLBACK: &str = "cloud-wave-file";
+const GLOBAL_INDEX_FALLBACK: &str = "cloud-date-time-index";
+const CONTENT_INDEX_FALLBACK: &str = "cloud-content-hash-index";
+const BATCH_INDEX_FALLBACK: &str = "cloud-batch-index";
+const OBJECT_KEY_INDEX_FALLBACK: &str = "cloud-object-key-index";
+const STATS_TABLE_FALLBACK: &str = "cloud-storage-stats";
+const BUCKET_NAME_FALLBACK: &str = "cloud-wave-file-bucket";
+const GENERATOR_LAMBDA_FALLBACK: &str = "cloud-sine-generator";
+const MAX_RETENTION_DAYS_FALLBACK: i64 = 30;
+// roughly what the generator renders within its timeout
+const MAX_RENDER_OPERATIONS_FALLBACK: u64 = 500_000_000;
+const MAX_FILE_SIZE_FALLBACK: u64 = 512 * 1024 * 1024;
+const DELETE_CONCURRENCY_FALLBACK: u64 = 4;
+// S3 handles about 3500 deletes per second and prefix
+const DELETE_RATE_FALLBACK: u64 = 3000;
+
+#[derive(Debug, PartialEq)]
+pub struct ConfigErr(String);
+
+impl Display for ConfigErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for ConfigErr {}
+
+/// The names of the resources used by the lambdas, together with the server limits.
+///
+/// The values are read at runtime, so the same binary can be deployed to different stages.
+/// Variables that are not set fall back to the names used in the production deployment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub table_name: String,
+    pub global_index: String,
+    pub content_index: String,
+    pub batch_index: String,
+    pub object_key_index: String,
+    /// Table the bucket cleaner writes the storage stats of each run to.
+    pub stats_table: String,
+    pub bucket_name: String,
+    pub generator_lambda: String,
+    pub max_retention_days: i64,
+    /// Maximum number of sine evaluations needed to render a single file.
+    pub max_render_operations: u64,
+    /// Maximum size of a single file in bytes.
+    pub max_file_size: u64,
+    /// Retention rules of the bucket cleaner as JSON, which parses them during its cold start.
+    /// Not set if the variable is missing or empty, the cleaner uses its default retention then.
+    pub retention_policy: Option<String>,
+    /// Hours the bucket cleaner keeps deleted files in its trash, before they are deleted for good.
+    /// Not set if the variable is missing or empty, the cleaner deletes files right away then.
+    pub trash_retention_hours: Option<u64>,
+    /// Number of delete requests the bucket cleaner sends at the same time.
+    pub delete_concurrency: u64,
+    /// Number of objects the bucket cleaner deletes per second at most.
+    pub delete_rate: u64,
+}
+
+impl Config {
+    /// Reads the configuration from the environment of the process.
+    /// Should be called once during the cold start of a lambda, so a misconfiguration fails early.
+    pub fn from_env() -> Result<Self, ConfigErr> {
+        Self::from_lookup(|name| env::var(name).ok())
+    }
+
+    /// Reads the configuration with a custom lookup function, which returns the value of a variable by its name.
+    /// Useful to inject values in tests, without modifying the environment of the process.
+    pub fn from_lookup<F>(lookup: F) -> Result<Self, ConfigErr>
+    where F: Fn(&str) -> Option<String>
+    {
+        let read = |name: &str, fallback: &str| lookup(name).unwrap_or_else(|| fallback.to_owned());
+        let read_number = |name: &str, fallback: u64| match lookup(name) {
+            Some(value) => value.parse().map_err(|_| ConfigErr(format!("{name} is not a number: {value}"))),
+            None => Ok(fallback),
+        };
+
+        let config = Config {
+            table_name: read(TABLE_NAME, TABLE_NAME_FALLBACK),
+            global_index: read(GLOBAL_INDEX, GLOBAL_INDEX_FALLBACK),
+            content_index: read(CONTENT_INDEX, CONTENT_INDEX_FALLBACK),
+            batch_index: read(BATCH_INDEX, BATCH_INDEX_FALLBACK),
+            object_key_index: read(OBJECT_KEY_INDEX, OBJECT_KEY_INDEX_FALLBACK),
+            stats_table: read(STATS_TABLE, STATS_TABLE_FALLBACK),
+            bucket_name: read(BUCKET_NAME, BUCKET_NAME_FALLBACK),
+            generator_lambda: read(GENERATOR_LAMBDA, GENERATOR_LAMBDA_FALLBACK),
+            max_retention_days: match lookup(MAX_RETENTION_DAYS) {
+                Some(days) => days
+                    .parse()
+                    .map_err(|_| ConfigErr(format!("{MAX_RETENTION_DAYS} is not a number: {days}")))?,
+                None => MAX_RETENTION_DAYS_FALLBACK,
+            },
+            max_render_operations: read_number(MAX_RENDER_OPERATIONS, MAX_RENDER_OPERATIONS_FALLBACK)?,
+            max_file_size: read_number(MAX_FILE_SIZE, MAX_FILE_SIZE_FALLBACK)?,
+            retention_policy: lookup(RETENTION_POLICY).filter(|policy| !policy.trim().is_empty()),
+            trash_retention_hours: match lookup(TRASH_RETENTION_HOURS).filter(|hours| !hours.trim().is_empty()) {
+                Some(hours) => Some(hours
+                    .parse()
+                    .map_err(|_| ConfigErr(format!("{TRASH_RETENTION_HOURS} is not a number: {hours}")))?),
+                None => None,
+            },
+            delete_concurrency: read_number(DELETE_CONCURRENCY, DELETE_CONCURRENCY_FALLBACK)?,
+            delete_rate: read_number(DELETE_RATE, DELETE_RATE_FALLBACK)?,
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the values against the naming rules of the respective aws service.
+    fn validate(&self) -> Result<(), ConfigErr> {
+        validate_dynamodb_name(TABLE_NAME, &self.table_name)?;
+        validate_dynamodb_name(GLOBAL_INDEX, &self.global_index)?;
+        validate_dynamodb_name(CONTENT_INDEX, &self.content_index)?;
+        validate_dynamodb_name(BATCH_INDEX, &self.batch_index)?;
+        validate_dynamodb_name(OBJECT_KEY_INDEX, &self.object_key_index)?;
+        validate_dynamodb_name(STATS_TABLE, &self.stats_table)?;
+        validate_bucket_name(BUCKET_NAME, &self.bucket_name)?;
+        validate_lambda_name(GENERATOR_LAMBDA, &self.generator_lambda)?;
+
+        if self.max_retention_days < 1 {
+            return Err(ConfigErr(format!("{MAX_RETENTION_DAYS} needs to be at least 1, got {}", self.max_retention_days)));
+        }
+        if self.max_render_operations < 1 {
+            return Err(ConfigErr(format!("{MAX_RENDER_OPERATIONS} needs to be at least 1")));
+        }
+        if self.max_file_size < 1 {
+            return Err(ConfigErr(format!("{MAX_FILE_SIZE} needs to be at least 1")));
+        }
+        if self.delete_concurrency < 1 {
+            return Err(ConfigErr(format!("{DELETE_CONCURRENCY} needs to be at least 1")));
+        }
+        if self.delete_rate < 1 {
+            return Err(ConfigErr(format!("{DELETE_RATE} needs to be at least 1")));
+        }
+        if self.trash_retention_hours == Some(0) {
+            return Err(ConfigErr(format!("{TRASH_RETENTION_HOURS} needs to be at least 1, leave it empty to disable the trash")));
+        }
+
+        Ok(())
+    }
+}
+
+/// Table and index names: 3 to 255 characters, alphanumeric, `_`, `-` and `.`
+fn validate_dynamodb_name(variable: &str, value: &str) -> Result<(), ConfigErr> {
+    let valid_chars = value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if !(3..=255).contains(&value.len()) || !valid_chars {
+        return Err(ConfigErr(format!("{variable} is not a valid table or index name: {value}")));
+    }
+    Ok(())
+}
+
+/// Bucket names: 3 to 63 characters, lowercase alphanumeric, `-` and `.`,
+/// starting and ending with a letter or a digit
+fn validate_bucket_name(variable: &str, value: &str) -> Result<(), ConfigErr> {
+    let valid_chars = value.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.');
+    let valid_ends = value.starts_with(|c: char| c.is_ascii_alphanumeric()) && value.ends_with(|c: char| c.is_ascii_alphanumeric());
+    if !(3..=63).contains(&value.len()) || !valid_chars || !valid_ends {
+        return Err(ConfigErr(format!("{variable} is not a valid bucket name: {value}")));
+    }
+    Ok(())
+}
+
+/// Function names: 1 to 64 characters, alphanumeric, `_` and `-`. ARNs are accepted as well.
+fn validate_lambda_name(variable: &str, value: &str) -> Result<(), ConfigErr> {
+    if value.starts_with("arn:") {
+        return Ok(());
+    }
+    let valid_chars = value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !(1..=64).contains(&value.len()) || !valid_chars {
+        return Err(ConfigErr(format!("{variable} is not a valid function name: {value}")));
+    }
+    Ok(())
+}
+
+#[test]
+fn from_lookup_uses_fallbacks() {
+    let config = Config::from_lookup(|_| None).unwrap();
+    assert_eq!(config.table_name, "cloud-wave-file");
+    assert_eq!(config.global_index, "cloud-date-time-index");
+    assert_eq!(config.content_index, "cloud-content-hash-index");
+    assert_eq!(config.batch_index, "cloud-batch-index");
+    assert_eq!(config.object_key_index, "cloud-object-key-index");
+    assert_eq!(config.stats_table, "cloud-storage-stats");
+    assert_eq!(config.bucket_name, "cloud-wave-file-bucket");
+    assert_eq!(config.generator_lambda, "cloud-sine-generator");
+    assert_eq!(config.max_retention_days, 30);
+    assert_eq!(config.max_render_operations, 500_000_000);
+    assert_eq!(config.max_file_size, 512 * 1024 * 1024);
+    assert_eq!(config.retention_policy, None);
+    assert_eq!(config.trash_retention_hours, None);
+    assert_eq!(config.delete_concurrency, 4);
+    assert_eq!(config.delete_rate, 3000);
+}
+
+#[test]
+fn from_lookup_reads_variables() {
+    use std::collections::HashMap;
+
+    let env = HashMap::from([
+        (TABLE_NAME, "staging-wave-file"),
+        (BUCKET_NAME, "staging-wave-file-bucket"),
+        (MAX_RETENTION_DAYS, "7"),
+        (MAX_RENDER_OPERATIONS, "1000"),
+        (RETENTION_POLICY, r#"{"rules": []}"#),
+        (TRASH_RETENTION_HOURS, "24"),
+        (DELETE_CONCURRENCY, "8"),
+    ]);
+    let config = Config::from_lookup(|name| env.get(name).map(|value| value.to_string())).unwrap();
+    assert_eq!(config.table_name, "staging-wave-file");
+    assert_eq!(config.bucket_name, "staging-wave-file-bucket");
+    assert_eq!(config.global_index, "cloud-date-time-index");
+    assert_eq!(config.max_retention_days, 7);
+    assert_eq!(config.max_render_operations, 1000);
+    assert_eq!(config.retention_policy.as_deref(), Some(r#"{"rules": []}"#));
+    assert_eq!(config.trash_retention_hours, Some(24));
+    assert_eq!(config.delete_concurrency, 8);
+}
+
+#[test]
+fn from_lookup_rejects_invalid_values() {
+    let invalid = [
+        (TABLE_NAME, "wave table"),
+        (GLOBAL_INDEX, "ix"),
+        (BUCKET_NAME, "Cloud_Bucket"),
+        (BUCKET_NAME, "-bucket"),
+        (GENERATOR_LAMBDA, "sine:generator"),
+        (MAX_RETENTION_DAYS, "two"),
+        (MAX_RETENTION_DAYS, "0"),
+        (MAX_RENDER_OPERATIONS, "-1"),
+        (MAX_FILE_SIZE, "0"),
+        (TRASH_RETENTION_HOURS, "0"),
+        (TRASH_RETENTION_HOURS, "a day"),
+        (DELETE_CONCURRENCY, "0"),
+        (DELETE_RATE, "0"),
+    ];
+
+    for (variable, value) in invalid {
+        let config = Config::from_lookup(|name| if name == variable { Some(value.to_owned()) } else { None });
+        assert!(config.is_err(), "{variable}={value} should be invalid");
+    }
+}
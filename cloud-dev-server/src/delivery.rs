@@ -0,0 +1,222 @@
+use std::{fmt::Display, error};
+
+use serde_json::{json, Value};
+use wave_store::WaveStore;
+use wave_table::{WaveRepository, RepositoryErr, FILE_EXTENSION};
+
+/// Size of the parts a file is sent in, same as in the wave delivery service.
+pub const BYTE_RANGE: usize = 4096000;
+
+#[derive(Debug, PartialEq)]
+pub struct DeliveryErr(String);
+
+impl Display for DeliveryErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for DeliveryErr {}
+
+impl From<RepositoryErr> for DeliveryErr {
+    fn from(err: RepositoryErr) -> Self {
+        DeliveryErr(err.to_string())
+    }
+}
+
+/// The state of a requested file.
+#[derive(Debug, PartialEq)]
+pub enum Delivery {
+    /// The file is still being generated, the frontend should ask again later.
+    InProgress,
+    /// A part of the file, large files are sent in several parts of `BYTE_RANGE` bytes.
+    /// The checksums cover the whole file, so the client can verify it once all parts are put together.
+    Ready { part: Vec<u8>, is_last: bool, sha256: Option<String>, crc32c: Option<String> },
+    /// The generator rejected the file, it will never be ready.
+    Failed { error: String },
+    /// The bucket cleaner removed the file at `deleted_at`, in seconds since the epoch.
+    Deleted { deleted_at: i64 },
+}
+
+/// Returns the part `offset_num` of the file with the id `file_id`,
+/// and marks the item as downloaded after the last part was sent.
+///
+/// Behaves like the wave delivery service: only the client, which made the request, may download the file, and only once.
+pub async fn deliver(
+    file_id: &str,
+    request_id: &str,
+    offset_num: usize,
+    repository: &dyn WaveRepository,
+    store: &dyn WaveStore)
+-> Result<Delivery, DeliveryErr> {
+    let mut item = repository.get(file_id).await?.ok_or_else(|| DeliveryErr(format!("No file with id {}", file_id)))?;
+
+    if item.request_id != request_id {
+        return Err(DeliveryErr("Different client than the one initiating the request. Request not valid.".to_owned()));
+    }
+    if item.is_downloaded {
+        return Err(DeliveryErr("Corresponding file to request_id already downloaded. Request not valid.".to_owned()));
+    }
+
+    // files of deduplicated requests are stored under the key of the original request
+    let file = store.get(&item.object_key()).await.ok().flatten();
+
+    // the checksums and the errors are recorded on the item, which creates the file
+    let is_owner = item.object_key() == item.id.clone() + FILE_EXTENSION;
+    let owner = if is_owner || item.sha256.is_some() || item.error.is_some() {
+        None
+    } else {
+        repository.get_by_object_key(&item.object_key()).await?
+    };
+    let (sha256, crc32c, error) = match owner {
+        Some(owner) => (owner.sha256, owner.crc32c, owner.error),
+        None => (item.sha256.clone(), item.crc32c.clone(), item.error.clone()),
+    };
+
+    let file = match (file, error, item.deleted_at) {
+        (Some(file), _, _) => file,
+        (None, Some(error), _) => return Ok(Delivery::Failed { error }),
+        (None, None, Some(deleted_at)) => return Ok(Delivery::Deleted { deleted_at }),
+        (None, None, None) => return Ok(Delivery::InProgress),
+    };
+
+    let offset = (offset_num * BYTE_RANGE).min(file.len());
+    let end = (offset + BYTE_RANGE).min(file.len());
+    let is_last = end == file.len();
+
+    if is_last {
+        item.is_downloaded = true;
+        repository.put(item).await?;
+    }
+
+    Ok(Delivery::Ready { part: file[offset..end].to_vec(), is_last, sha256, crc32c })
+}
+
+/// Wraps the result into the response of the wave delivery service, which the frontend expects.
+pub fn to_response(result: Result<Delivery, DeliveryErr>) -> Value {
+    let headers = json!({ "Content-Type": "application/json" });
+    match result {
+        Ok(Delivery::InProgress) => json!({
+            "isBase64Encoded": false, "isLast": true, "statusCode": "200", "headers": headers,
+            "body": { "status": "in_progress" },
+        }),
+        Ok(Delivery::Ready { part, is_last, sha256, crc32c }) => json!({
+            "isBase64Encoded": true, "isLast": is_last, "statusCode": "200", "headers": headers,
+            "body": { "status": "ready", "file": base64::encode(part), "sha256": sha256, "crc32c": crc32c },
+        }),
+        Ok(Delivery::Failed { error }) => json!({
+            "isBase64Encoded": false, "isLast": true, "statusCode": "200", "headers": headers,
+            "body": { "status": "failed", "error": error },
+        }),
+        Ok(Delivery::Deleted { deleted_at }) => json!({
+            "isBase64Encoded": false, "isLast": true, "statusCode": "200", "headers": headers,
+            "body": { "status": "deleted", "deleted_at": deleted_at },
+        }),
+        Err(e) => json!({
+            "isBase64Encoded": false, "isLast": true, "statusCode": "400", "headers": headers,
+            "body": e.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use wave_store::InMemoryStore;
+    use wave_table::{CreationTime, InMemoryRepository, WaveItem};
+
+    pub(crate) fn test_item(id: &str, request_id: &str) -> WaveItem {
+        use chrono::{TimeZone, Utc};
+        use sine_generator::data_formats::{WavSpec, WavData};
+
+        let spec = WavSpec::new(1, 8000, 8).unwrap();
+        let data = WavData { frequencies: vec![440], duration: 2, volume: 1. };
+        let created = CreationTime::from(Utc.ymd(2022, 7, 27).and_hms(12, 0, 0));
+        WaveItem::new(id, request_id, spec, data, created, 0, ("hash", &format!("{}.wav", id)))
+    }
+
+    #[tokio::test]
+    async fn test_deliver_in_parts() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        repository.put(test_item("a", "request")).await.unwrap();
+
+        assert_eq!(deliver("a", "request", 0, &repository, &store).await, Ok(Delivery::InProgress));
+
+        store.put("a.wav", vec![7; BYTE_RANGE + 10]).await.unwrap();
+        match deliver("a", "request", 0, &repository, &store).await.unwrap() {
+            Delivery::Ready { part, is_last, .. } => assert_eq!((part.len(), is_last), (BYTE_RANGE, false)),
+            _ => panic!("file should be ready"),
+        }
+        assert!(!repository.get("a").await.unwrap().unwrap().is_downloaded);
+
+        match deliver("a", "request", 1, &repository, &store).await.unwrap() {
+            Delivery::Ready { part, is_last, .. } => assert_eq!((part.len(), is_last), (10, true)),
+            _ => panic!("file should be ready"),
+        }
+        assert!(repository.get("a").await.unwrap().unwrap().is_downloaded);
+        assert!(deliver("a", "request", 0, &repository, &store).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_deliver_checksums_of_original_file() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let mut original = test_item("a", "request");
+        original.sha256 = Some("abc".to_owned());
+        original.crc32c = Some("AAAAAA==".to_owned());
+        repository.put(original).await.unwrap();
+        // a deduplicated request points to the file of the original request
+        let mut duplicate = test_item("b", "other");
+        duplicate.object_key = Some("a.wav".to_owned());
+        repository.put(duplicate).await.unwrap();
+        store.put("a.wav", vec![7; 10]).await.unwrap();
+
+        let response = to_response(deliver("b", "other", 0, &repository, &store).await);
+        assert_eq!(response["body"]["sha256"], "abc");
+        assert_eq!(response["body"]["crc32c"], "AAAAAA==");
+    }
+
+    #[tokio::test]
+    async fn test_deliver_rejected_file() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let mut original = test_item("a", "request");
+        original.error = Some("over budget".to_owned());
+        repository.put(original).await.unwrap();
+        let mut duplicate = test_item("b", "other");
+        duplicate.object_key = Some("a.wav".to_owned());
+        repository.put(duplicate).await.unwrap();
+
+        let failed = Delivery::Failed { error: "over budget".to_owned() };
+        assert_eq!(deliver("a", "request", 0, &repository, &store).await, Ok(failed));
+        let response = to_response(deliver("b", "other", 0, &repository, &store).await);
+        assert_eq!((response["body"]["status"].as_str(), response["body"]["error"].as_str()), (Some("failed"), Some("over budget")));
+        assert!(!repository.get("a").await.unwrap().unwrap().is_downloaded);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_deleted_file() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let mut item = test_item("a", "request");
+        item.deleted_at = Some(1659268800);
+        repository.put(item).await.unwrap();
+
+        let response = to_response(deliver("a", "request", 0, &repository, &store).await);
+        assert_eq!((response["body"]["status"].as_str(), response["body"]["deleted_at"].as_i64()), (Some("deleted"), Some(1659268800)));
+    }
+
+    #[tokio::test]
+    async fn test_deliver_invalid_request() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        repository.put(test_item("a", "request")).await.unwrap();
+
+        assert!(deliver("a", "other", 0, &repository, &store).await.is_err());
+        assert!(deliver("b", "request", 0, &repository, &store).await.is_err());
+
+        let response = to_response(deliver("a", "other", 0, &repository, &store).await);
+        assert_eq!(response["statusCode"], "400");
+    }
+}
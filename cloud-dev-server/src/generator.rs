@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use cloud_main::GeneratorClient;
+use lambda_runtime::Error;
+use serde_json::Value;
+use sine_generator::cost::RenderBudget;
+use tracing::{info, error};
+use wave_store::WaveStore;
+use wave_table::WaveRepository;
+
+/// Renders the files in a background task of the server,
+/// like the main lambda invoking the generator lambda with the `Event` invocation type.
+pub struct InProcessGenerator {
+    repository: Arc<dyn WaveRepository>,
+    store: Arc<dyn WaveStore>,
+    budget: RenderBudget,
+}
+
+impl InProcessGenerator {
+    pub fn new(repository: Arc<dyn WaveRepository>, store: Arc<dyn WaveStore>, budget: RenderBudget) -> Self {
+        InProcessGenerator { repository, store, budget }
+    }
+}
+
+#[async_trait]
+impl GeneratorClient for InProcessGenerator {
+    async fn invoke(&self, payload: Value) -> Result<(), Error> {
+        let repository = self.repository.clone();
+        let store = self.store.clone();
+        let budget = self.budget;
+        tokio::spawn(async move {
+            match cloud_sine_generator::handle_event(payload, &budget, repository.as_ref(), store.as_ref()).await {
+                Ok(response) => info!("Generator finished: {}", response),
+                Err(e) => error!("Generator failed: {}", e),
+            }
+        });
+        Ok(())
+    }
+}
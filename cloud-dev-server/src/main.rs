@@ -0,0 +1,67 @@
+//! Runs the whole pipeline on a local machine, without access to AWS.
+//!
+//! The handlers of the lambdas are hosted in-process behind a single HTTP server,
+//! the WaveTable and the bucket are kept in memory, or in a directory if `DEV_SERVER_DATA_DIR` is set.
+
+mod delivery;
+mod generator;
+mod persist;
+mod routes;
+
+use std::{convert::Infallible, env, fs, net::SocketAddr, path::Path, sync::Arc};
+
+use cloud_config::Config;
+use hyper::{service::{make_service_fn, service_fn}, Server};
+use lambda_runtime::Error;
+use tracing::info;
+use wave_store::{InMemoryStore, LocalStore, WaveStore};
+use wave_table::{InMemoryRepository, InMemoryStatsRepository, WaveRepository};
+
+use generator::InProcessGenerator;
+use persist::JsonFileRepository;
+use routes::State;
+
+/// Address the server listens on, defaults to `127.0.0.1:8080`.
+const ADDRESS: &str = "DEV_SERVER_ADDRESS";
+/// Directory to persist the table and the files in, if not set everything is lost on shutdown.
+const DATA_DIR: &str = "DEV_SERVER_DATA_DIR";
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false)
+        .init();
+
+    // uses the same variables as the lambdas, the bucket name is used as directory of the files
+    let config = Config::from_env()?;
+    let address: SocketAddr = env::var(ADDRESS).unwrap_or_else(|_| "127.0.0.1:8080".to_owned()).parse()?;
+
+    let (repository, store): (Arc<dyn WaveRepository>, Arc<dyn WaveStore>) = match env::var(DATA_DIR) {
+        Ok(dir) => {
+            info!("Storing table and files in {}", dir);
+            fs::create_dir_all(&dir)?;
+            let repository = JsonFileRepository::open(Path::new(&dir).join(format!("{}.json", config.table_name))).await?;
+            (Arc::new(repository), Arc::new(LocalStore::new(Path::new(&dir).join(&config.bucket_name))))
+        },
+        Err(_) => {
+            info!("Keeping table and files in memory");
+            (Arc::new(InMemoryRepository::new()), Arc::new(InMemoryStore::new()))
+        },
+    };
+
+    let generator = InProcessGenerator::new(repository.clone(), store.clone(), cloud_main::render_budget(&config));
+    let state = Arc::new(State { generator, repository, store, stats: Arc::new(InMemoryStatsRepository::new()), config });
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| routes::route(request, state.clone()))) }
+    });
+
+    info!("Listening on http://{}", address);
+    Server::bind(&address)
+        .serve(make_service)
+        .with_graceful_shutdown(async { tokio::signal::ctrl_c().await.unwrap_or_default() })
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::{fs, path::PathBuf};
+
+use async_trait::async_trait;
+use wave_table::{FileRef, InMemoryRepository, RepositoryErr, WaveItem, WaveRepository};
+
+/// A `WaveRepository` which keeps the items in memory and writes all of them to a json file after each change,
+/// so the table survives a restart of the development server.
+pub struct JsonFileRepository {
+    path: PathBuf,
+    items: InMemoryRepository,
+}
+
+impl JsonFileRepository {
+    /// Loads the items from the file at `path`, if it already exists.
+    pub async fn open<P: Into<PathBuf>>(path: P) -> Result<Self, RepositoryErr> {
+        let path = path.into();
+        let items = InMemoryRepository::new();
+
+        if path.is_file() {
+            let content = fs::read(&path).map_err(|e| RepositoryErr::new(&e.to_string()))?;
+            let stored: Vec<WaveItem> = serde_json::from_slice(&content).map_err(|e| RepositoryErr::new(&e.to_string()))?;
+            for item in stored {
+                items.put(item).await?;
+            }
+        }
+
+        Ok(JsonFileRepository { path, items })
+    }
+
+    fn save(&self) -> Result<(), RepositoryErr> {
+        let content = serde_json::to_vec_pretty(&self.items.items()).map_err(|e| RepositoryErr::new(&e.to_string()))?;
+        fs::write(&self.path, content).map_err(|e| RepositoryErr::new(&e.to_string()))
+    }
+}
+
+#[async_trait]
+impl WaveRepository for JsonFileRepository {
+    async fn put(&self, item: WaveItem) -> Result<(), RepositoryErr> {
+        self.items.put(item).await?;
+        self.save()
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<WaveItem>, RepositoryErr> {
+        self.items.get(id).await
+    }
+
+    async fn query_by_date(&self, date: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        self.items.query_by_date(date).await
+    }
+
+    async fn query_by_content_hash(&self, content_hash: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        self.items.query_by_content_hash(content_hash).await
+    }
+
+    async fn query_by_object_key(&self, object_key: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        self.items.query_by_object_key(object_key).await
+    }
+
+    async fn query_by_batch(&self, batch_id: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        self.items.query_by_batch(batch_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery::tests::test_item;
+
+    #[tokio::test]
+    async fn test_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wave-table.json");
+
+        let repository = JsonFileRepository::open(&path).await.unwrap();
+        repository.put(test_item("a", "request")).await.unwrap();
+        drop(repository);
+
+        let repository = JsonFileRepository::open(&path).await.unwrap();
+        assert_eq!(repository.get("a").await.unwrap(), Some(test_item("a", "request")));
+    }
+}
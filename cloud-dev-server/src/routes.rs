@@ -0,0 +1,133 @@
+use std::{convert::Infallible, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use cloud_bucket_cleaner::{CleanerEvent, CleanerInvocation, Settings};
+use cloud_config::Config;
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use lambda_runtime::Error;
+use serde_json::Value;
+use tracing::{info, error};
+use ulid::Ulid;
+use wave_store::WaveStore;
+use wave_table::{StatsRepository, WaveRepository};
+
+use crate::{delivery, generator::InProcessGenerator};
+
+/// Everything the handlers need, shared between all connections.
+pub struct State {
+    pub repository: Arc<dyn WaveRepository>,
+    pub store: Arc<dyn WaveStore>,
+    /// The stats of the cleaner runs, always kept in memory.
+    pub stats: Arc<dyn StatsRepository>,
+    pub generator: InProcessGenerator,
+    pub config: Config,
+}
+
+/// Dispatches a request to the handler of the respective lambda:
+///
+/// - `POST /main` with the same body the frontend sends to the main lambda
+/// - `GET /delivery?file_id=..&request_id=..&offset_num=..` like the wave delivery service
+/// - `POST /generator` with the payload the main lambda sends to the generator, renders the file synchronously
+/// - `POST /cleaner[?time=<rfc3339>]` runs the bucket cleaner as if it was triggered at `time`, defaults to now.
+///   A body is passed to the cleaner as the event of a manual invocation, e.g. a backfill or a dry run
+pub async fn route(request: Request<Body>, state: Arc<State>) -> Result<Response<Body>, Infallible> {
+    info!("{} {}", request.method(), request.uri());
+
+    let result = match (request.method(), request.uri().path()) {
+        // preflight requests of the frontend, the cors headers are added to every response
+        (&Method::OPTIONS, _) => Ok(Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty()).unwrap()),
+        (&Method::POST, "/main") => handle_main(request, &state).await,
+        (&Method::GET, "/delivery") => handle_delivery(request, &state).await,
+        (&Method::POST, "/generator") => handle_generator(request, &state).await,
+        (&Method::POST, "/cleaner") => handle_cleaner(request, &state).await,
+        _ => Ok(text(StatusCode::NOT_FOUND, "Not found")),
+    };
+
+    let mut response = result.unwrap_or_else(|e| {
+        error!("Error while handling request: {}", e);
+        text(StatusCode::BAD_REQUEST, &e.to_string())
+    });
+    let headers = response.headers_mut();
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*".parse().unwrap());
+    headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type".parse().unwrap());
+    headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, POST, OPTIONS".parse().unwrap());
+
+    Ok(response)
+}
+
+async fn handle_main(request: Request<Body>, state: &State) -> Result<Response<Body>, Error> {
+    let body = read_json(request).await?;
+    let request_id = Ulid::new().to_string();
+    let response = cloud_main::handle_request(
+        body,
+        &request_id,
+        state.repository.as_ref(),
+        state.store.as_ref(),
+        &state.generator,
+        &state.config).await?;
+    Ok(json_response(&response))
+}
+
+async fn handle_delivery(request: Request<Body>, state: &State) -> Result<Response<Body>, Error> {
+    let file_id = query_param(&request, "file_id").unwrap_or_default();
+    let request_id = query_param(&request, "request_id").unwrap_or_default();
+    let offset_num = query_param(&request, "offset_num").unwrap_or_default().parse().unwrap_or(0);
+
+    let result = delivery::deliver(&file_id, &request_id, offset_num, state.repository.as_ref(), state.store.as_ref()).await;
+    Ok(json_response(&delivery::to_response(result)))
+}
+
+async fn handle_generator(request: Request<Body>, state: &State) -> Result<Response<Body>, Error> {
+    let event = read_json(request).await?;
+    let budget = cloud_main::render_budget(&state.config);
+    let response = cloud_sine_generator::handle_event(event, &budget, state.repository.as_ref(), state.store.as_ref()).await?;
+    Ok(json_response(&response))
+}
+
+async fn handle_cleaner(request: Request<Body>, state: &State) -> Result<Response<Body>, Error> {
+    let time = match query_param(&request, "time") {
+        Some(time) => DateTime::parse_from_rfc3339(&time)?.with_timezone(&Utc),
+        None => Utc::now(),
+    };
+
+    // a body contains the event of a manual invocation, e.g. a backfill
+    let body = hyper::body::to_bytes(request.into_body()).await?;
+    let invocation = match body.is_empty() {
+        true => CleanerInvocation { event: CleanerEvent::Scheduled { time }, dry_run: false },
+        false => CleanerInvocation::from_value(&serde_json::from_slice(&body)?)?,
+    };
+
+    let settings = Settings::from_config(&state.config)?;
+    let report = cloud_bucket_cleaner::handle_event(invocation, &settings, None, state.repository.as_ref(), state.store.as_ref(), state.stats.as_ref()).await?;
+    Ok(json_response(&report.to_json()))
+}
+
+async fn read_json(request: Request<Body>) -> Result<Value, Error> {
+    let body = hyper::body::to_bytes(request.into_body()).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Returns the value of a parameter in the query string, the values are expected to be url safe.
+fn query_param(request: &Request<Body>, name: &str) -> Option<String> {
+    request
+        .uri()
+        .query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_owned())
+}
+
+fn json_response(value: &Value) -> Response<Body> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(value.to_string()))
+        .unwrap()
+}
+
+fn text(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(message.to_owned()))
+        .unwrap()
+}
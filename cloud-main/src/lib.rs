@@ -0,0 +1,806 @@
+//! The logic of the main lambda, which receives the requests of the frontend.
+//!
+//! The handler only depends on the `WaveRepository`, `WaveStore` and `GeneratorClient` traits,
+//! so it can be hosted by the lambda runtime as well as by the local development server.
+
+use std::{collections::HashMap, fmt::Display, error, str::FromStr};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use lambda_runtime::Error;
+use aws_sdk_lambda::{types::Blob, model::InvocationType};
+use cloud_config::Config;
+use serde_json::{json, Value};
+use sha2::{Sha256, Digest};
+use sine_generator::{cost::{BudgetErr, RenderBudget, RenderCost}, data_formats::{WavData, WavSpec, Verifiable, canonical_form}};
+use tracing::{info, debug};
+use ulid::{Generator, Ulid};
+use wave_store::{BundleFormat, WaveStore};
+use wave_table::{CreationTime, WaveItem, WaveRepository, FILE_EXTENSION};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Maximum number of files, which can be requested in a single batch.
+const MAX_BATCH_SIZE: usize = 100;
+
+#[derive(Debug)]
+struct InvalidRequestErr(&'static str);
+
+impl Display for InvalidRequestErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for InvalidRequestErr {}
+
+/// The retention tiers a request can choose from.
+/// Each tier keeps files for a default amount of days, 
+/// and limits how long a request may ask for them to be kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RetentionTier {
+    Basic,
+    Extended,
+}
+
+impl RetentionTier {
+    fn default_days(&self) -> i64 {
+        match self {
+            RetentionTier::Basic => 2,
+            RetentionTier::Extended => 7,
+        }
+    }
+
+    fn max_days(&self) -> i64 {
+        match self {
+            RetentionTier::Basic => 2,
+            RetentionTier::Extended => 30,
+        }
+    }
+}
+
+impl FromStr for RetentionTier {
+    type Err = InvalidRequestErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "basic" => Ok(RetentionTier::Basic),
+            "extended" => Ok(RetentionTier::Extended),
+            _ => Err(InvalidRequestErr("unknown retention tier")),
+        }
+    }
+}
+
+/// Returns the limits of a single file, which are checked here and again by the generator.
+pub fn render_budget(config: &Config) -> RenderBudget {
+    RenderBudget { max_operations: config.max_render_operations, max_file_size: config.max_file_size }
+}
+
+/// Starts the generation of a wave file.
+/// The lambda invokes the generator asynchronously, the development server renders the file in-process.
+#[async_trait]
+pub trait GeneratorClient: Send + Sync {
+    /// Hands the payload to the generator, without waiting for the file to be complete.
+    async fn invoke(&self, payload: Value) -> Result<(), Error>;
+}
+
+/// Invokes the generator lambda with the `Event` invocation type.
+pub struct LambdaGenerator {
+    client: aws_sdk_lambda::Client,
+    function_name: String,
+}
+
+impl LambdaGenerator {
+    pub fn new(client: aws_sdk_lambda::Client, function_name: &str) -> Self {
+        LambdaGenerator { client, function_name: function_name.to_owned() }
+    }
+}
+
+#[async_trait]
+impl GeneratorClient for LambdaGenerator {
+    async fn invoke(&self, payload: Value) -> Result<(), Error> {
+        let lambda = self.client
+            .invoke()
+            .invocation_type(InvocationType::Event)
+            .function_name(&self.function_name)
+            .payload(Blob::new(payload.to_string()))
+            .send()
+            .await?; 
+        
+        debug!("Lambda output {:?}", lambda);
+        Ok(())
+    }
+}
+
+/// Handles a request of the frontend: creates an item for the requested file
+/// and starts the generation, unless an identical file already exists.
+/// Returns the id of the file together with the id of the request, which are needed to download it.
+///
+/// Requests exceeding the render budget are rejected, unless they set `downgrade` to `true`.
+/// In that case the sample rate is lowered until the file fits, and the response contains the `wav_spec` that is used.
+///
+/// Requests containing a `batch` field are handled by `handle_batch`, 
+/// requests containing only a `batch_id` return the status of that batch.
+pub async fn handle_request(
+    body: Value,
+    request_id: &str,
+    repository: &dyn WaveRepository,
+    store: &dyn WaveStore,
+    generator: &dyn GeneratorClient,
+    config: &Config)
+-> Result<Value, Error> {
+    debug!("Request Body: {:?}", body);
+
+    if body.get("batch").is_some() {
+        return handle_batch(body, request_id, repository, store, generator, config).await;
+    }
+    if let Some(batch_id) = body.get("batch_id") {
+        let batch_id = batch_id.as_str().ok_or(InvalidRequestErr("batch_id invalid format"))?;
+        return batch_status(batch_id, repository, store).await;
+    }
+
+    info!("Verifying request data");
+    let (requested_spec, data) = verify_specs(&body)?;
+    let retention_days = verify_retention(&body, config.max_retention_days)?;
+    let spec = verify_budget(requested_spec, &data, verify_downgrade(&body)?, &render_budget(config))?;
+    let downgraded = spec != requested_spec;
+    if downgraded {
+        info!("Downgraded sample rate from {} to {}", requested_spec.sample_rate, spec.sample_rate);
+    }
+
+    info!("Looking for an existing file with the same content");
+    let content_hash = create_content_hash(&spec, &data);
+    let existing_key = find_existing_object(repository, store, &content_hash).await?;
+
+    info!("Creating entry for dynamoDB");
+    let partition_key = create_partition_key();
+    let object_key = match &existing_key {
+        Some(key) => key.clone(),
+        None => partition_key.clone() + FILE_EXTENSION,
+    };
+    let created = CreationTime::from(Utc::now());
+    let expires_at = created.created_at_ms / 1000 + retention_days * SECONDS_PER_DAY;
+    info!("File will expire after {} days", retention_days);
+    let item = WaveItem::new(&partition_key, request_id, spec, data, created, expires_at, (&content_hash, &object_key));
+    debug!("DB Item:\n{:?}", item);
+
+    // store in dynamo db
+    info!("Inserting into dynamoDB");
+    repository.put(item).await?;
+
+    // an identical file is already in the bucket, so there is no need to create it again
+    let mut response = json!({"id": partition_key, "request_id": request_id});
+    if downgraded {
+        response["wav_spec"] = json!(spec);
+    }
+
+    if existing_key.is_some() {
+        info!("Found existing file {}, skipping generation", object_key);
+        info!("Response: {}", response);
+        return Ok(response);
+    }
+
+    let lambda_payload = json!({ "wav_id": partition_key, "wav_data": body["wav_data"], "wav_spec": spec });
+    
+    info!("Invoking lambda with:\n{:?}", lambda_payload);
+    generator.invoke(lambda_payload).await?;
+
+    info!("Response: {}", response);
+    Ok(response)
+}
+
+/// Handles a batch request, which contains a list of `wav_spec`/`wav_data` pairs in its `batch` field.
+///
+/// Creates an item for each file, which belongs to the batch via its `batch_id` attribute, 
+/// and invokes the generator once for all files that need to be generated.
+/// Identical files are only generated once, also within the same batch.
+/// If the optional `bundle` field is set, the generator also puts an archive of all files into the bucket.
+/// The whole batch is rejected if one of the files exceeds the render budget, unless `downgrade` is set,
+/// or if the distinct files of the batch exceed the budget together.
+/// Returns the id of the batch together with the ids of the files, in the order of the request,
+/// and the ids of the files that were downgraded.
+async fn handle_batch(
+    body: Value,
+    request_id: &str,
+    repository: &dyn WaveRepository,
+    store: &dyn WaveStore,
+    generator: &dyn GeneratorClient,
+    config: &Config)
+-> Result<Value, Error> {
+    info!("Verifying batch request");
+    let requests = verify_batch(&body)?;
+    let retention_days = verify_retention(&body, config.max_retention_days)?;
+    let bundle = verify_bundle(&body)?;
+    let downgrade = verify_downgrade(&body)?;
+    let budget = render_budget(config);
+
+    // all entries are checked before the first item is created
+    let mut checked = vec![];
+    for (index, (requested_spec, data)) in requests.into_iter().enumerate() {
+        match verify_budget(requested_spec, &data, downgrade, &budget) {
+            Ok(spec) => checked.push((spec, data, spec != requested_spec)),
+            Err(e) => return Err(format!("batch entry {}: {}", index, e).into()),
+        }
+    }
+    // the generator renders all files of the batch in a single invocation, identical files only once
+    let mut costs: HashMap<String, RenderCost> = HashMap::new();
+    for (spec, data, _) in &checked {
+        costs.insert(create_content_hash(spec, data), RenderCost::new(spec, data));
+    }
+    budget.check_total(&costs.into_values().collect::<Vec<_>>())?;
+
+    // the ids are generated monotonically, so sorting them restores the order of the request
+    let mut id_generator = Generator::new();
+    let batch_id = id_generator.generate()?.to_string();
+    let created = CreationTime::from(Utc::now());
+    let expires_at = created.created_at_ms / 1000 + retention_days * SECONDS_PER_DAY;
+    info!("Creating {} items for batch {}, files will expire after {} days", checked.len(), batch_id, retention_days);
+
+    // content hashes of the files generated by this batch, which are not in the bucket yet
+    let mut generated: HashMap<String, String> = HashMap::new();
+    let mut to_generate = vec![];
+    let mut files = vec![];
+    let mut ids = vec![];
+    let mut downgraded = vec![];
+
+    for (spec, data, is_downgraded) in checked {
+        let id = id_generator.generate()?.to_string();
+        let content_hash = create_content_hash(&spec, &data);
+        let existing_key = match generated.get(&content_hash) {
+            Some(key) => Some(key.clone()),
+            None => find_existing_object(repository, store, &content_hash).await?,
+        };
+        let object_key = existing_key.clone().unwrap_or_else(|| id.clone() + FILE_EXTENSION);
+
+        if existing_key.is_none() {
+            generated.insert(content_hash.clone(), object_key.clone());
+            to_generate.push(json!({ "wav_id": id, "wav_spec": spec, "wav_data": data }));
+        }
+        if is_downgraded {
+            downgraded.push(id.clone());
+        }
+        ids.push(id.clone());
+        files.push(json!({ "id": id, "object_key": object_key, "wav_spec": spec, "wav_data": data }));
+
+        let mut item = WaveItem::new(&id, request_id, spec, data, created.clone(), expires_at, (&content_hash, &object_key));
+        item.batch_id = Some(batch_id.clone());
+        repository.put(item).await?;
+    }
+
+    info!("{} of {} files need to be generated", to_generate.len(), ids.len());
+    if !to_generate.is_empty() || bundle.is_some() {
+        let bundle = bundle.map(|format| json!({ "format": format.name(), "files": files }));
+        let lambda_payload = json!({ "batch_id": batch_id, "items": to_generate, "bundle": bundle });
+        debug!("Invoking lambda with:\n{:?}", lambda_payload);
+        generator.invoke(lambda_payload).await?;
+    }
+
+    let response = json!({ "batch_id": batch_id, "request_id": request_id, "ids": ids, "downgraded": downgraded });
+    info!("Response: {}", response);
+    Ok(response)
+}
+
+/// Returns the status of each file of a batch, and the key of the bundle once it is stored in the bucket.
+/// A batch is `complete` as soon as all of its files are stored, the bundle is created afterwards.
+/// Files the generator rejected are `failed` together with the reason, which also fails the batch.
+/// Files the cleaner removed are `deleted`, without looking them up in the bucket.
+async fn batch_status(batch_id: &str, repository: &dyn WaveRepository, store: &dyn WaveStore) -> Result<Value, Error> {
+    info!("Looking up status of batch {}", batch_id);
+    let files = repository.query_by_batch(batch_id).await?;
+    if files.is_empty() {
+        return Err(Box::new(InvalidRequestErr("no batch with this batch_id")));
+    }
+
+    let mut items = vec![];
+    let mut completed = 0;
+    let mut failed = 0;
+    let mut deleted = 0;
+    for file in &files {
+        if file.deleted_at.is_some() {
+            deleted += 1;
+            items.push(json!({ "id": file.id, "status": "deleted", "is_downloaded": file.is_downloaded }));
+            continue;
+        }
+        let is_stored = store.head(&file.object_key).await?.is_some();
+        let mut item = json!({ "id": file.id, "status": "in_progress", "is_downloaded": file.is_downloaded });
+        // the checksums and the errors are recorded on the item, which creates the file
+        let owner = match (is_stored, &file.error) {
+            (false, Some(_)) => None,
+            (false, None) if file.object_key == file.id.clone() + FILE_EXTENSION => None,
+            _ => repository.get_by_object_key(&file.object_key).await?,
+        };
+        if is_stored {
+            completed += 1;
+            item["status"] = json!("ready");
+            if let Some(owner) = owner {
+                item["sha256"] = json!(owner.sha256);
+                item["crc32c"] = json!(owner.crc32c);
+            }
+        } else if let Some(error) = file.error.clone().or_else(|| owner.and_then(|owner| owner.error)) {
+            failed += 1;
+            item["status"] = json!("failed");
+            item["error"] = json!(error);
+        }
+        items.push(item);
+    }
+
+    // the format of the bundle isn't stored, so each of them is looked up
+    let mut bundle = None;
+    for format in BundleFormat::ALL {
+        let key = format.key(batch_id);
+        if store.head(&key).await?.is_some() {
+            bundle = Some(key);
+        }
+    }
+    let status = if completed == files.len() {
+        "complete"
+    } else if completed + failed + deleted < files.len() {
+        "in_progress"
+    } else if failed > 0 {
+        "failed"
+    } else {
+        "deleted"
+    };
+
+    Ok(json!({
+        "batch_id": batch_id,
+        "status": status,
+        "completed": completed,
+        "failed": failed,
+        "deleted": deleted,
+        "total": files.len(),
+        "items": items,
+        "bundle": bundle,
+    }))
+}
+
+/// Creates a new, lexicographically sortable file id.
+/// The id is a ULID, which starts with the creation time in milliseconds, followed by 80 random bits.
+fn create_partition_key() -> String {
+    Ulid::new().to_string()
+}
+
+/// Creates a hash over the normalized spec and data of a request.
+/// Requests with the same hash result in an identical wave file.
+fn create_content_hash(spec: &WavSpec, data: &WavData) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(canonical_form(spec, data).as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Looks up items with the same content hash and returns the key of an object
+/// that belongs to one of them and is already stored in the bucket.
+/// Items whose file was deleted or rejected by the generator are skipped without looking them up,
+/// items whose file is still being generated are skipped, since their object might never appear.
+async fn find_existing_object(
+    repository: &dyn WaveRepository, 
+    store: &dyn WaveStore, 
+    content_hash: &str) 
+-> Result<Option<String>, Error> {
+    let mut object_keys: Vec<String> = repository
+        .query_by_content_hash(content_hash).await?
+        .into_iter()
+        .filter(|file| file.deleted_at.is_none() && file.error.is_none())
+        .map(|file| file.object_key)
+        .collect();
+    object_keys.sort();
+    object_keys.dedup();
+
+    for key in object_keys {
+        if store.head(&key).await?.is_some() {
+            return Ok(Some(key));
+        }
+    }
+
+    Ok(None)
+}
+
+fn verify_specs(body: &Value) -> Result<(WavSpec, WavData), InvalidRequestErr> {
+    let (data, spec): (WavData, WavSpec) = match (body.get("wav_data"), body.get("wav_spec")) {
+        (Some(data), Some(spec)) => match (serde_json::from_value(data.clone()) , serde_json::from_value(spec.clone())) {
+            (Ok(data), Ok(spec)) => (data, spec),
+            (_, _) => return Err(InvalidRequestErr("data or spec invalid format")),
+        },
+        (_, _) => return Err(InvalidRequestErr("data or spec not found in request")),
+    };
+
+    if !(data.is_valid() && spec.is_valid()) {
+        return Err(InvalidRequestErr("data or spec contain invalid data"));
+    }
+
+    Ok((spec, data))
+}
+
+/// Verifies each entry of the `batch` field like a single request.
+fn verify_batch(body: &Value) -> Result<Vec<(WavSpec, WavData)>, InvalidRequestErr> {
+    let batch = body["batch"].as_array().ok_or(InvalidRequestErr("batch invalid format"))?;
+    if batch.is_empty() || batch.len() > MAX_BATCH_SIZE {
+        return Err(InvalidRequestErr("batch needs to contain between 1 and 100 entries"));
+    }
+
+    batch.iter().map(verify_specs).collect()
+}
+
+/// Reads the optional `downgrade` field, which allows to lower the sample rate of requests exceeding the render budget.
+fn verify_downgrade(body: &Value) -> Result<bool, InvalidRequestErr> {
+    match body.get("downgrade") {
+        Some(Value::Bool(downgrade)) => Ok(*downgrade),
+        None => Ok(false),
+        Some(_) => Err(InvalidRequestErr("downgrade invalid format")),
+    }
+}
+
+/// Returns the spec the file is rendered with, which is only changed if `downgrade` is set.
+fn verify_budget(spec: WavSpec, data: &WavData, downgrade: bool, budget: &RenderBudget) -> Result<WavSpec, BudgetErr> {
+    if downgrade {
+        budget.downgrade(&spec, data)
+    } else {
+        budget.check(&spec, data).map(|_| spec)
+    }
+}
+
+/// Reads the optional `bundle` field of a batch request, which is either the name of an archive format or a bool.
+/// `true` requests a zip archive.
+fn verify_bundle(body: &Value) -> Result<Option<BundleFormat>, InvalidRequestErr> {
+    match body.get("bundle") {
+        Some(Value::Bool(true)) => Ok(Some(BundleFormat::Zip)),
+        Some(Value::Bool(false)) | None => Ok(None),
+        Some(Value::String(format)) => format.parse().map(Some).map_err(|_| InvalidRequestErr("unknown bundle format")),
+        Some(_) => Err(InvalidRequestErr("bundle invalid format")),
+    }
+}
+
+/// Reads the optional `tier` and `retention_days` fields of a request 
+/// and returns the number of days the file should be kept.
+/// The requested days are limited by the maximum of the tier and the server limit.
+fn verify_retention(body: &Value, server_max_days: i64) -> Result<i64, InvalidRequestErr> {
+    let tier = match body.get("tier") {
+        Some(Value::String(tier)) => tier.parse()?,
+        Some(_) => return Err(InvalidRequestErr("tier invalid format")),
+        None => RetentionTier::Basic,
+    };
+
+    let days = match body.get("retention_days") {
+        Some(days) => days.as_i64().filter(|days| *days > 0).ok_or(InvalidRequestErr("retention_days invalid format"))?,
+        None => tier.default_days(),
+    };
+
+    Ok(days.min(tier.max_days()).min(server_max_days))
+}
+
+#[test]
+fn test_create_db_item() {
+
+    let request = json!({
+        "wav_data": {
+            "duration": 30 as u16,
+            "frequencies": [440 as u16, 660 as u16],
+            "volume": 0.9 as f64,
+        },
+        "wav_spec": {
+            "bits_per_sample": 8 as u16,
+            "number_of_channels": 2 as u16,
+            "sample_rate": 8000 as u16,
+        }
+    });
+
+    let (spec, data) = verify_specs(&request).unwrap();
+    let context = lambda_runtime::Context::default();
+
+    use chrono::TimeZone;
+
+    let created = CreationTime::from(Utc.ymd(2022, 2, 4).and_hms(12, 12, 12));
+    let item = WaveItem::new("123", &context.request_id, spec, data, created, 1643976732, ("abc", "123.wav"));
+    println!("{:?}", item);
+}
+
+#[test]
+fn test_create_content_hash() {
+    let spec = WavSpec{ number_of_channels: 2, bits_per_sample: 16, sample_rate: 44100};
+    let data = WavData{ frequencies: vec![440, 660], duration: 10, volume: 0.9 };
+    let reordered = WavData{ frequencies: vec![660, 440], duration: 10, volume: 0.9 };
+    let louder = WavData{ frequencies: vec![440, 660], duration: 10, volume: 1. };
+
+    assert_eq!(create_content_hash(&spec, &data), create_content_hash(&spec, &reordered));
+    assert_ne!(create_content_hash(&spec, &data), create_content_hash(&spec, &louder));
+    assert_eq!(create_content_hash(&spec, &data).len(), 64);
+}
+
+
+#[test]
+fn test_create_partition_key() {
+    let first = create_partition_key();
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    let second = create_partition_key();
+
+    assert_eq!(first.len(), 26);
+    assert!(first < second);
+    assert!(Ulid::from_string(&first).is_ok());
+    assert!(sine_generator::data_formats::parse_legacy_id(&first).is_none());
+}
+
+#[test]
+fn test_verify_retention() {
+    assert_eq!(verify_retention(&json!({}), 30).unwrap(), 2);
+    assert_eq!(verify_retention(&json!({"retention_days": 1}), 30).unwrap(), 1);
+    assert_eq!(verify_retention(&json!({"retention_days": 5}), 30).unwrap(), 2);
+    assert_eq!(verify_retention(&json!({"tier": "extended"}), 30).unwrap(), 7);
+    assert_eq!(verify_retention(&json!({"tier": "extended", "retention_days": 14}), 30).unwrap(), 14);
+    assert_eq!(verify_retention(&json!({"tier": "extended", "retention_days": 60}), 30).unwrap(), 30);
+    assert_eq!(verify_retention(&json!({"tier": "extended", "retention_days": 14}), 10).unwrap(), 10);
+
+    assert!(verify_retention(&json!({"tier": "premium"}), 30).is_err());
+    assert!(verify_retention(&json!({"tier": 1}), 30).is_err());
+    assert!(verify_retention(&json!({"retention_days": 0}), 30).is_err());
+    assert!(verify_retention(&json!({"retention_days": "2"}), 30).is_err());
+}
+
+#[test]
+fn test_verify_batch() {
+    let entry = json!({
+        "wav_spec": { "number_of_channels": 1, "sample_rate": 8000, "bits_per_sample": 8 },
+        "wav_data": { "frequencies": [440], "duration": 1, "volume": 1 },
+    });
+
+    assert_eq!(verify_batch(&json!({ "batch": [entry, entry] })).unwrap().len(), 2);
+    assert!(verify_batch(&json!({ "batch": [] })).is_err());
+    assert!(verify_batch(&json!({ "batch": vec![&entry; MAX_BATCH_SIZE + 1] })).is_err());
+    assert!(verify_batch(&json!({ "batch": [entry, { "wav_spec": entry["wav_spec"] }] })).is_err());
+    assert!(verify_batch(&json!({ "batch": entry })).is_err());
+
+    assert_eq!(verify_bundle(&json!({ "bundle": true })).unwrap(), Some(BundleFormat::Zip));
+    assert_eq!(verify_bundle(&json!({ "bundle": "tar.gz" })).unwrap(), Some(BundleFormat::TarGz));
+    assert_eq!(verify_bundle(&json!({ "bundle": false })).unwrap(), None);
+    assert_eq!(verify_bundle(&json!({})).unwrap(), None);
+    assert!(verify_bundle(&json!({ "bundle": "rar" })).is_err());
+    assert!(verify_bundle(&json!({ "bundle": 1 })).is_err());
+}
+
+#[test]
+fn test_verify_budget() {
+    let budget = RenderBudget { max_operations: 1_000_000, max_file_size: u64::MAX };
+    let spec = WavSpec { number_of_channels: 1, sample_rate: 44100, bits_per_sample: 16 };
+    let data = WavData { frequencies: vec![440, 660], duration: 20, volume: 1. };
+
+    assert!(verify_budget(spec, &data, false, &budget).is_err());
+    assert_eq!(verify_budget(spec, &data, true, &budget).unwrap().sample_rate, 22050);
+    assert_eq!(verify_budget(spec, &WavData { duration: 1, ..data.clone() }, false, &budget).unwrap(), spec);
+
+    assert!(verify_downgrade(&json!({ "downgrade": true })).unwrap());
+    assert!(!verify_downgrade(&json!({})).unwrap());
+    assert!(verify_downgrade(&json!({ "downgrade": "yes" })).is_err());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use wave_store::InMemoryStore;
+    use wave_table::InMemoryRepository;
+
+    /// Records the payloads instead of generating the files.
+    #[derive(Default)]
+    struct RecordingGenerator {
+        payloads: Mutex<Vec<Value>>,
+    }
+
+    #[async_trait]
+    impl GeneratorClient for RecordingGenerator {
+        async fn invoke(&self, payload: Value) -> Result<(), Error> {
+            self.payloads.lock().unwrap().push(payload);
+            Ok(())
+        }
+    }
+
+    fn batch_request(frequencies: &[u16], bundle: Value) -> Value {
+        let batch: Vec<Value> = frequencies.iter().map(|frequency| json!({
+            "wav_spec": { "number_of_channels": 1, "sample_rate": 8000, "bits_per_sample": 8 },
+            "wav_data": { "frequencies": [frequency], "duration": 1, "volume": 1 },
+        })).collect();
+        json!({ "batch": batch, "bundle": bundle })
+    }
+
+    #[tokio::test]
+    async fn test_handle_batch() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let generator = RecordingGenerator::default();
+        let config = Config::from_lookup(|_| None).unwrap();
+
+        let request = batch_request(&[440, 660, 440], json!("tar.gz"));
+        let response = handle_request(request, "request", &repository, &store, &generator, &config).await.unwrap();
+        let batch_id = response["batch_id"].as_str().unwrap();
+        let ids: Vec<&str> = response["ids"].as_array().unwrap().iter().map(|id| id.as_str().unwrap()).collect();
+        assert_eq!(ids.len(), 3);
+        assert!(batch_id < ids[0] && ids[0] < ids[1] && ids[1] < ids[2]);
+
+        let items = repository.items();
+        assert!(items.iter().all(|item| item.batch_id.as_deref() == Some(batch_id)));
+        // the identical third file is only generated once
+        assert_eq!(items[2].object_key(), items[0].object_key());
+
+        let payloads = generator.payloads.lock().unwrap();
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0]["items"].as_array().unwrap().len(), 2);
+        assert_eq!(payloads[0]["bundle"]["format"], "tar.gz");
+        assert_eq!(payloads[0]["bundle"]["files"].as_array().unwrap().len(), 3);
+        assert_eq!(payloads[0]["bundle"]["files"][2]["object_key"], format!("{}.wav", ids[0]));
+        assert_eq!(payloads[0]["bundle"]["files"][2]["wav_data"]["frequencies"], json!([440]));
+    }
+
+    #[tokio::test]
+    async fn test_batch_status() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let generator = RecordingGenerator::default();
+        let config = Config::from_lookup(|_| None).unwrap();
+
+        let response = handle_request(batch_request(&[440, 660], json!(false)), "request", &repository, &store, &generator, &config).await.unwrap();
+        let batch_id = response["batch_id"].as_str().unwrap();
+        let status_request = || json!({ "batch_id": batch_id });
+        assert_eq!(generator.payloads.lock().unwrap()[0]["bundle"], Value::Null);
+
+        let status = handle_request(status_request(), "other", &repository, &store, &generator, &config).await.unwrap();
+        assert_eq!((status["status"].as_str(), status["completed"].as_u64()), (Some("in_progress"), Some(0)));
+
+        // the generator records the checksums on the item before storing the file
+        let first_id = response["ids"][0].as_str().unwrap();
+        let mut item = repository.get(first_id).await.unwrap().unwrap();
+        item.sha256 = Some("abc".to_owned());
+        item.crc32c = Some("AAAAAA==".to_owned());
+        repository.put(item).await.unwrap();
+        store.put(&format!("{}.wav", first_id), vec![0]).await.unwrap();
+        let status = handle_request(status_request(), "other", &repository, &store, &generator, &config).await.unwrap();
+        assert_eq!((status["status"].as_str(), status["completed"].as_u64()), (Some("in_progress"), Some(1)));
+        assert_eq!(status["items"][0]["status"], "ready");
+        assert_eq!((status["items"][0]["sha256"].as_str(), status["items"][0]["crc32c"].as_str()), (Some("abc"), Some("AAAAAA==")));
+        assert_eq!(status["items"][1].get("sha256"), None);
+
+        store.put(&format!("{}.wav", response["ids"][1].as_str().unwrap()), vec![0]).await.unwrap();
+        let status = handle_request(status_request(), "other", &repository, &store, &generator, &config).await.unwrap();
+        assert_eq!(status["status"], "complete");
+        assert_eq!(status["bundle"], Value::Null);
+
+        store.put(&format!("{}.tar.gz", batch_id), vec![0]).await.unwrap();
+        let status = handle_request(status_request(), "other", &repository, &store, &generator, &config).await.unwrap();
+        assert_eq!(status["bundle"], format!("{}.tar.gz", batch_id));
+
+        // files removed by the cleaner are deleted, also before they are gone from the bucket
+        repository.mark_deleted(first_id, 1).await.unwrap();
+        let status = handle_request(status_request(), "other", &repository, &store, &generator, &config).await.unwrap();
+        assert_eq!((status["status"].as_str(), status["deleted"].as_u64()), (Some("deleted"), Some(1)));
+        assert_eq!(status["items"][0]["status"], "deleted");
+        assert_eq!(status["items"][1]["status"], "ready");
+
+        assert!(handle_request(json!({ "batch_id": "unknown" }), "other", &repository, &store, &generator, &config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_skip_deleted_files() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let generator = RecordingGenerator::default();
+        let config = Config::from_lookup(|_| None).unwrap();
+        let request = || json!({
+            "wav_spec": { "number_of_channels": 1, "sample_rate": 8000, "bits_per_sample": 8 },
+            "wav_data": { "frequencies": [440], "duration": 1, "volume": 1 },
+        });
+
+        let response = handle_request(request(), "request", &repository, &store, &generator, &config).await.unwrap();
+        store.put(&format!("{}.wav", response["id"].as_str().unwrap()), vec![0]).await.unwrap();
+        handle_request(request(), "other", &repository, &store, &generator, &config).await.unwrap();
+        assert_eq!(generator.payloads.lock().unwrap().len(), 1);
+
+        // files marked as deleted are generated again, even if they are still in the bucket
+        for item in repository.items() {
+            repository.mark_deleted(&item.id, 1).await.unwrap();
+        }
+        handle_request(request(), "other", &repository, &store, &generator, &config).await.unwrap();
+        assert_eq!(generator.payloads.lock().unwrap().len(), 2);
+    }
+
+    /// Allows 10 seconds with a single frequency at 8 kHz.
+    fn small_budget_config() -> Config {
+        Config::from_lookup(|name| (name == cloud_config::MAX_RENDER_OPERATIONS).then(|| "80000".to_owned())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reject_over_budget() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let generator = RecordingGenerator::default();
+        let config = small_budget_config();
+
+        let mut request = batch_request(&[440], json!(false))["batch"][0].clone();
+        request["wav_data"]["duration"] = json!(20);
+        let error = handle_request(request.clone(), "request", &repository, &store, &generator, &config).await.unwrap_err();
+        assert!(error.to_string().contains("160000 operations"));
+
+        let mut batch = batch_request(&[440, 660], json!(false));
+        batch["batch"][1] = request.clone();
+        let error = handle_request(batch, "request", &repository, &store, &generator, &config).await.unwrap_err();
+        assert!(error.to_string().starts_with("batch entry 1:"));
+
+        // 8 kHz is the lowest rate, so the request can't be downgraded
+        request["downgrade"] = json!(true);
+        assert!(handle_request(request, "request", &repository, &store, &generator, &config).await.is_err());
+
+        assert!(repository.items().is_empty());
+        assert!(generator.payloads.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reject_batch_over_budget() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let generator = RecordingGenerator::default();
+        let config = small_budget_config();
+        let batch = |frequencies: &[u16]| {
+            let mut batch = batch_request(frequencies, json!(false));
+            for entry in batch["batch"].as_array_mut().unwrap() {
+                entry["wav_data"]["duration"] = json!(4);
+            }
+            batch
+        };
+
+        // each file fits into the budget, but not all three together
+        let error = handle_request(batch(&[440, 660, 880]), "request", &repository, &store, &generator, &config).await.unwrap_err();
+        assert!(error.to_string().contains("96000 operations in total"));
+        assert!(repository.items().is_empty());
+
+        // identical files are only rendered once
+        handle_request(batch(&[440, 440, 440]), "request", &repository, &store, &generator, &config).await.unwrap();
+        assert_eq!(generator.payloads.lock().unwrap()[0]["items"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_downgrade() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let generator = RecordingGenerator::default();
+        let config = small_budget_config();
+
+        let mut request = batch_request(&[440], json!(false))["batch"][0].clone();
+        request["wav_spec"]["sample_rate"] = json!(44100);
+        // leaves room for a second file in the batch below
+        request["wav_data"]["duration"] = json!(6);
+        request["downgrade"] = json!(true);
+        let response = handle_request(request.clone(), "request", &repository, &store, &generator, &config).await.unwrap();
+        assert_eq!(response["wav_spec"]["sample_rate"], 11025);
+        assert_eq!(generator.payloads.lock().unwrap()[0]["wav_spec"]["sample_rate"], 11025);
+        let item = repository.get(response["id"].as_str().unwrap()).await.unwrap().unwrap();
+        assert_eq!(item.wav_spec.sample_rate, 11025);
+
+        let batch = json!({ "batch": [request, batch_request(&[440], json!(false))["batch"][0]], "downgrade": true });
+        let response = handle_request(batch, "request", &repository, &store, &generator, &config).await.unwrap();
+        assert_eq!(response["downgraded"], json!([response["ids"][0]]));
+    }
+
+    #[tokio::test]
+    async fn test_batch_status_with_rejected_file() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let generator = RecordingGenerator::default();
+        let config = Config::from_lookup(|_| None).unwrap();
+
+        let response = handle_request(batch_request(&[440, 660, 440], json!(false)), "request", &repository, &store, &generator, &config).await.unwrap();
+        let batch_id = response["batch_id"].as_str().unwrap();
+        let status_request = || json!({ "batch_id": batch_id });
+
+        // the generator records the reason on the item instead of rendering the file
+        let mut item = repository.get(response["ids"][0].as_str().unwrap()).await.unwrap().unwrap();
+        item.error = Some("over budget".to_owned());
+        repository.put(item).await.unwrap();
+        let status = handle_request(status_request(), "other", &repository, &store, &generator, &config).await.unwrap();
+        assert_eq!(status["status"], "in_progress");
+        assert_eq!(status["failed"], 2);
+        assert_eq!((status["items"][0]["status"].as_str(), status["items"][0]["error"].as_str()), (Some("failed"), Some("over budget")));
+        // the identical third file points to the rejected one
+        assert_eq!(status["items"][2]["status"], "failed");
+        assert_eq!(status["items"][1]["status"], "in_progress");
+
+        store.put(&format!("{}.wav", response["ids"][1].as_str().unwrap()), vec![0]).await.unwrap();
+        let status = handle_request(status_request(), "other", &repository, &store, &generator, &config).await.unwrap();
+        assert_eq!((status["status"].as_str(), status["completed"].as_u64()), (Some("failed"), Some(1)));
+    }
+}
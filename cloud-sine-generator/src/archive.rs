@@ -0,0 +1,321 @@
+//! Archives, which are written entry by entry into a buffer that can be drained after each entry.
+//! Neither format needs to seek back, so an archive can be uploaded while it is written.
+
+use std::io::{self, Write};
+
+use flate2::{write::{DeflateEncoder, GzEncoder}, Compression};
+use wave_store::BundleFormat;
+
+/// Writes the entries of an archive into an internal buffer.
+pub trait ArchiveWriter: Send {
+    /// Appends a file to the archive.
+    fn append(&mut self, name: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Returns the bytes written since the last call, which can be uploaded before appending the next file.
+    fn take_output(&mut self) -> Vec<u8>;
+
+    /// Writes the end of the archive and returns the remaining bytes.
+    fn finish(self: Box<Self>) -> io::Result<Vec<u8>>;
+}
+
+/// Creates an empty archive of the given format.
+pub fn writer(format: BundleFormat) -> Box<dyn ArchiveWriter> {
+    match format {
+        BundleFormat::Zip => Box::new(ZipStream::new()),
+        BundleFormat::TarGz => Box::new(TarGzStream::new()),
+    }
+}
+
+/// Sizes and offsets above this limit are stored in the zip64 extra field.
+const ZIP64_LIMIT: u64 = u32::MAX as u64;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const ZIP64_END_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const END_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// Bit 11 of the flags marks the names as UTF-8.
+const FLAG_UTF8: u16 = 1 << 11;
+const METHOD_DEFLATE: u16 = 8;
+/// 1980-01-01 00:00 in the MS-DOS format, the files don't have a meaningful modification time.
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+/// An entry of the central directory, which is written at the end of the archive.
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    offset: u64,
+}
+
+/// A zip archive with deflated entries.
+///
+/// Each file is compressed before its local header is written, so the header already contains
+/// the sizes and the checksum and no data descriptor is needed. Archives larger than 4 GiB use zip64.
+struct ZipStream {
+    output: Vec<u8>,
+    /// Number of bytes written so far, including the bytes already taken from the buffer
+    offset: u64,
+    entries: Vec<CentralEntry>,
+    zip64_limit: u64,
+}
+
+impl ZipStream {
+    fn new() -> Self {
+        ZipStream { output: vec![], offset: 0, entries: vec![], zip64_limit: ZIP64_LIMIT }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.output.extend_from_slice(bytes);
+        self.offset += bytes.len() as u64;
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    /// Returns the value for a 32 bit field, or the marker which points to the zip64 extra field.
+    fn field_u32(&self, value: u64) -> u32 {
+        if value > self.zip64_limit { u32::MAX } else { value as u32 }
+    }
+
+    fn write_central_directory(&mut self) {
+        let start = self.offset;
+        let entries = std::mem::take(&mut self.entries);
+
+        for entry in &entries {
+            // the extra field only contains the values, which don't fit into their regular field
+            let zip64_values: Vec<u64> = [entry.size, entry.compressed_size, entry.offset]
+                .into_iter()
+                .filter(|value| *value > self.zip64_limit)
+                .collect();
+            let version = if zip64_values.is_empty() { VERSION_DEFAULT } else { VERSION_ZIP64 };
+            let extra_len = if zip64_values.is_empty() { 0 } else { 4 + 8 * zip64_values.len() as u16 };
+
+            self.write_u32(CENTRAL_HEADER_SIGNATURE);
+            self.write_u16(VERSION_ZIP64);
+            self.write_u16(version);
+            self.write_u16(FLAG_UTF8);
+            self.write_u16(METHOD_DEFLATE);
+            self.write_u16(DOS_TIME);
+            self.write_u16(DOS_DATE);
+            self.write_u32(entry.crc);
+            self.write_u32(self.field_u32(entry.compressed_size));
+            self.write_u32(self.field_u32(entry.size));
+            self.write_u16(entry.name.len() as u16);
+            self.write_u16(extra_len);
+            self.write_u16(0);  // comment length
+            self.write_u16(0);  // disk number
+            self.write_u16(0);  // internal attributes
+            self.write_u32(0);  // external attributes
+            self.write_u32(self.field_u32(entry.offset));
+            self.write(entry.name.as_bytes());
+            if !zip64_values.is_empty() {
+                self.write_u16(ZIP64_EXTRA_ID);
+                self.write_u16(8 * zip64_values.len() as u16);
+                for value in zip64_values {
+                    self.write_u64(value);
+                }
+            }
+        }
+
+        let size = self.offset - start;
+        let count = entries.len() as u64;
+        let needs_zip64 = count >= u16::MAX as u64 || size > self.zip64_limit || start > self.zip64_limit;
+
+        if needs_zip64 {
+            let zip64_end = self.offset;
+            self.write_u32(ZIP64_END_SIGNATURE);
+            self.write_u64(44);  // size of the remaining record
+            self.write_u16(VERSION_ZIP64);
+            self.write_u16(VERSION_ZIP64);
+            self.write_u32(0);  // number of this disk
+            self.write_u32(0);  // disk of the central directory
+            self.write_u64(count);
+            self.write_u64(count);
+            self.write_u64(size);
+            self.write_u64(start);
+
+            self.write_u32(ZIP64_LOCATOR_SIGNATURE);
+            self.write_u32(0);  // disk of the zip64 end record
+            self.write_u64(zip64_end);
+            self.write_u32(1);  // total number of disks
+        }
+
+        let count = if needs_zip64 { u16::MAX } else { count as u16 };
+        self.write_u32(END_SIGNATURE);
+        self.write_u16(0);  // number of this disk
+        self.write_u16(0);  // disk of the central directory
+        self.write_u16(count);
+        self.write_u16(count);
+        self.write_u32(if needs_zip64 { u32::MAX } else { size as u32 });
+        self.write_u32(if needs_zip64 { u32::MAX } else { start as u32 });
+        self.write_u16(0);  // comment length
+    }
+}
+
+impl ArchiveWriter for ZipStream {
+    fn append(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+
+        let entry = CentralEntry {
+            name: name.to_owned(),
+            crc: crc32fast::hash(data),
+            compressed_size: compressed.len() as u64,
+            size: data.len() as u64,
+            offset: self.offset,
+        };
+        let is_zip64 = entry.size > self.zip64_limit || entry.compressed_size > self.zip64_limit;
+
+        self.write_u32(LOCAL_HEADER_SIGNATURE);
+        self.write_u16(if is_zip64 { VERSION_ZIP64 } else { VERSION_DEFAULT });
+        self.write_u16(FLAG_UTF8);
+        self.write_u16(METHOD_DEFLATE);
+        self.write_u16(DOS_TIME);
+        self.write_u16(DOS_DATE);
+        self.write_u32(entry.crc);
+        // a local zip64 extra field always contains both sizes
+        self.write_u32(if is_zip64 { u32::MAX } else { entry.compressed_size as u32 });
+        self.write_u32(if is_zip64 { u32::MAX } else { entry.size as u32 });
+        self.write_u16(name.len() as u16);
+        self.write_u16(if is_zip64 { 20 } else { 0 });
+        self.write(name.as_bytes());
+        if is_zip64 {
+            self.write_u16(ZIP64_EXTRA_ID);
+            self.write_u16(16);
+            self.write_u64(entry.size);
+            self.write_u64(entry.compressed_size);
+        }
+        self.write(&compressed);
+
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<Vec<u8>> {
+        self.write_central_directory();
+        Ok(self.output)
+    }
+}
+
+/// A gzip compressed tar archive.
+struct TarGzStream {
+    builder: tar::Builder<GzEncoder<Vec<u8>>>,
+}
+
+impl TarGzStream {
+    fn new() -> Self {
+        TarGzStream { builder: tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default())) }
+    }
+}
+
+impl ArchiveWriter for TarGzStream {
+    fn append(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        self.builder.append_data(&mut header, name, data)
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        // the encoder keeps its state, only the compressed bytes written so far are taken
+        std::mem::take(self.builder.get_mut().get_mut())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<Vec<u8>> {
+        self.builder.into_inner()?.finish()
+    }
+}
+
+#[cfg(test)]
+fn read_all<R: io::Read>(mut reader: R) -> Vec<u8> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data).unwrap();
+    data
+}
+
+/// Appends the files to an archive, draining the output after each file like an upload would.
+#[cfg(test)]
+fn write_archive(mut archive: Box<dyn ArchiveWriter>, files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut output = vec![];
+    for (name, data) in files {
+        archive.append(name, data).unwrap();
+        output.extend(archive.take_output());
+    }
+    output.extend(archive.finish().unwrap());
+    output
+}
+
+#[cfg(test)]
+fn test_files() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("a.wav", (0..100_000).map(|i| (i % 251) as u8).collect()),
+        ("empty.wav", vec![]),
+        ("manifest.json", b"{}".to_vec()),
+    ]
+}
+
+#[test]
+fn test_zip_stream() {
+    let files = test_files();
+    let output = write_archive(writer(BundleFormat::Zip), &files);
+
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(output)).unwrap();
+    assert_eq!(archive.len(), files.len());
+    for (name, data) in &files {
+        assert_eq!(&read_all(archive.by_name(name).unwrap()), data);
+    }
+}
+
+#[test]
+fn test_zip64_stream() {
+    // lowering the limit writes the zip64 fields, without creating an archive larger than 4 GiB
+    let mut stream = ZipStream::new();
+    stream.zip64_limit = 10;
+    let files = test_files();
+    let output = write_archive(Box::new(stream), &files);
+
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(output)).unwrap();
+    assert_eq!(archive.len(), files.len());
+    for (name, data) in &files {
+        assert_eq!(&read_all(archive.by_name(name).unwrap()), data);
+    }
+}
+
+#[test]
+fn test_tar_gz_stream() {
+    let files = test_files();
+    let output = write_archive(writer(BundleFormat::TarGz), &files);
+
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(io::Cursor::new(output)));
+    let entries: Vec<(String, Vec<u8>)> = archive
+        .entries().unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.path().unwrap().to_str().unwrap().to_owned(), read_all(entry))
+        })
+        .collect();
+    let expected: Vec<(String, Vec<u8>)> = files.into_iter().map(|(name, data)| (name.to_owned(), data)).collect();
+    assert_eq!(entries, expected);
+}
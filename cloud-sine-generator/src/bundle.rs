@@ -0,0 +1,103 @@
+use std::fmt::Display;
+
+use lambda_runtime::Error;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sine_generator::data_formats::{WavData, WavSpec};
+use tracing::{info, error};
+use wave_store::{BundleFormat, ObjectUpload, WaveStore};
+
+use crate::archive::{self, ArchiveWriter};
+
+/// Name of the file in the bundle, which describes all other files.
+const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Debug)]
+struct BundleErr(String);
+
+impl Display for BundleErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for BundleErr {}
+
+/// The bundle requested for a batch, containing all of its files.
+#[derive(Debug, Deserialize)]
+pub struct BundleRequest {
+    pub format: String,
+    pub files: Vec<BundleEntry>,
+}
+
+/// A file, which is put into the bundle of a batch.
+/// Files of deduplicated requests are stored under the key of another file.
+#[derive(Debug, Deserialize)]
+pub struct BundleEntry {
+    pub id: String,
+    pub object_key: String,
+    pub wav_spec: WavSpec,
+    pub wav_data: WavData,
+}
+
+/// Puts all files of a batch together with a `manifest.json` into an archive, which is stored under the id of the batch.
+/// Each file is named after its id, in the order of the request. Returns the key of the archive.
+///
+/// The archive is uploaded in parts while it is written, so only a single file of the batch is held in memory at a time
+/// and nothing is written to the tmp folder.
+pub async fn create_bundle(batch_id: &str, request: &BundleRequest, store: &dyn WaveStore) -> Result<String, Error> {
+    let format: BundleFormat = request.format.parse()?;
+    let key = format.key(batch_id);
+    info!("Creating bundle {} of {} files", key, request.files.len());
+
+    let mut upload = store.start_upload(&key).await?;
+    match write_bundle(batch_id, archive::writer(format), &request.files, store, upload.as_mut()).await {
+        Ok(()) => upload.complete().await?,
+        Err(e) => {
+            if let Err(abort_err) = upload.abort().await {
+                error!("Unable to abort upload of bundle {}: {}", key, abort_err);
+            }
+            return Err(e);
+        },
+    }
+
+    info!("Stored bundle {}", key);
+    Ok(key)
+}
+
+async fn write_bundle(
+    batch_id: &str,
+    mut archive: Box<dyn ArchiveWriter>,
+    entries: &[BundleEntry],
+    store: &dyn WaveStore,
+    upload: &mut dyn ObjectUpload)
+-> Result<(), Error> {
+    let mut manifest = vec![];
+
+    for entry in entries {
+        let file = store
+            .get(&entry.object_key).await?
+            .ok_or_else(|| BundleErr(format!("File {} of bundle is not in the bucket", entry.object_key)))?;
+
+        let name = format!("{}.wav", entry.id);
+        manifest.push(json!({
+            "name": name,
+            "id": entry.id,
+            "wav_spec": entry.wav_spec,
+            "wav_data": entry.wav_data,
+            "duration": entry.wav_data.duration,
+            "size": file.len(),
+            "sha256": format!("{:x}", Sha256::digest(&file)),
+        }));
+
+        archive.append(&name, &file)?;
+        upload.write(&archive.take_output()).await?;
+    }
+
+    // the manifest is written last, since it contains the checksums of all files
+    let manifest = serde_json::to_vec_pretty(&json!({ "batch_id": batch_id, "files": manifest }))?;
+    archive.append(MANIFEST_NAME, &manifest)?;
+    upload.write(&archive.finish()?).await?;
+    Ok(())
+}
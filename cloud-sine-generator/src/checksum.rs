@@ -0,0 +1,53 @@
+use std::io::{self, Write};
+
+use sha2::{Digest, Sha256};
+use wave_store::Checksums;
+
+/// Passes all bytes through to the inner writer, while computing the checksums of everything written.
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    sha256: Sha256,
+    crc32c: u32,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChecksumWriter { inner, sha256: Sha256::new(), crc32c: 0 }
+    }
+
+    /// Flushes the inner writer and returns it together with the checksums.
+    pub fn finish(mut self) -> io::Result<(W, Checksums)> {
+        self.inner.flush()?;
+        let checksums = Checksums {
+            sha256: format!("{:x}", self.sha256.finalize()),
+            crc32c: base64::encode(self.crc32c.to_be_bytes()),
+        };
+        Ok((self.inner, checksums))
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // only the bytes accepted by the inner writer are part of the output
+        let written = self.inner.write(buf)?;
+        self.sha256.update(&buf[..written]);
+        self.crc32c = crc32c::crc32c_append(self.crc32c, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn test_checksum_writer() {
+    let mut writer = ChecksumWriter::new(vec![]);
+    writer.write_all(b"123456789").unwrap();
+    let (output, checksums) = writer.finish().unwrap();
+
+    assert_eq!(output, b"123456789");
+    assert_eq!(checksums.sha256, "15e2b0d3c33891ebb0f1ef609ec419420c20e320ce94c65fbc8c3312448eb225");
+    // the check value of CRC32C is 0xe3069283
+    assert_eq!(checksums.crc32c, base64::encode(0xe3069283_u32.to_be_bytes()));
+}
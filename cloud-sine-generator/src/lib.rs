@@ -0,0 +1,562 @@
+//! The logic of the sine generator lambda, which renders the requested wave files.
+
+mod archive;
+mod bundle;
+mod checksum;
+mod render;
+
+use std::fmt::Display;
+
+use lambda_runtime::Error;
+use tracing::{info, error};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sine_generator::{cost::{BudgetErr, RenderBudget}, data_formats::{WavSpec, WavData}};
+use wave_store::{Checksums, WaveStore};
+use wave_table::{WaveRepository, FILE_EXTENSION};
+
+use bundle::BundleRequest;
+use render::RenderLimits;
+
+#[derive(Debug)]
+struct WavSpecErr(&'static str);
+
+impl WavSpecErr {
+    fn new_default() -> Self {
+        WavSpecErr("Error creating WavSpec, got invalid values/fields") 
+    }
+}
+
+impl Display for WavSpecErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f,"{}", self.0)
+    }
+}
+
+impl std::error::Error for WavSpecErr {}
+
+/// A file of a batch, which needs to be generated.
+#[derive(Debug, Deserialize)]
+struct BatchItem {
+    wav_id: String,
+    wav_spec: WavSpec,
+    wav_data: WavData,
+}
+
+/// The event sent by the main lambda for a batch request.
+/// `bundle` lists all files of the batch, if they should be put into a bundle after generating them.
+#[derive(Debug, Deserialize)]
+struct BatchEvent {
+    batch_id: String,
+    items: Vec<BatchItem>,
+    bundle: Option<BundleRequest>,
+}
+
+/// Renders the wave file described by the event and stores it under the id of the event.
+/// The checksums of the file are recorded on the item with the same id.
+/// Events containing a `batch_id` are handled by `handle_batch`.
+///
+/// The main lambda only sends files within the budget, but the generator can also be invoked directly,
+/// so each file is checked again. A file exceeding the budget isn't rendered, the reason is recorded on its item instead.
+pub async fn handle_event(
+    mut event: Value,
+    budget: &RenderBudget,
+    repository: &dyn WaveRepository,
+    store: &dyn WaveStore)
+-> Result<Value, Error> {
+    if event.get("batch_id").is_some() {
+        return handle_batch(serde_json::from_value(event)?, budget, &RenderLimits::default(), repository, store).await;
+    }
+
+    // TODO refactor into function maybe
+    info!("Looking for WavSpec in event");
+    let wav_spec: WavSpec = serde_json::from_value(
+        event
+            .get_mut("wav_spec")
+            .ok_or(WavSpecErr("WavSpec field missing"))?
+            .take())?;
+    
+    info!("Looking for WavData in event");
+    let wav_data: WavData = serde_json::from_value(
+        event
+            .get_mut("wav_data")
+            .ok_or(WavSpecErr("WavData field missing"))?
+            .take())?;
+
+    let id: String = serde_json::from_value(
+        event
+            .get_mut("wav_id")
+            .ok_or(WavSpecErr("Id field missing"))?
+            .take())?;
+
+    if let Err(e) = budget.check(&wav_spec, &wav_data) {
+        reject(&id, &e, repository).await?;
+        return Err(Box::new(e));
+    }
+    
+    let checksums = render(&id, wav_spec, &wav_data, repository, store, &RenderLimits::default()).await?;
+
+    Ok(json!({ "message": format!("Stored Wav File in Bucket"), "id": id, "sha256": checksums.sha256, "crc32c": checksums.crc32c }))
+}
+
+/// Renders all files of a batch one after another, and creates the bundle afterwards if it was requested.
+///
+/// Files exceeding the budget are rejected up front, the other files are still rendered.
+/// The files share the budget of a single file, if they exceed it together all of them are rejected.
+/// Since the bundle would be incomplete, it is skipped if any file was rejected.
+/// The rejected files are listed in the response instead of failing the event, 
+/// so a retry of the invocation doesn't render the other files again.
+async fn handle_batch(
+    batch: BatchEvent,
+    budget: &RenderBudget,
+    limits: &RenderLimits,
+    repository: &dyn WaveRepository,
+    store: &dyn WaveStore)
+-> Result<Value, Error> {
+    let mut rejected = vec![];
+    let mut costs = vec![];
+    for item in &batch.items {
+        match budget.check(&item.wav_spec, &item.wav_data) {
+            Ok(cost) => costs.push(cost),
+            Err(e) => {
+                reject(&item.wav_id, &e, repository).await?;
+                rejected.push(item.wav_id.clone());
+            },
+        }
+    }
+    if let Err(e) = budget.check_total(&costs) {
+        let remaining: Vec<String> = batch.items.iter().map(|item| item.wav_id.clone()).filter(|id| !rejected.contains(id)).collect();
+        for id in remaining {
+            reject(&id, &e, repository).await?;
+            rejected.push(id);
+        }
+    }
+
+    info!("Rendering {} files of batch {}", batch.items.len() - rejected.len(), batch.batch_id);
+    for item in batch.items.iter().filter(|item| !rejected.contains(&item.wav_id)) {
+        render(&item.wav_id, item.wav_spec, &item.wav_data, repository, store, limits).await?;
+    }
+
+    let bundle = match &batch.bundle {
+        Some(_) if !rejected.is_empty() => {
+            error!("Skipping bundle of batch {}, {} files were rejected", batch.batch_id, rejected.len());
+            None
+        },
+        Some(request) => Some(bundle::create_bundle(&batch.batch_id, request, store).await?),
+        None => None,
+    };
+
+    Ok(json!({ "message": "Stored batch in Bucket", "batch_id": batch.batch_id, "bundle": bundle, "rejected": rejected }))
+}
+
+/// Records why the file of the item isn't rendered, so the status of the request reports it.
+async fn reject(id: &str, reason: &BudgetErr, repository: &dyn WaveRepository) -> Result<(), Error> {
+    error!("Rejecting file {}: {}", id, reason);
+    match repository.get(id).await? {
+        Some(mut item) => {
+            item.error = Some(reason.to_string());
+            repository.put(item).await?;
+        },
+        None => error!("No item with id {}, the rejection isn't recorded", id),
+    }
+    Ok(())
+}
+
+/// Renders a single wave file and stores it in the bucket under `id`, together with its checksums.
+async fn render(
+    id: &str,
+    wav_spec: WavSpec,
+    wav_data: &WavData,
+    repository: &dyn WaveRepository,
+    store: &dyn WaveStore,
+    limits: &RenderLimits)
+-> Result<Checksums, Error> {
+    let key = id.to_owned() + FILE_EXTENSION;
+    info!("Rendering file {}", key);
+    let (rendered, checksums) = render::render(&key, wav_spec, wav_data, store, limits).await?;
+
+    // the item is updated before the file is visible, so a client that sees the file also sees its checksums
+    // and the delivery can't mark the item as downloaded in between
+    if let Err(e) = record_checksums(id, &checksums, repository).await {
+        rendered.discard().await;
+        return Err(e);
+    }
+
+    info!("Putting file into bucket...");
+    rendered.store(&key, &checksums, store).await?;
+    info!("Successfully put file into bucket with sha256 {}", checksums.sha256);
+
+    Ok(checksums)
+}
+
+async fn record_checksums(id: &str, checksums: &Checksums, repository: &dyn WaveRepository) -> Result<(), Error> {
+    match repository.get(id).await? {
+        Some(mut item) => {
+            item.sha256 = Some(checksums.sha256.clone());
+            item.crc32c = Some(checksums.crc32c.clone());
+            repository.put(item).await?;
+        },
+        None => error!("No item with id {}, the checksums are only stored in the bucket", id),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_deserialize_wavdata() {
+    let obj = json!({
+        "frequencies": [1 as u16,2 as u16,3 as u16],
+        "duration": 10 as u16,
+        "volume": 1.5 as f64
+    });
+    let wav_data: Result<WavData, _> = serde_json::from_str(&obj.to_string());
+
+    assert!(wav_data.is_ok());
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::{Cursor, Read}, path::Path, sync::atomic::{AtomicUsize, Ordering}};
+    use sha2::{Digest, Sha256};
+    use wave_store::{BatchDeleteResult, InMemoryStore, ListPage, ObjectInfo, ObjectUpload, StoreErr};
+    use wave_table::{CreationTime, InMemoryRepository, WaveItem};
+
+    fn spec_and_data(frequency: u16) -> (Value, Value) {
+        (
+            json!({ "number_of_channels": 1, "sample_rate": 8000, "bits_per_sample": 8 }),
+            json!({ "frequencies": [frequency], "duration": 1, "volume": 1 }),
+        )
+    }
+
+    fn item(id: &str, frequency: u16) -> Value {
+        let (spec, data) = spec_and_data(frequency);
+        json!({ "wav_id": id, "wav_spec": spec, "wav_data": data })
+    }
+
+    /// Fits the files of the tests, which render one second at 8 kHz.
+    fn budget() -> RenderBudget {
+        RenderBudget { max_operations: 100_000, max_file_size: 100_000 }
+    }
+
+    /// Puts the item the main lambda creates for a file of the event.
+    async fn put_item(repository: &InMemoryRepository, event: &Value) {
+        let id = event["wav_id"].as_str().unwrap();
+        let spec: WavSpec = serde_json::from_value(event["wav_spec"].clone()).unwrap();
+        let data: WavData = serde_json::from_value(event["wav_data"].clone()).unwrap();
+        let created = CreationTime::from(chrono::Utc::now());
+        repository.put(WaveItem::new(id, "request", spec, data, created, 0, ("hash", &format!("{}.wav", id)))).await.unwrap();
+    }
+
+    fn bundle_entry(id: &str, object_key: &str, frequency: u16) -> Value {
+        let (spec, data) = spec_and_data(frequency);
+        json!({ "id": id, "object_key": object_key, "wav_spec": spec, "wav_data": data })
+    }
+
+    /// A batch of three files, the third one is identical to the first one, so it points to its object.
+    fn batch_event(batch_id: &str, format: &str) -> Value {
+        let id = |suffix: &str| format!("{}-{}", batch_id, suffix);
+        json!({
+            "batch_id": batch_id,
+            "items": [item(&id("a"), 440), item(&id("b"), 660)],
+            "bundle": {
+                "format": format,
+                "files": [
+                    bundle_entry(&id("a"), &format!("{}.wav", id("a")), 440),
+                    bundle_entry(&id("b"), &format!("{}.wav", id("b")), 660),
+                    bundle_entry(&id("c"), &format!("{}.wav", id("a")), 440),
+                ],
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn test_handle_batch_with_zip() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let limits = small_limits(temp_dir.path());
+
+        let batch = serde_json::from_value(batch_event("zip-batch", "zip")).unwrap();
+        let response = handle_batch(batch, &budget(), &limits, &repository, &store).await.unwrap();
+        assert_eq!(response["bundle"], "zip-batch.zip");
+        assert!(store.head("zip-batch-b.wav").await.unwrap().is_some());
+        assert!(is_empty(temp_dir.path()));
+
+        let bundle = store.get("zip-batch.zip").await.unwrap().unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(bundle)).unwrap();
+        assert_eq!(archive.len(), 4);
+
+        let file = store.get("zip-batch-a.wav").await.unwrap().unwrap();
+        let mut bundled = vec![];
+        archive.by_name("zip-batch-c.wav").unwrap().read_to_end(&mut bundled).unwrap();
+        assert_eq!(bundled, file);
+
+        let mut manifest = String::new();
+        archive.by_name("manifest.json").unwrap().read_to_string(&mut manifest).unwrap();
+        let manifest: Value = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(manifest["batch_id"], "zip-batch");
+        assert_eq!(manifest["files"][2]["name"], "zip-batch-c.wav");
+        assert_eq!(manifest["files"][2]["duration"], 1);
+        assert_eq!(manifest["files"][2]["size"], file.len());
+        assert_eq!(manifest["files"][2]["sha256"], manifest["files"][0]["sha256"]);
+        assert_eq!(manifest["files"][1]["wav_data"]["frequencies"], json!([660]));
+    }
+
+    #[tokio::test]
+    async fn test_records_checksums() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let event = item("checksum-item", 440);
+        let key = "checksum-item.wav";
+        put_item(&repository, &event).await;
+
+        let response = handle_event(event, &budget(), &repository, &store).await.unwrap();
+
+        let file = store.get(key).await.unwrap().unwrap();
+        let sha256 = format!("{:x}", Sha256::digest(&file));
+        let crc32c = base64::encode(crc32c::crc32c(&file).to_be_bytes());
+        assert_eq!(response["sha256"], sha256);
+        assert_eq!(response["crc32c"], crc32c);
+
+        let item = repository.get("checksum-item").await.unwrap().unwrap();
+        assert_eq!(item.sha256, Some(sha256));
+        assert_eq!(item.crc32c, Some(crc32c));
+    }
+
+    #[tokio::test]
+    async fn test_reject_over_budget() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let mut event = item("expensive-item", 440);
+        event["wav_data"]["frequencies"] = json!(vec![440; 20]);
+        put_item(&repository, &event).await;
+
+        let error = handle_event(event, &budget(), &repository, &store).await.unwrap_err();
+        assert!(error.to_string().contains("160000 operations"));
+        assert!(store.head("expensive-item.wav").await.unwrap().is_none());
+        let item = repository.get("expensive-item").await.unwrap().unwrap();
+        assert_eq!(item.error, Some(error.to_string()));
+        assert_eq!(item.sha256, None);
+    }
+
+    #[tokio::test]
+    async fn test_reject_over_budget_in_batch() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let mut event = batch_event("rejected-batch", "zip");
+        event["items"][1]["wav_data"]["duration"] = json!(20);
+        for item in event["items"].as_array().unwrap() {
+            put_item(&repository, item).await;
+        }
+
+        let response = handle_event(event, &budget(), &repository, &store).await.unwrap();
+        assert_eq!(response["rejected"], json!(["rejected-batch-b"]));
+        assert_eq!(response["bundle"], Value::Null);
+        assert!(store.head("rejected-batch-a.wav").await.unwrap().is_some());
+        assert!(store.head("rejected-batch-b.wav").await.unwrap().is_none());
+        assert!(store.head("rejected-batch.zip").await.unwrap().is_none());
+        assert!(repository.get("rejected-batch-b").await.unwrap().unwrap().error.unwrap().contains("operations"));
+        assert_eq!(repository.get("rejected-batch-a").await.unwrap().unwrap().error, None);
+    }
+
+    #[tokio::test]
+    async fn test_reject_batch_over_budget() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let mut event = batch_event("expensive-batch", "zip");
+        for item in event["items"].as_array_mut().unwrap() {
+            item["wav_data"]["duration"] = json!(7);
+            put_item(&repository, item).await;
+        }
+
+        // each file fits into the budget, but not both together
+        let response = handle_event(event, &budget(), &repository, &store).await.unwrap();
+        assert_eq!(response["rejected"], json!(["expensive-batch-a", "expensive-batch-b"]));
+        assert_eq!(response["bundle"], Value::Null);
+        for id in ["expensive-batch-a", "expensive-batch-b"] {
+            assert!(store.head(&format!("{}.wav", id)).await.unwrap().is_none());
+            assert!(repository.get(id).await.unwrap().unwrap().error.unwrap().contains("112000 operations in total"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_batch_with_tar_gz() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+
+        let response = handle_event(batch_event("tar-batch", "tar.gz"), &budget(), &repository, &store).await.unwrap();
+        assert_eq!(response["bundle"], "tar-batch.tar.gz");
+
+        let bundle = store.get("tar-batch.tar.gz").await.unwrap().unwrap();
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(Cursor::new(bundle)));
+        let names: Vec<String> = archive
+            .entries().unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().to_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(names, vec!["tar-batch-a.wav", "tar-batch-b.wav", "tar-batch-c.wav", "manifest.json"]);
+    }
+
+    #[tokio::test]
+    async fn test_bundle_with_missing_file() {
+        let repository = InMemoryRepository::new();
+        let store = InMemoryStore::new();
+        let mut event = batch_event("missing-batch", "zip");
+        event["items"] = json!([]);
+
+        assert!(handle_event(event, &budget(), &repository, &store).await.is_err());
+        assert!(store.head("missing-batch.zip").await.unwrap().is_none());
+
+        let mut event = batch_event("unknown-batch", "rar");
+        event["items"] = json!([]);
+        assert!(handle_event(event, &budget(), &repository, &store).await.is_err());
+    }
+
+    /// A store, which fails some of the requests, in order to check that nothing is left behind.
+    #[derive(Default)]
+    struct FailingStore {
+        inner: InMemoryStore,
+        /// Number of uploads, which fail to start before the next one starts
+        failed_start_uploads: AtomicUsize,
+        fail_upload_write: bool,
+        fail_put: bool,
+        aborted: AtomicUsize,
+    }
+
+    impl FailingStore {
+        fn failing_start_uploads(times: usize) -> Self {
+            FailingStore { failed_start_uploads: AtomicUsize::new(times), ..Default::default() }
+        }
+    }
+
+    struct FailingUpload<'a> {
+        inner: Box<dyn ObjectUpload + 'a>,
+        store: &'a FailingStore,
+    }
+
+    fn failure() -> StoreErr {
+        StoreErr::new("failed on purpose")
+    }
+
+    #[async_trait::async_trait]
+    impl ObjectUpload for FailingUpload<'_> {
+        async fn write(&mut self, data: &[u8]) -> Result<(), StoreErr> {
+            if self.store.fail_upload_write { Err(failure()) } else { self.inner.write(data).await }
+        }
+
+        async fn complete(self: Box<Self>) -> Result<(), StoreErr> {
+            self.inner.complete().await
+        }
+
+        async fn abort(self: Box<Self>) -> Result<(), StoreErr> {
+            self.store.aborted.fetch_add(1, Ordering::SeqCst);
+            self.inner.abort().await
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl WaveStore for FailingStore {
+        async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), StoreErr> {
+            if self.fail_put { Err(failure()) } else { self.inner.put(key, body).await }
+        }
+
+        async fn start_upload(&self, key: &str) -> Result<Box<dyn ObjectUpload + '_>, StoreErr> {
+            if self.failed_start_uploads.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |times| times.checked_sub(1)).is_ok() {
+                return Err(failure());
+            }
+            Ok(Box::new(FailingUpload { inner: self.inner.start_upload(key).await?, store: self }))
+        }
+
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreErr> {
+            self.inner.get(key).await
+        }
+
+        async fn head(&self, key: &str) -> Result<Option<ObjectInfo>, StoreErr> {
+            self.inner.head(key).await
+        }
+
+        async fn list(&self, prefix: Option<&str>, continuation_token: Option<&str>) -> Result<ListPage, StoreErr> {
+            self.inner.list(prefix, continuation_token).await
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), StoreErr> {
+            self.inner.delete(key).await
+        }
+
+        async fn delete_batch(&self, keys: &[String]) -> Result<BatchDeleteResult, StoreErr> {
+            self.inner.delete_batch(keys).await
+        }
+    }
+
+    /// Limits, which render the test files with 8000 bytes of data into an upload or a temporary file in `temp_dir`.
+    fn small_limits(temp_dir: &Path) -> RenderLimits {
+        RenderLimits { max_memory_size: 1000, max_temp_file_size: 10_000, temp_dir: temp_dir.to_owned() }
+    }
+
+    fn is_empty(dir: &Path) -> bool {
+        std::fs::read_dir(dir).unwrap().next().is_none()
+    }
+
+    async fn render_test_file(id: &str, store: &dyn WaveStore, limits: &RenderLimits) -> Result<Checksums, Error> {
+        let spec = WavSpec::new(1, 8000, 8).unwrap();
+        let data = WavData { frequencies: vec![440], duration: 1, volume: 1. };
+        render(id, spec, &data, &InMemoryRepository::new(), store, limits).await
+    }
+
+    #[tokio::test]
+    async fn test_render_into_upload() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let limits = small_limits(temp_dir.path());
+
+        let store = FailingStore::default();
+        let checksums = render_test_file("upload", &store, &limits).await.unwrap();
+        let file = store.get("upload.wav").await.unwrap().unwrap();
+        assert_eq!(file.len(), 44 + 8000);
+        assert_eq!(checksums.sha256, format!("{:x}", Sha256::digest(&file)));
+        assert!(is_empty(temp_dir.path()));
+
+        // a failed upload is aborted, and the file never becomes visible
+        let store = FailingStore { fail_upload_write: true, ..Default::default() };
+        assert!(render_test_file("upload", &store, &limits).await.is_err());
+        assert_eq!(store.aborted.load(Ordering::SeqCst), 1);
+        assert!(store.head("upload.wav").await.unwrap().is_none());
+        assert!(is_empty(temp_dir.path()));
+    }
+
+    #[tokio::test]
+    async fn test_render_into_temp_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let limits = small_limits(temp_dir.path());
+
+        // the upload can be started by the time the file is stored
+        let store = FailingStore::failing_start_uploads(1);
+        let checksums = render_test_file("temp", &store, &limits).await.unwrap();
+        let file = store.get("temp.wav").await.unwrap().unwrap();
+        assert_eq!(file.len(), 44 + 8000);
+        assert_eq!(checksums.sha256, format!("{:x}", Sha256::digest(&file)));
+        assert!(is_empty(temp_dir.path()));
+
+        // no upload at all, the file is stored with a single request
+        let store = FailingStore::failing_start_uploads(usize::MAX);
+        render_test_file("temp", &store, &limits).await.unwrap();
+        assert_eq!(store.get("temp.wav").await.unwrap().unwrap(), file);
+        assert!(is_empty(temp_dir.path()));
+
+        // the temporary file is removed when storing it fails
+        let store = FailingStore { fail_put: true, ..FailingStore::failing_start_uploads(usize::MAX) };
+        assert!(render_test_file("temp", &store, &limits).await.is_err());
+        assert!(is_empty(temp_dir.path()));
+
+        let store = FailingStore { fail_upload_write: true, ..FailingStore::failing_start_uploads(1) };
+        assert!(render_test_file("temp", &store, &limits).await.is_err());
+        assert_eq!(store.aborted.load(Ordering::SeqCst), 1);
+        assert!(store.head("temp.wav").await.unwrap().is_none());
+        assert!(is_empty(temp_dir.path()));
+
+        // files that don't fit into a temporary file fail, before anything is written
+        let limits = RenderLimits { max_temp_file_size: 1000, ..limits };
+        let store = FailingStore::failing_start_uploads(usize::MAX);
+        assert!(render_test_file("temp", &store, &limits).await.is_err());
+        assert!(store.head("temp.wav").await.unwrap().is_none());
+        assert!(is_empty(temp_dir.path()));
+    }
+}
@@ -1,31 +1,23 @@
-use std::{fmt::Display, path::{Path, PathBuf}};
-
-use aws_sdk_s3::types::ByteStream;
+use cloud_config::Config;
+use cloud_sine_generator::handle_event;
 use lambda_runtime::{service_fn, LambdaEvent, Error};
-use tracing::{info, error};
-use serde_json::{json, Value};
-use sine_generator::{data_formats::{WavSpec, WavData} , frequency_writer::{SineWavSpec, self}};
-
-const BUCKET_NAME: Option<&str> = option_env!("TF_VAR_BUCKET_NAME");
-const BUCKET_NAME_FALLBACK: &str = "cloud-wav-file-bucket";
+use serde_json::Value;
+use sine_generator::cost::RenderBudget;
+use wave_store::{S3Store, UploadConfig};
+use wave_table::DynamoRepository;
 
-#[derive(Debug)]
-struct WavSpecErr(&'static str);
+async fn function_handler(event: LambdaEvent<Value>, config: &Config) -> Result<Value, Error> {
+    let aws_config = aws_config::load_from_env().await;
+    let repository = DynamoRepository::new(aws_sdk_dynamodb::Client::new(&aws_config), config);
+    // large files are uploaded in parts, several at a time and each part retried on its own
+    let store = S3Store::new(aws_sdk_s3::Client::new(&aws_config), &config.bucket_name).with_upload_config(UploadConfig::default());
 
-impl WavSpecErr {
-    fn new_default() -> Self {
-        WavSpecErr("Error creating WavSpec, got invalid values/fields") 
-    }
-}
+    let budget = RenderBudget { max_operations: config.max_render_operations, max_file_size: config.max_file_size };
 
-impl Display for WavSpecErr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f,"{}", self.0)
-    }
+    let (event, _) = event.into_parts();
+    handle_event(event, &budget, &repository, &store).await
 }
 
-impl std::error::Error for WavSpecErr {}
-
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     tracing_subscriber::fmt()
@@ -34,84 +26,11 @@ async fn main() -> Result<(), Error> {
     .without_time()
     .init();
 
-    let func = service_fn(handle_event);
-    lambda_runtime::run(func).await?;
-    Ok(())
-}
-
-async fn handle_event(event: LambdaEvent<Value>) -> Result<Value, Error> {
-    let (mut event, _) = event.into_parts();
-    
-    // TODO refactor into function maybe
-    info!("Looking for WavSpec in event");
-    let wav_spec: WavSpec = serde_json::from_value(
-        event
-            .get_mut("wav_spec")
-            .ok_or(WavSpecErr("WavSpec field missing"))?
-            .take())?;
-    
-    info!("Looking for WavData in event");
-    let wav_data: WavData = serde_json::from_value(
-        event
-            .get_mut("wav_data")
-            .ok_or(WavSpecErr("WavData field missing"))?
-            .take())?;
-
-    let id: String = serde_json::from_value(
-        event
-            .get_mut("wav_id")
-            .ok_or(WavSpecErr("Id field missing"))?
-            .take())?;
-    
-    info!("Creating SineSpec");
-    let sine_spec = match SineWavSpec::new(&wav_spec, &wav_data) {
-        Some(spec) => spec,
-        None => { 
-            error!("Supplied data is invalid, cannot create SineWavSpec."); 
-            return Err(Box::new(WavSpecErr::new_default()));
-        }
-    }; 
-    
-    info!("Creating WavWriter");
-    let file_name: PathBuf = [r"/tmp", &(id.clone() + ".wav")].iter().collect(); // lambda functions only have write access to tmp folder
-    let writer = sine_generator::wav_writer::WavWriter::new_with_spec(wav_spec, file_name.to_str().unwrap())?;
-
-    info!("Writing to file...");
-    frequency_writer::write_wave(sine_spec, writer)?;
-
-    store_in_bucket(file_name.as_path()).await?;
-
-    Ok(json!({ "message": format!("Stored Wav File in Bucket"), "id": id }))
-}
-
-async fn store_in_bucket(file_path: &Path) -> Result<(), Error> {
-    let config = aws_config::load_from_env().await;
-    let client = aws_sdk_s3::Client::new(&config);
-
-    info!("Getting file {:?} from lambda.", file_path);
-    let file = ByteStream::from_path(file_path).await?;
-
-    info!("Putting file into bucket...");
-    let _ = client
-        .put_object()
-        .bucket(BUCKET_NAME.unwrap_or(BUCKET_NAME_FALLBACK))
-        .key(file_path.file_name().unwrap().to_str().unwrap())
-        .body(file)
-        .send().await?;
-
-    info!("Successfully put file into bucket");
+    // read the configuration once during the cold start
+    let config = Config::from_env()?;
+    let config = &config;
 
+    let func = service_fn(move |event| async move { function_handler(event, config).await });
+    lambda_runtime::run(func).await?;
     Ok(())
 }
-
-#[test]
-fn test_deserialize_wavdata() {
-    let obj = json!({
-        "frequencies": [1 as u16,2 as u16,3 as u16],
-        "duration": 10 as u16,
-        "volume": 1.5 as f64
-    });
-    let wav_data: Result<WavData, _> = serde_json::from_str(&obj.to_string());
-
-    assert!(wav_data.is_ok());
-}
\ No newline at end of file
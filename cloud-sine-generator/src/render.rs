@@ -0,0 +1,219 @@
+//! Renders a wave file into the bucket, without keeping large files in memory or in the tmp folder.
+//!
+//! Small files are rendered into memory and stored with a single request. Larger files are streamed
+//! into an upload in parts while they are rendered. If the upload can't be started, the file is written
+//! into a temporary file instead, as long as it fits, and removed again once it is stored or discarded.
+
+use std::{fs::File, io::{self, BufWriter, Read, Write}, path::{Path, PathBuf}};
+
+use lambda_runtime::Error;
+use sine_generator::{data_formats::{self, WavData, WavSpec}, frequency_writer::{self, SineWavSpec}, wav_writer::WavWriter};
+use tokio::sync::mpsc;
+use tracing::{info, warn, error};
+use wave_store::{Checksums, ObjectUpload, WaveStore};
+
+use crate::{checksum::ChecksumWriter, WavSpecErr};
+
+/// Size of the chunks the rendered file is passed on in.
+const CHUNK_SIZE: usize = 1024 * 1024;
+/// Number of chunks, which can wait for the upload before the rendering is paused.
+const CHANNEL_CAPACITY: usize = 4;
+
+/// Determines where a file is rendered to.
+#[derive(Debug, Clone)]
+pub struct RenderLimits {
+    /// Files up to this size are rendered into memory and stored with a single request.
+    pub max_memory_size: u64,
+    /// Files up to this size can be written into a temporary file, if the upload can't be started.
+    pub max_temp_file_size: u64,
+    /// The folder of the temporary files, lambda functions only have write access to `/tmp`.
+    pub temp_dir: PathBuf,
+}
+
+impl Default for RenderLimits {
+    fn default() -> Self {
+        RenderLimits {
+            max_memory_size: 8 * 1024 * 1024,
+            // the lambda has 512 MB of ephemeral storage, which is shared with the runtime
+            max_temp_file_size: 400 * 1024 * 1024,
+            temp_dir: std::env::temp_dir(),
+        }
+    }
+}
+
+/// A temporary file, which is removed when it is dropped, no matter if it was stored or not.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn create(dir: &Path, key: &str) -> io::Result<(TempFile, File)> {
+        let temp_file = TempFile { path: dir.join(key) };
+        let file = File::create(&temp_file.path)?;
+        Ok((temp_file, file))
+    }
+
+    /// Streams the file into an upload, if one can be started by now. Otherwise the file is stored
+    /// with a single request, it was only written because it is smaller than `max_temp_file_size`.
+    async fn store(&self, key: &str, checksums: &Checksums, store: &dyn WaveStore) -> Result<(), Error> {
+        let mut file = File::open(&self.path)?;
+        match store.start_upload(key).await {
+            Ok(mut upload) => {
+                if let Err(e) = copy_into_upload(&mut file, upload.as_mut()).await {
+                    Rendered::Upload(upload).discard().await;
+                    return Err(e);
+                }
+                upload.complete().await?;
+            },
+            Err(e) => {
+                warn!("Unable to start upload, storing {:?} with a single request: {}", self.path, e);
+                let mut body = vec![];
+                file.read_to_end(&mut body)?;
+                store.put_with_checksums(key, body, checksums).await?;
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            error!("Unable to remove temporary file {:?}: {}", self.path, e);
+        }
+    }
+}
+
+async fn copy_into_upload(file: &mut File, upload: &mut dyn ObjectUpload) -> Result<(), Error> {
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        match file.read(&mut chunk)? {
+            0 => return Ok(()),
+            read => upload.write(&chunk[..read]).await?,
+        }
+    }
+}
+
+/// A rendered file, which is not visible in the bucket yet.
+pub enum Rendered<'a> {
+    Memory(Vec<u8>),
+    Upload(Box<dyn ObjectUpload + 'a>),
+    TempFile(TempFile),
+}
+
+impl Rendered<'_> {
+    /// Makes the file visible in the bucket under `key`.
+    pub async fn store(self, key: &str, checksums: &Checksums, store: &dyn WaveStore) -> Result<(), Error> {
+        match self {
+            Rendered::Memory(file) => store.put_with_checksums(key, file, checksums).await?,
+            Rendered::Upload(upload) => upload.complete().await?,
+            Rendered::TempFile(temp_file) => temp_file.store(key, checksums, store).await?,
+        }
+        Ok(())
+    }
+
+    /// Drops the file, without making it visible.
+    pub async fn discard(self) {
+        if let Rendered::Upload(upload) = self {
+            if let Err(e) = upload.abort().await {
+                error!("Unable to abort upload: {}", e);
+            }
+        }
+    }
+}
+
+/// Renders the wave file, which is stored under `key` once `Rendered::store` is called.
+pub async fn render<'a>(
+    key: &str,
+    wav_spec: WavSpec,
+    wav_data: &WavData,
+    store: &'a dyn WaveStore,
+    limits: &RenderLimits)
+-> Result<(Rendered<'a>, Checksums), Error> {
+    if SineWavSpec::new(&wav_spec, wav_data).is_none() {
+        error!("Supplied data is invalid, cannot create SineWavSpec.");
+        return Err(Box::new(WavSpecErr::new_default()));
+    }
+
+    let size = data_formats::file_size(&wav_spec, wav_data);
+    if size <= limits.max_memory_size {
+        info!("Rendering {} bytes into memory", size);
+        let (file, checksums) = write_file(wav_spec, wav_data, Vec::with_capacity(size as usize))?;
+        return Ok((Rendered::Memory(file), checksums));
+    }
+
+    match store.start_upload(key).await {
+        Ok(mut upload) => {
+            info!("Rendering {} bytes into an upload", size);
+            match render_into_upload(wav_spec, wav_data, upload.as_mut()).await {
+                Ok(checksums) => Ok((Rendered::Upload(upload), checksums)),
+                Err(e) => {
+                    Rendered::Upload(upload).discard().await;
+                    Err(e)
+                },
+            }
+        },
+        Err(e) if size <= limits.max_temp_file_size => {
+            warn!("Unable to start upload, rendering {} bytes into a temporary file instead: {}", size, e);
+            let (temp_file, file) = TempFile::create(&limits.temp_dir, key)?;
+            let (_, checksums) = write_file(wav_spec, wav_data, file)?;
+            Ok((Rendered::TempFile(temp_file), checksums))
+        },
+        Err(e) => {
+            error!("Unable to start upload of {} bytes, which don't fit into a temporary file: {}", size, e);
+            Err(Box::new(e))
+        },
+    }
+}
+
+/// Renders the file on a blocking thread, while the chunks written so far are uploaded.
+async fn render_into_upload(wav_spec: WavSpec, wav_data: &WavData, upload: &mut dyn ObjectUpload) -> Result<Checksums, Error> {
+    let (sender, mut receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    let wav_data = wav_data.clone();
+    let renderer = tokio::task::spawn_blocking(move || {
+        write_file(wav_spec, &wav_data, ChannelWriter(sender)).map(|(_, checksums)| checksums)
+    });
+
+    let mut uploaded = Ok(());
+    while let Some(chunk) = receiver.recv().await {
+        if let Err(e) = upload.write(&chunk).await {
+            uploaded = Err(e);
+            break;
+        }
+    }
+    // stops the renderer, if the upload failed
+    drop(receiver);
+
+    let rendered = renderer.await?;
+    uploaded?;
+    Ok(rendered?)
+}
+
+/// Writes the wave file into `output` and returns it together with the checksums of the file.
+fn write_file<W: Write>(wav_spec: WavSpec, wav_data: &WavData, output: W) -> io::Result<(W, Checksums)> {
+    let sine_spec = SineWavSpec::new(&wav_spec, wav_data)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Supplied data is invalid"))?;
+
+    // the checksums are computed from the buffered output, so they see the file in large chunks
+    let mut output = BufWriter::with_capacity(CHUNK_SIZE, ChecksumWriter::new(output));
+    // the sizes are written into the header up front, so the file never needs to be changed once it is written
+    let writer = WavWriter::new_with_data(wav_spec, wav_data, &mut output)?;
+    frequency_writer::write_wave(sine_spec, writer)?;
+    output.into_inner().map_err(|e| e.into_error())?.finish()
+}
+
+/// Passes the written chunks on to the upload, waiting while the upload is behind.
+struct ChannelWriter(mpsc::Sender<Vec<u8>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The upload was stopped"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
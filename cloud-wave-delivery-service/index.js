@@ -62,7 +62,9 @@ exports.handler = async (event, context) => {
                   Key: {
                     id: file_id
                   },
-                  ProjectionExpression: 'request_id, is_downloaded'
+                  // error is a reserved word in dynamo db expressions
+                  ProjectionExpression: 'request_id, is_downloaded, object_key, sha256, crc32c, #error, deleted_at',
+                  ExpressionAttributeNames: {'#error': 'error'}
                 };
 
                 console.log("offset_num: " + offsetNum);
@@ -83,6 +85,13 @@ exports.handler = async (event, context) => {
                     } else {
                         let ready = false;
                         let file;
+                        let checksums;
+
+                        // files of deduplicated requests are stored under the key of the original request
+                        if (data.Item.object_key) {
+                            objParams.Key = data.Item.object_key;
+                            objParamsBuffer.Key = data.Item.object_key;
+                        }
                         
                         // check if file is already finished processing, goes to catch block if file not found in s3 bucket
                         try {
@@ -91,6 +100,26 @@ exports.handler = async (event, context) => {
                             const s3ObjMeta = await (s3.headObject(objParams).promise());
                             console.log("meta: " + JSON.stringify(s3ObjMeta));
                             const objSize = s3ObjMeta.ContentLength;
+
+                            // checksums of the whole file, set by the sine generator in the metadata of the object
+                            const metadata = s3ObjMeta.Metadata || {};
+                            checksums = {sha256: metadata.sha256, crc32c: metadata.crc32c};
+
+                            // large files are uploaded in parts, their checksums are only recorded on the item that created the file
+                            if (!checksums.sha256) {
+                                let owner = data.Item;
+                                if (!owner.sha256 && data.Item.object_key && data.Item.object_key !== file_id + ".wav") {
+                                    const ownerParams = {
+                                        TableName : process.env.TABLE_NAME,
+                                        Key: {
+                                            id: data.Item.object_key.replace(/\.wav$/, "")
+                                        },
+                                        ProjectionExpression: 'sha256, crc32c'
+                                    };
+                                    owner = (await dynamo.get(ownerParams).promise()).Item || {};
+                                }
+                                checksums = {sha256: owner.sha256, crc32c: owner.crc32c};
+                            }
                             
                             // determines if request is the last part of the file
                             if (objSize - offsetNum * byteRange >= byteRange) {
@@ -150,10 +179,32 @@ exports.handler = async (event, context) => {
                         if (ready) {
                             // wraps the file (part) as base64 string
                             isBase64Encoded = true;
-                            body = {status: "ready", file: file.toString('base64')};
+                            body = {status: "ready", file: file.toString('base64'), sha256: checksums.sha256, crc32c: checksums.crc32c};
                         } else {
-                            // tells the frontend to wait and ask again
-                            body = {status: "in_progress"};
+                            // the generator records why it rejected a file on the item that creates the file
+                            let error = data.Item.error;
+                            if (!error && data.Item.object_key && data.Item.object_key !== file_id + ".wav") {
+                                const ownerParams = {
+                                    TableName : process.env.TABLE_NAME,
+                                    Key: {
+                                        id: data.Item.object_key.replace(/\.wav$/, "")
+                                    },
+                                    ProjectionExpression: '#error',
+                                    ExpressionAttributeNames: {'#error': 'error'}
+                                };
+                                error = ((await dynamo.get(ownerParams).promise()).Item || {}).error;
+                            }
+
+                            if (error) {
+                                // the file will never be ready, so the frontend stops asking
+                                body = {status: "failed", error: error};
+                            } else if (data.Item.deleted_at) {
+                                // the bucket cleaner removed the file after its retention ended
+                                body = {status: "deleted", deleted_at: data.Item.deleted_at};
+                            } else {
+                                // tells the frontend to wait and ask again
+                                body = {status: "in_progress"};
+                            }
                         }
                     }
                 } else {
@@ -52,6 +52,21 @@ resource "aws_dynamodb_table" "wave_file_table" {
     type = "S"
   }
 
+  attribute {
+    name = "content_hash"
+    type = "S"
+  }
+
+  attribute {
+    name = "batch_id"
+    type = "S"
+  }
+
+  attribute {
+    name = "object_key"
+    type = "S"
+  }
+
   # attribute {
   #   name = "is_downloaded"
   #   type = "S"
@@ -69,7 +84,63 @@ resource "aws_dynamodb_table" "wave_file_table" {
     write_capacity     = 10
     read_capacity      = 10
     projection_type    = "INCLUDE"
-    non_key_attributes = ["is_downloaded"]
+    non_key_attributes = ["is_downloaded", "object_key", "content_hash", "deleted_at", "expires_at"]
+  }
+
+  // used to find files of identical requests, which can be reused
+  global_secondary_index {
+    name               = var.CONTENT_INDEX
+    hash_key           = "content_hash"
+    write_capacity     = 10
+    read_capacity      = 10
+    projection_type    = "INCLUDE"
+    non_key_attributes = ["is_downloaded", "object_key", "deleted_at", "expires_at", "error"]
+  }
+
+  // used to report the status of batch requests, only items created by a batch contain the attribute
+  global_secondary_index {
+    name               = var.BATCH_INDEX
+    hash_key           = "batch_id"
+    write_capacity     = 10
+    read_capacity      = 10
+    projection_type    = "INCLUDE"
+    non_key_attributes = ["is_downloaded", "object_key", "content_hash", "deleted_at", "expires_at", "error"]
+  }
+
+  // used by the bucket cleaner to find the items pointing to a file, also once the item that created it expired
+  global_secondary_index {
+    name               = var.OBJECT_KEY_INDEX
+    hash_key           = "object_key"
+    write_capacity     = 10
+    read_capacity      = 10
+    projection_type    = "INCLUDE"
+    non_key_attributes = ["is_downloaded", "content_hash", "deleted_at", "expires_at"]
+  }
+
+  // items are removed by dynamodb some time after they expired
+  ttl {
+    attribute_name = "expires_at"
+    enabled        = true
+  }
+}
+
+// DynamoDB Table of the storage stats, written by the bucket cleaner after each run
+resource "aws_dynamodb_table" "storage_stats_table" {
+  name           = var.STATS_TABLE
+  billing_mode   = "PROVISIONED"
+  read_capacity  = 1
+  write_capacity = 1
+  hash_key       = "date"
+  range_key      = "created_at"
+
+  attribute {
+    name = "date"
+    type = "S"
+  }
+
+  attribute {
+    name = "created_at"
+    type = "S"
   }
 }
 
@@ -79,6 +150,20 @@ resource "aws_s3_bucket" "cloud-wav-file-bucket" {
 
 }
 
+// removes the parts of bundle uploads, which failed and couldn't be aborted
+resource "aws_s3_bucket_lifecycle_configuration" "wave_file_bucket_lifecycle" {
+  bucket = aws_s3_bucket.cloud-wav-file-bucket.id
+
+  rule {
+    id     = "abort-incomplete-uploads"
+    status = "Enabled"
+
+    abort_incomplete_multipart_upload {
+      days_after_initiation = 1
+    }
+  }
+}
+
 // S3 Bucket 'react-website-bucket'
 resource "aws_s3_bucket" "react-website-bucket" {
   bucket = "cloud-react-website-bucket"
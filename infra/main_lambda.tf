@@ -29,6 +29,27 @@ resource "aws_lambda_function" "main_lambda" {
 
   source_code_hash = filebase64sha256(var.MAIN_LAMBDA_BOOTSTRAP)
 
+  // read by the lambda at runtime
+  environment {
+    variables = {
+      "TF_VAR_TABLE_NAME"            = var.TABLE_NAME
+      "TF_VAR_GLOBAL_INDEX"          = var.GLOBAL_INDEX
+      "TF_VAR_CONTENT_INDEX"         = var.CONTENT_INDEX
+      "TF_VAR_BATCH_INDEX"           = var.BATCH_INDEX
+      "TF_VAR_OBJECT_KEY_INDEX"      = var.OBJECT_KEY_INDEX
+      "TF_VAR_STATS_TABLE"           = var.STATS_TABLE
+      "TF_VAR_BUCKET_NAME"           = var.BUCKET_NAME
+      "TF_VAR_GENERATOR_LAMBDA"      = var.GENERATOR_LAMBDA
+      "TF_VAR_MAX_RETENTION_DAYS"    = var.MAX_RETENTION_DAYS
+      "TF_VAR_MAX_RENDER_OPERATIONS" = var.MAX_RENDER_OPERATIONS
+      "TF_VAR_MAX_FILE_SIZE"         = var.MAX_FILE_SIZE
+      "TF_VAR_RETENTION_POLICY"      = var.RETENTION_POLICY
+      "TF_VAR_TRASH_RETENTION_HOURS" = var.TRASH_RETENTION_HOURS
+      "TF_VAR_DELETE_CONCURRENCY"    = var.DELETE_CONCURRENCY
+      "TF_VAR_DELETE_RATE"           = var.DELETE_RATE
+    }
+  }
+
   ephemeral_storage {
     size = 512 # Min 512 MB and the Max 10240 MB
   }
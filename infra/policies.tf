@@ -46,8 +46,15 @@ resource "aws_s3_bucket_policy" "allow_access_from_lambdas" {
             "Sid": "LambdaAccessAction",
             "Effect": "Allow",
             "Principal": {"AWS": ["${aws_iam_role.wave_delivery_service_role.arn}", "${aws_iam_role.main_lambda_role.arn}", "${aws_iam_role.bucket_cleaner_role.arn}", "${aws_iam_role.sine_generator_role.arn}"]},
-            "Action": ["s3:GetObject","s3:PutObject","s3:DeleteObject"],
+            "Action": ["s3:GetObject","s3:PutObject","s3:DeleteObject","s3:AbortMultipartUpload"],
             "Resource": "${aws_s3_bucket.cloud-wav-file-bucket.arn}/*"
+        },
+        {
+            "Sid": "LambdaListAction",
+            "Effect": "Allow",
+            "Principal": {"AWS": ["${aws_iam_role.main_lambda_role.arn}"]},
+            "Action": ["s3:ListBucket"],
+            "Resource": "${aws_s3_bucket.cloud-wav-file-bucket.arn}"
         }
     ]
 }
@@ -70,8 +77,10 @@ data "aws_iam_policy_document" "allow_access_to_wave_files" {
       "s3:ListBucket",
       "s3:PutObject",
       "s3:DeleteObject",
+      "s3:AbortMultipartUpload",
       "s3:HeadBucket",
-      "s3:HeadObject"
+      "s3:HeadObject",
+      "s3:GetObjectTagging"
     ]
 
     resources = [
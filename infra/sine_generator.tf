@@ -30,6 +30,27 @@ resource "aws_lambda_function" "sine_generator" {
 
   source_code_hash = filebase64sha256(var.GENERATOR_LAMBDA_BOOTSTRAP)
 
+  // read by the lambda at runtime
+  environment {
+    variables = {
+      "TF_VAR_TABLE_NAME"            = var.TABLE_NAME
+      "TF_VAR_GLOBAL_INDEX"          = var.GLOBAL_INDEX
+      "TF_VAR_CONTENT_INDEX"         = var.CONTENT_INDEX
+      "TF_VAR_BATCH_INDEX"           = var.BATCH_INDEX
+      "TF_VAR_OBJECT_KEY_INDEX"      = var.OBJECT_KEY_INDEX
+      "TF_VAR_STATS_TABLE"           = var.STATS_TABLE
+      "TF_VAR_BUCKET_NAME"           = var.BUCKET_NAME
+      "TF_VAR_GENERATOR_LAMBDA"      = var.GENERATOR_LAMBDA
+      "TF_VAR_MAX_RETENTION_DAYS"    = var.MAX_RETENTION_DAYS
+      "TF_VAR_MAX_RENDER_OPERATIONS" = var.MAX_RENDER_OPERATIONS
+      "TF_VAR_MAX_FILE_SIZE"         = var.MAX_FILE_SIZE
+      "TF_VAR_RETENTION_POLICY"      = var.RETENTION_POLICY
+      "TF_VAR_TRASH_RETENTION_HOURS" = var.TRASH_RETENTION_HOURS
+      "TF_VAR_DELETE_CONCURRENCY"    = var.DELETE_CONCURRENCY
+      "TF_VAR_DELETE_RATE"           = var.DELETE_RATE
+    }
+  }
+
   ephemeral_storage {
     size = 512 
   }
@@ -39,6 +60,11 @@ resource "aws_iam_role_policy_attachment" "sine_generator_right" {
   role       = aws_iam_role.sine_generator_role.name
   policy_arn = aws_iam_policy.dynamodb_write_wave_table_policy.arn
 }
+// the generator reads the item, in order to record the checksums of the file on it
+resource "aws_iam_role_policy_attachment" "sine_generator_right_read" {
+  role       = aws_iam_role.sine_generator_role.name
+  policy_arn = aws_iam_policy.read_and_update_wave_files_db_policy.arn
+}
 resource "aws_iam_role_policy_attachment" "sine_generator_right2" {
   role       = aws_iam_role.sine_generator_role.name
   policy_arn = "arn:aws:iam::aws:policy/AWSLambdaExecute"
@@ -74,9 +100,31 @@ resource "aws_lambda_function" "bucket_cleaner" {
   role          = aws_iam_role.bucket_cleaner_role.arn
   handler       = "bootstrap"
   runtime       = "provided.al2" 
+  timeout       = 300 // large cleanups stop before the timeout and continue on the next run
 
   source_code_hash = filebase64sha256(var.CLEANER_LAMBDA_BOOTSTRAP)
 
+  // read by the lambda at runtime
+  environment {
+    variables = {
+      "TF_VAR_TABLE_NAME"            = var.TABLE_NAME
+      "TF_VAR_GLOBAL_INDEX"          = var.GLOBAL_INDEX
+      "TF_VAR_CONTENT_INDEX"         = var.CONTENT_INDEX
+      "TF_VAR_BATCH_INDEX"           = var.BATCH_INDEX
+      "TF_VAR_OBJECT_KEY_INDEX"      = var.OBJECT_KEY_INDEX
+      "TF_VAR_STATS_TABLE"           = var.STATS_TABLE
+      "TF_VAR_BUCKET_NAME"           = var.BUCKET_NAME
+      "TF_VAR_GENERATOR_LAMBDA"      = var.GENERATOR_LAMBDA
+      "TF_VAR_MAX_RETENTION_DAYS"    = var.MAX_RETENTION_DAYS
+      "TF_VAR_MAX_RENDER_OPERATIONS" = var.MAX_RENDER_OPERATIONS
+      "TF_VAR_MAX_FILE_SIZE"         = var.MAX_FILE_SIZE
+      "TF_VAR_RETENTION_POLICY"      = var.RETENTION_POLICY
+      "TF_VAR_TRASH_RETENTION_HOURS" = var.TRASH_RETENTION_HOURS
+      "TF_VAR_DELETE_CONCURRENCY"    = var.DELETE_CONCURRENCY
+      "TF_VAR_DELETE_RATE"           = var.DELETE_RATE
+    }
+  }
+
   ephemeral_storage {
     size = 512 # Min 512 MB and the Max 10240 MB
   }
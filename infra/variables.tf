@@ -21,9 +21,42 @@ variable BUCKET_NAME {
 }
 variable TABLE_NAME {
 
+}
+variable STATS_TABLE {
+
 }
 variable GLOBAL_INDEX {
 
+}
+variable CONTENT_INDEX {
+
+}
+variable BATCH_INDEX {
+
+}
+variable OBJECT_KEY_INDEX {
+
+}
+variable MAX_RETENTION_DAYS {
+  default = 30
+}
+variable MAX_RENDER_OPERATIONS {
+  default = 500000000
+}
+variable MAX_FILE_SIZE {
+  default = 536870912
+}
+variable RETENTION_POLICY {
+  default = ""
+}
+variable TRASH_RETENTION_HOURS {
+  default = ""
+}
+variable DELETE_CONCURRENCY {
+  default = 4
+}
+variable DELETE_RATE {
+  default = 3000
 }
 variable MAIN_LAMBDA_BOOTSTRAP {
 
@@ -0,0 +1,200 @@
+use std::{error, fmt::Display, fs, io::{self, Cursor, Read, Seek, Write}, path::PathBuf, process};
+
+use clap::Parser;
+use serde_json::{json, Value};
+use sine_generator::{data_formats::{file_size, WavData, WavSpec, Verifiable}, frequency_writer::{self, SineWavSpec}, wav_writer::WavWriter};
+
+#[derive(Debug, PartialEq)]
+struct InvalidSpecErr(String);
+
+impl Display for InvalidSpecErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for InvalidSpecErr {}
+
+/// Generates a wave file locally, from the same json which is sent to the main lambda.
+///
+/// The json contains the fields `wav_spec` and `wav_data`, each of them can be overridden with the flags below.
+/// Without an input file, all fields need to be given as flags.
+#[derive(Parser, Debug)]
+#[clap(version, about)]
+struct Args {
+    /// Json file with the request, `-` reads it from stdin
+    input: Option<PathBuf>,
+
+    /// File to write the wave file to, writes to stdout if not given
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+
+    /// Prints the size and duration of the file, instead of writing it
+    #[clap(long)]
+    info: bool,
+
+    /// Number of channels, 1 or 2
+    #[clap(long)]
+    channels: Option<u16>,
+
+    /// Sample rate in Hz
+    #[clap(long)]
+    sample_rate: Option<u32>,
+
+    /// Bits per sample, 8 or 16
+    #[clap(long)]
+    bits: Option<u16>,
+
+    /// Comma separated list of frequencies in Hz
+    #[clap(long, value_delimiter = ',')]
+    frequencies: Option<Vec<u16>>,
+
+    /// Duration in seconds
+    #[clap(long)]
+    duration: Option<u16>,
+
+    /// Volume between 0 and 1
+    #[clap(long)]
+    volume: Option<f64>,
+}
+
+fn main() {
+    if let Err(e) = run(Args::parse()) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), Box<dyn error::Error>> {
+    let mut request = read_request(args.input.as_ref())?;
+    apply_overrides(&mut request, &args);
+    let (spec, data) = verify_request(&request)?;
+
+    if args.info {
+        print_info(&spec, &data);
+        return Ok(());
+    }
+
+    match &args.output {
+        Some(path) => render(spec, &data, io::BufWriter::new(fs::File::create(path)?))?,
+        None => {
+            // stdout can't seek back to the header, so the file is created in memory first
+            let mut buffer = Cursor::new(vec![]);
+            render(spec, &data, &mut buffer)?;
+            io::stdout().lock().write_all(buffer.get_ref())?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Reads the request from a file, or from stdin if the path is `-`.
+/// Returns an empty request if no path is given, so all fields need to be set with flags.
+fn read_request(input: Option<&PathBuf>) -> Result<Value, Box<dyn error::Error>> {
+    let content = match input {
+        Some(path) if path.to_str() == Some("-") => {
+            let mut content = String::new();
+            io::stdin().read_to_string(&mut content)?;
+            content
+        },
+        Some(path) => fs::read_to_string(path)?,
+        None => return Ok(json!({ "wav_spec": {}, "wav_data": {} })),
+    };
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// Replaces the fields of the request with the values given as flags.
+fn apply_overrides(request: &mut Value, args: &Args) {
+    let spec = [
+        ("number_of_channels", args.channels.map(Value::from)),
+        ("sample_rate", args.sample_rate.map(Value::from)),
+        ("bits_per_sample", args.bits.map(Value::from)),
+    ];
+    let data = [
+        ("frequencies", args.frequencies.clone().map(Value::from)),
+        ("duration", args.duration.map(Value::from)),
+        ("volume", args.volume.map(Value::from)),
+    ];
+
+    for (object, fields) in [("wav_spec", spec), ("wav_data", data)] {
+        for (field, value) in fields {
+            if let Some(value) = value {
+                request[object][field] = value;
+            }
+        }
+    }
+}
+
+/// Applies the same rules as the main lambda.
+fn verify_request(request: &Value) -> Result<(WavSpec, WavData), InvalidSpecErr> {
+    let spec: WavSpec = serde_json::from_value(request["wav_spec"].clone())
+        .map_err(|e| InvalidSpecErr(format!("wav_spec is invalid: {}", e)))?;
+    let data: WavData = serde_json::from_value(request["wav_data"].clone())
+        .map_err(|e| InvalidSpecErr(format!("wav_data is invalid: {}", e)))?;
+
+    if !spec.is_valid() {
+        return Err(InvalidSpecErr("wav_spec contains invalid data, supported are 1 or 2 channels with 8 or 16 bits".to_owned()));
+    }
+    if !data.is_valid() {
+        return Err(InvalidSpecErr("wav_data contains invalid data, duration needs to be between 1 and 1800 seconds, volume between 0 and 1".to_owned()));
+    }
+
+    Ok((spec, data))
+}
+
+fn print_info(spec: &WavSpec, data: &WavData) {
+    let size = file_size(spec, data);
+    println!("channels:        {}", spec.number_of_channels);
+    println!("sample rate:     {} Hz", spec.sample_rate);
+    println!("bits per sample: {}", spec.bits_per_sample);
+    println!("frequencies:     {:?} Hz", data.frequencies);
+    println!("duration:        {} s", data.duration);
+    println!("samples:         {} per channel", data.duration as u64 * spec.sample_rate as u64);
+    println!("size:            {} bytes ({:.2} MiB)", size, size as f64 / (1024. * 1024.));
+}
+
+fn render<W: Write + Seek>(spec: WavSpec, data: &WavData, writer: W) -> Result<(), Box<dyn error::Error>> {
+    let sine_spec = SineWavSpec::new(&spec, data).ok_or_else(|| InvalidSpecErr("Unable to create sine spec".to_owned()))?;
+    frequency_writer::write_wave(sine_spec, WavWriter::new(spec, writer)?)?;
+    Ok(())
+}
+
+#[test]
+fn test_apply_overrides() {
+    let args = Args::parse_from(["sine-generator", "request.json", "--sample-rate", "8000", "--frequencies", "440,660"]);
+    let mut request = json!({
+        "wav_spec": { "number_of_channels": 2, "sample_rate": 44100, "bits_per_sample": 16 },
+        "wav_data": { "frequencies": [1000], "duration": 2, "volume": 0.5 },
+    });
+
+    apply_overrides(&mut request, &args);
+    let (spec, data) = verify_request(&request).unwrap();
+    assert_eq!(spec, WavSpec { number_of_channels: 2, sample_rate: 8000, bits_per_sample: 16 });
+    assert_eq!(data, WavData { frequencies: vec![440, 660], duration: 2, volume: 0.5 });
+}
+
+#[test]
+fn test_verify_request() {
+    let args = Args::parse_from(["sine-generator", "--channels", "1", "--sample-rate", "8000", "--bits", "8"]);
+    let mut request = read_request(None).unwrap();
+    apply_overrides(&mut request, &args);
+    assert!(verify_request(&request).is_err());     // wav_data is missing
+
+    let args = Args::parse_from(["sine-generator", "--frequencies", "440", "--duration", "1", "--volume", "1"]);
+    apply_overrides(&mut request, &args);
+    assert!(verify_request(&request).is_ok());
+
+    request["wav_spec"]["bits_per_sample"] = json!(24);
+    assert!(verify_request(&request).is_err());
+}
+
+#[test]
+fn test_render_matches_file_size() {
+    let spec = WavSpec::new(1, 8001, 8).unwrap();
+    let data = WavData { frequencies: vec![440], duration: 1, volume: 1. };
+    let mut buffer = Cursor::new(vec![]);
+
+    render(spec, &data, &mut buffer).unwrap();
+    assert_eq!(buffer.get_ref().len() as u64, file_size(&spec, &data));
+    assert_eq!(&buffer.get_ref()[0..4], b"RIFF");
+}
@@ -0,0 +1,175 @@
+use std::{fmt::Display, error};
+
+use crate::data_formats::{self, WavData, WavSpec};
+
+/// Sample rates a request can be downgraded to, from the highest to the lowest.
+pub const STANDARD_SAMPLE_RATES: [u32; 7] = [48000, 44100, 32000, 22050, 16000, 11025, 8000];
+
+/// The work needed to render a wave file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderCost {
+    /// Number of samples per channel.
+    pub samples: u64,
+    /// Number of sine waves, which are summed up for each sample.
+    pub components: u64,
+    /// Size of the rendered file in bytes.
+    pub file_size: u64,
+}
+
+impl RenderCost {
+    pub fn new(spec: &WavSpec, data: &WavData) -> Self {
+        RenderCost {
+            samples: data.duration as u64 * spec.sample_rate as u64,
+            components: data.frequencies.len() as u64,
+            file_size: data_formats::file_size(spec, data),
+        }
+    }
+
+    /// Number of sine evaluations, which dominates the render time.
+    /// A file without frequencies is silent, but its samples still need to be written.
+    pub fn operations(&self) -> u64 {
+        self.samples * self.components.max(1)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BudgetErr(String);
+
+impl Display for BudgetErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for BudgetErr {}
+
+/// Limits on the work a single wave file may cause.
+/// The same budget is checked when a request is accepted and again before the file is rendered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderBudget {
+    pub max_operations: u64,
+    pub max_file_size: u64,
+}
+
+impl RenderBudget {
+    /// Returns the cost of the file, or an error describing which limit it exceeds.
+    pub fn check(&self, spec: &WavSpec, data: &WavData) -> Result<RenderCost, BudgetErr> {
+        let cost = RenderCost::new(spec, data);
+        if cost.operations() > self.max_operations {
+            return Err(BudgetErr(format!(
+                "request needs {} operations ({} samples x {} frequencies), the limit is {}",
+                cost.operations(), cost.samples, cost.components, self.max_operations
+            )));
+        }
+        if cost.file_size > self.max_file_size {
+            return Err(BudgetErr(format!(
+                "request results in a file of {} bytes, the limit is {} bytes",
+                cost.file_size, self.max_file_size
+            )));
+        }
+        Ok(cost)
+    }
+
+    /// Checks the summed cost of several files, which are rendered one after another by a single invocation.
+    /// The files of a batch share the budget of a single file, so a batch takes no longer than a single request.
+    pub fn check_total(&self, costs: &[RenderCost]) -> Result<(), BudgetErr> {
+        let operations: u64 = costs.iter().map(RenderCost::operations).sum();
+        if operations > self.max_operations {
+            return Err(BudgetErr(format!(
+                "batch needs {} operations in total, the limit is {}", operations, self.max_operations
+            )));
+        }
+        let file_size: u64 = costs.iter().map(|cost| cost.file_size).sum();
+        if file_size > self.max_file_size {
+            return Err(BudgetErr(format!(
+                "batch results in files of {} bytes in total, the limit is {} bytes", file_size, self.max_file_size
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the spec unchanged if it fits into the budget. Otherwise returns the spec with the highest
+    /// standard sample rate below the requested one, which fits into the budget.
+    /// The sample rate needs to stay above twice the highest frequency, so that all frequencies can still be represented.
+    pub fn downgrade(&self, spec: &WavSpec, data: &WavData) -> Result<WavSpec, BudgetErr> {
+        let exceeded = match self.check(spec, data) {
+            Ok(_) => return Ok(*spec),
+            Err(e) => e,
+        };
+
+        let min_sample_rate = 2 * data.frequencies.iter().copied().max().unwrap_or(0) as u32;
+        STANDARD_SAMPLE_RATES
+            .iter()
+            .filter(|rate| **rate < spec.sample_rate && **rate > min_sample_rate)
+            .map(|rate| WavSpec { sample_rate: *rate, ..*spec })
+            .find(|downgraded| self.check(downgraded, data).is_ok())
+            .ok_or(exceeded)
+    }
+}
+
+#[test]
+fn test_render_cost() {
+    let spec = WavSpec { number_of_channels: 2, sample_rate: 44100, bits_per_sample: 16 };
+    let data = WavData { frequencies: vec![440; 10000], duration: 1800, volume: 1. };
+    let cost = RenderCost::new(&spec, &data);
+
+    assert_eq!(cost.samples, 79_380_000);
+    assert_eq!(cost.components, 10000);
+    assert_eq!(cost.operations(), 793_800_000_000);
+    assert_eq!(cost.file_size, 44 + 79_380_000 * 4);
+
+    let silence = WavData { frequencies: vec![], duration: 2, volume: 1. };
+    assert_eq!(RenderCost::new(&spec, &silence).operations(), 88200);
+}
+
+#[test]
+fn test_check_budget() {
+    let budget = RenderBudget { max_operations: 1_000_000, max_file_size: 100_000 };
+    let spec = WavSpec { number_of_channels: 1, sample_rate: 8000, bits_per_sample: 8 };
+
+    let data = WavData { frequencies: vec![440, 660], duration: 10, volume: 1. };
+    assert_eq!(budget.check(&spec, &data).unwrap().operations(), 160_000);
+
+    let data = WavData { frequencies: vec![440; 20], duration: 10, volume: 1. };
+    assert!(budget.check(&spec, &data).unwrap_err().to_string().contains("1600000 operations"));
+
+    let data = WavData { frequencies: vec![440], duration: 20, volume: 1. };
+    assert!(budget.check(&spec, &data).unwrap_err().to_string().contains("160044 bytes"));
+}
+
+#[test]
+fn test_check_total() {
+    let budget = RenderBudget { max_operations: 1_000_000, max_file_size: 100_000 };
+    let spec = WavSpec { number_of_channels: 1, sample_rate: 8000, bits_per_sample: 8 };
+
+    // each file is within the budget, together they are not
+    let cost = budget.check(&spec, &WavData { frequencies: vec![440; 10], duration: 5, volume: 1. }).unwrap();
+    assert_eq!(budget.check_total(&[cost; 2]), Ok(()));
+    assert!(budget.check_total(&[cost; 3]).unwrap_err().to_string().contains("1200000 operations"));
+
+    let cost = budget.check(&spec, &WavData { frequencies: vec![440], duration: 5, volume: 1. }).unwrap();
+    assert!(budget.check_total(&[cost; 3]).unwrap_err().to_string().contains("120132 bytes"));
+    assert_eq!(budget.check_total(&[]), Ok(()));
+}
+
+#[test]
+fn test_downgrade() {
+    let budget = RenderBudget { max_operations: 1_000_000, max_file_size: u64::MAX };
+    let spec = WavSpec { number_of_channels: 2, sample_rate: 44100, bits_per_sample: 16 };
+
+    let fitting = WavData { frequencies: vec![440], duration: 10, volume: 1. };
+    assert_eq!(budget.downgrade(&spec, &fitting).unwrap(), spec);
+
+    // 10 seconds with 3 frequencies fit up to 33333 samples per second
+    let data = WavData { frequencies: vec![440, 660, 880], duration: 10, volume: 1. };
+    assert_eq!(budget.downgrade(&spec, &data).unwrap().sample_rate, 32000);
+
+    // 16000 would fit, but can't represent 8000 Hz
+    let data = WavData { frequencies: vec![440, 8000], duration: 30, volume: 1. };
+    assert_eq!(budget.downgrade(&spec, &data), Err(budget.check(&spec, &data).unwrap_err()));
+
+    // the sample rate is never raised
+    let spec = WavSpec { sample_rate: 12000, ..spec };
+    let data = WavData { frequencies: vec![440; 10], duration: 10, volume: 1. };
+    assert_eq!(budget.downgrade(&spec, &data).unwrap().sample_rate, 8000);
+}
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 
 /// A struct containing metadata about the Wave file that will be created.
-#[derive(Deserialize, Serialize, Clone, Copy)]
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
 pub struct WavSpec {
     pub number_of_channels: u16,
     pub sample_rate: u32,
@@ -20,11 +20,11 @@ impl WavSpec {
 
 impl Verifiable for WavSpec {
     fn is_valid(&self) -> bool {
-        self.number_of_channels == 1 || self.number_of_channels == 2 || self.bits_per_sample == 8 || self.bits_per_sample == 16
+        (self.number_of_channels == 1 || self.number_of_channels == 2) && (self.bits_per_sample == 8 || self.bits_per_sample == 16)
     }
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct WavData {
     pub frequencies: Vec<u16>,
     pub duration: u16,
@@ -41,6 +41,59 @@ pub trait Verifiable {
     fn is_valid(&self) -> bool;
 }
 
+/// Separator of the fields in legacy file ids.
+pub const LEGACY_ID_SEPARATOR: char = '_';
+
+/// Parses a file id of the legacy form `prefix_channels_rate_bits`, 
+/// which was used before ids were generated independently of the spec.
+/// Returns the prefix (the first segment of the lambda request id) and the encoded `WavSpec`.
+pub fn parse_legacy_id(id: &str) -> Option<(&str, WavSpec)> {
+    let mut fields = id.split(LEGACY_ID_SEPARATOR);
+    let prefix = fields.next().filter(|prefix| !prefix.is_empty())?;
+    let number_of_channels = fields.next()?.parse().ok()?;
+    let sample_rate = fields.next()?.parse().ok()?;
+    let bits_per_sample = fields.next()?.parse().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    
+    WavSpec::new(number_of_channels, sample_rate, bits_per_sample).map(|spec| (prefix, spec))
+}
+
+/// Size of the header of a wave file in bytes.
+pub const HEADER_SIZE: u64 = 44;
+
+/// Returns the size in bytes of the data chunk of the wave file, which is created from the spec and the data.
+/// The data chunk is padded to an even length, as required by the specification.
+pub fn data_size(spec: &WavSpec, data: &WavData) -> u64 {
+    let samples = data.duration as u64 * spec.sample_rate as u64;
+    let data_size = samples * spec.number_of_channels as u64 * spec.bits_per_sample as u64 / 8;
+    data_size + data_size % 2
+}
+
+/// Returns the size in bytes of the wave file, which is created from the spec and the data.
+pub fn file_size(spec: &WavSpec, data: &WavData) -> u64 {
+    HEADER_SIZE + data_size(spec, data)
+}
+
+/// Returns a textual representation of a request, which is the same for all requests
+/// that result in an identical wave file.
+/// 
+/// The frequencies get sorted, since the order in which they are added up has no influence 
+/// on the written samples. Duplicates are kept, as they change the weighting of a frequency.
+pub fn canonical_form(spec: &WavSpec, data: &WavData) -> String {
+    let mut frequencies = data.frequencies.clone();
+    frequencies.sort_unstable();
+    let frequencies = frequencies
+        .iter()
+        .map(|f| f.to_string())
+        .collect::<Vec<String>>()
+        .join(",");
+
+    format!("ch={};rate={};bits={};duration={};volume={};frequencies={}", 
+            spec.number_of_channels, spec.sample_rate, spec.bits_per_sample, data.duration, data.volume, frequencies)
+}
+
 #[test]
 fn new_is_some() {
     let spec = WavSpec::new(1, 44100, 8);
@@ -59,4 +112,52 @@ fn new_is_none() {
     assert!(spec.is_none());
     let spec = WavSpec::new(1, 44100, 24);
     assert!(spec.is_none());
-}
\ No newline at end of file
+}
+
+#[test]
+fn is_valid_checks_channels_and_bits() {
+    assert!(WavSpec { number_of_channels: 2, sample_rate: 44100, bits_per_sample: 16 }.is_valid());
+    assert!(!WavSpec { number_of_channels: 1, sample_rate: 44100, bits_per_sample: 24 }.is_valid());
+    assert!(!WavSpec { number_of_channels: 3, sample_rate: 44100, bits_per_sample: 8 }.is_valid());
+}
+
+#[test]
+fn file_size_is_padded() {
+    let data = WavData { frequencies: vec![440], duration: 1, volume: 1. };
+    assert_eq!(file_size(&WavSpec::new(2, 44100, 16).unwrap(), &data), 44 + 176400);
+    assert_eq!(file_size(&WavSpec::new(1, 11025, 8).unwrap(), &data), 44 + 11026);
+}
+
+#[test]
+fn canonical_form_ignores_frequency_order() {
+    let spec = WavSpec::new(2, 44100, 16).unwrap();
+    let lhs = WavData { frequencies: vec![660, 440, 880], duration: 10, volume: 0.9 };
+    let rhs = WavData { frequencies: vec![440, 880, 660], duration: 10, volume: 0.9 };
+    assert_eq!(canonical_form(&spec, &lhs), canonical_form(&spec, &rhs));
+}
+
+#[test]
+fn canonical_form_keeps_duplicates() {
+    let spec = WavSpec::new(1, 8000, 8).unwrap();
+    let lhs = WavData { frequencies: vec![440, 440, 660], duration: 2, volume: 1. };
+    let rhs = WavData { frequencies: vec![440, 660], duration: 2, volume: 1. };
+    assert_ne!(canonical_form(&spec, &lhs), canonical_form(&spec, &rhs));
+}
+
+#[test]
+fn parse_legacy_id_is_some() {
+    let (prefix, spec) = parse_legacy_id("567fab82_2_23000_16").unwrap();
+    assert_eq!(prefix, "567fab82");
+    assert_eq!(spec.number_of_channels, 2);
+    assert_eq!(spec.sample_rate, 23000);
+    assert_eq!(spec.bits_per_sample, 16);
+}
+
+#[test]
+fn parse_legacy_id_is_none() {
+    assert!(parse_legacy_id("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E").is_none());
+    assert!(parse_legacy_id("567fab82_2_23000").is_none());
+    assert!(parse_legacy_id("567fab82_2_23000_16_1").is_none());
+    assert!(parse_legacy_id("567fab82_3_23000_16").is_none());
+    assert!(parse_legacy_id("_2_23000_16").is_none());
+}
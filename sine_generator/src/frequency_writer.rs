@@ -21,7 +21,7 @@ impl<'spec> SineWavSpec<'spec> {
     }
 }
 
-pub fn write_wave<W: std::io::Write + std::io::Seek>(sine_spec: SineWavSpec, mut wav_writer: WavWriter<W>) -> io::Result<()> {
+pub fn write_wave<W: std::io::Write>(sine_spec: SineWavSpec, mut wav_writer: WavWriter<W>) -> io::Result<()> {
     //what to do with volume?
     
     let spec = sine_spec.wav_spec;
@@ -50,10 +50,11 @@ pub fn write_wave<W: std::io::Write + std::io::Seek>(sine_spec: SineWavSpec, mut
             sample += f(x);
         }
         let scaled_sample = data.volume * sample as f64 / data.frequencies.len() as f64 ;
+        // a writer that failed won't recover, so there is no point in calculating the remaining samples
         if sine_spec.wav_spec.bits_per_sample == 16 {
-            let _ = wav_writer.write_sample(scaled_sample as i16);
+            wav_writer.write_sample(scaled_sample as i16)?;
         } else {
-            let _ = wav_writer.write_sample(scaled_sample as u8);
+            wav_writer.write_sample(scaled_sample as u8)?;
         }
     }
 
@@ -7,6 +7,8 @@ pub mod wav_writer;
 pub mod frequency_writer;
 #[cfg(feature = "data")]
 pub mod data_formats;
+#[cfg(feature = "data")]
+pub mod cost;
 
 
 pub trait Sample {
@@ -1,7 +1,7 @@
 use std::io::{Seek, Write, self, BufWriter, SeekFrom };
 use std::fs::File;
 
-use crate::{ data_formats::WavSpec, Sample };
+use crate::{ data_formats::{self, WavData, WavSpec}, Sample };
 
 /// Provides functionality in order to write numbers in lesser endian 
 /// which is required for the data fields
@@ -87,7 +87,7 @@ fn write_le_u32() {
 /// The `WavWriter` is the main interface used by the application
 /// to create a Wave file and write data into it.
 pub struct WavWriter<W> 
-where W: Write + Seek
+where W: Write
 {
     writer: ChunkWriter<W>,
 }
@@ -95,6 +95,28 @@ where W: Write + Seek
 impl<W> WavWriter<W>
 where W: Write + Seek
 {
+    /// Creates a writer, which writes the wave file into `writer`, starting with the header.
+    /// The sizes in the header are updated once all samples are written.
+    pub fn new(spec: WavSpec, writer: W) -> io::Result<WavWriter<W>> {
+        Ok(WavWriter {
+            writer: ChunkWriter::initialize_with_spec(spec, writer)?
+        })
+    }
+}
+
+impl<W> WavWriter<W>
+where W: Write
+{
+    /// Creates a writer for the samples of `data`, which writes the final sizes into the header right away,
+    /// so `writer` doesn't need to support seeking. Finalizing fails if a different amount of samples was written.
+    pub fn new_with_data(spec: WavSpec, data: &WavData, writer: W) -> io::Result<WavWriter<W>> {
+        let length = u32::try_from(data_formats::data_size(&spec, data))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "The data does not fit into a wave file"))?;
+        Ok(WavWriter {
+            writer: ChunkWriter::initialize_with_length(spec, writer, length)?
+        })
+    }
+
     #[inline(always)]
     pub fn write_sample<S: Sample>(&mut self, value: S) -> io::Result<u32> {
         self.writer.write(value)
@@ -117,14 +139,23 @@ impl WavWriter<BufWriter<File>> {
     }
 }
 
+/// Determines how the sizes in the header are set, once all samples are written.
+enum HeaderSizes<W> {
+    /// The sizes are written at the end, by seeking back to the header.
+    Update(fn(&mut W, u32) -> io::Result<()>),
+    /// The sizes were written up front, the data needs to have exactly this length.
+    Announced(u32),
+}
+
 /// The internal writer used by the `WavWriter`, which provides all the functionality of creating a 
 /// Wave file and writing all the necessary data, and maintaining the state of the Data Chunk. 
 struct ChunkWriter<W> 
-where W: Write + Seek
+where W: Write
 {
     spec: WavSpec,
     writer: W,
     data_state: DataState,
+    header_sizes: HeaderSizes<W>,
 }
 
 impl<W> ChunkWriter<W>
@@ -134,14 +165,50 @@ where W: Write + Seek
     /// Upon initialization, it will immediately write the fields
     /// of the Wave file header, except for file sizes.
     fn initialize_with_spec(spec: WavSpec, writer: W) -> Result<ChunkWriter<W>, io::Error> {
-        let mut chunk_writer = Self { spec, writer, data_state: DataState { bytes_written: 0, dirty: true } };
-        chunk_writer.write_header()?;
+        let mut chunk_writer = Self { 
+            spec, 
+            writer, 
+            data_state: DataState { bytes_written: 0, dirty: true },
+            header_sizes: HeaderSizes::Update(update_chunk_size::<W>),
+        };
+        chunk_writer.write_header(0)?;
+        Ok(chunk_writer)
+    }
+}
+
+/// Update the chunk size fields in the header
+/// length is the total amount of sample data written
+fn update_chunk_size<W: Write + Seek>(writer: &mut W, length: u32) -> io::Result<()> {
+    writer.seek(SeekFrom::Start(4))?;      // update ChunkSize field
+    writer.write_le_u32(length + 36)?;
+    writer.seek(SeekFrom::Start(40))?;     // update Subchunk2Size field
+    writer.write_le_u32(length)?;
+    writer.seek(SeekFrom::End(0))?;
+    Ok(())
+}
+
+impl<W> ChunkWriter<W>
+where W: Write 
+{
+    /// Initializes a new `ChunkWriter`, which writes the complete header
+    /// including the sizes for `length` bytes of sample data.
+    fn initialize_with_length(spec: WavSpec, writer: W, length: u32) -> Result<ChunkWriter<W>, io::Error> {
+        let mut chunk_writer = Self { 
+            spec, 
+            writer, 
+            data_state: DataState { bytes_written: 0, dirty: true },
+            header_sizes: HeaderSizes::Announced(length),
+        };
+        chunk_writer.write_header(length)?;
         Ok(chunk_writer)
     }
 
     /// Writes the Wave header into the buffer.
-    fn write_header(&mut self) -> io::Result<()> {
-        self.writer.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+    /// `length` is the size of the data chunk, which is 0 if it isn't known yet.
+    fn write_header(&mut self, length: u32) -> io::Result<()> {
+        self.writer.write_all(b"RIFF")?;
+        self.writer.write_le_u32(if length == 0 { 0 } else { length + 36 })?;  // ChunkSize
+        self.writer.write_all(b"WAVEfmt ")?;
         self.writer.write_le_u32(16)?;  // Subchunk2 Size
         self.writer.write_le_u16(1)?;   // 1 = PCM
         self.writer.write_le_u16(self.spec.number_of_channels)?;
@@ -149,19 +216,8 @@ where W: Write + Seek
         self.writer.write_le_u32(self.spec.sample_rate * self.spec.number_of_channels as u32 * self.spec.bits_per_sample as u32 / 8)?;
         self.writer.write_le_u16(self.spec.number_of_channels * self.spec.bits_per_sample / 8)?;
         self.writer.write_le_u16(self.spec.bits_per_sample)?;
-        self.writer.write_all(b"data\0\0\0\0")?;
-        Ok(())
-    }
-
-    /// Update the chunk size fields in the header
-    /// length is the total amount of sample data written
-    fn update_chunk_size(&mut self) -> io::Result<()> {
-        let length = self.data_state.bytes_written;
-        self.writer.seek(SeekFrom::Start(4))?;      // update ChunkSize field
-        self.writer.write_le_u32(length + 36)?;
-        self.writer.seek(SeekFrom::Start(40))?;     // update Subchunk2Size field
-        self.writer.write_le_u32(length)?;
-        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.write_all(b"data")?;
+        self.writer.write_le_u32(length)?;          // Subchunk2Size
         Ok(())
     }
 
@@ -183,14 +239,21 @@ where W: Write + Seek
     /// Then flushes the writer
     fn flush(&mut self) -> io::Result<()> {
         if !self.data_state.is_valid_length() {
-            self.data_state.bytes_written += match self.spec.bits_per_sample {
-                8 => self.write(0_u8),
-                16 => self.write(0_i16),
-                _ => panic!("Only 8 or 16 bit are supported"),
-            }?;
+            // the filler byte is a single byte, independent of the bits per sample
+            self.writer.write_u8(0)?;
+            self.data_state.bytes_written += 1;
         }
         self.data_state.dirty = false;
-        self.update_chunk_size()?;
+        let length = self.data_state.bytes_written;
+        match self.header_sizes {
+            HeaderSizes::Update(update) => update(&mut self.writer, length)?,
+            HeaderSizes::Announced(announced) if announced != length => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData, 
+                    format!("The header announced {} bytes of data, but {} were written", announced, length)));
+            },
+            HeaderSizes::Announced(_) => (),
+        }
         self.writer.flush()?;
         Ok(())
     }
@@ -227,11 +290,42 @@ fn write_header() {
     assert_eq!(data[40..44], 0_u32.to_be_bytes());                  // Subchunk2Size = 0
 }
 
+#[test]
+fn write_with_data_matches_seeking_writer() {
+    use std::io::Cursor;
+
+    let spec = WavSpec::new(1, 11025, 8).unwrap();
+    let data = WavData { frequencies: vec![440], duration: 1, volume: 1. };
+    fn write_samples<W: Write>(writer: &mut WavWriter<W>) {
+        for i in 0..11025_u32 {
+            writer.write_sample((i % 256) as u8).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    let mut seeking = Cursor::new(vec![]);
+    write_samples(&mut WavWriter::new(spec, &mut seeking).unwrap());
+    let mut sequential = vec![];
+    write_samples(&mut WavWriter::new_with_data(spec, &data, &mut sequential).unwrap());
+
+    assert_eq!(sequential.len(), 44 + 11026);
+    assert_eq!(seeking.into_inner(), sequential);
+}
+
+#[test]
+fn write_with_data_checks_length() {
+    let spec = WavSpec::new(2, 8000, 16).unwrap();
+    let data = WavData { frequencies: vec![440], duration: 1, volume: 1. };
+    let mut writer = WavWriter::new_with_data(spec, &data, vec![]).unwrap();
+    writer.write_sample(0_i16).unwrap();
+    assert_eq!(writer.finalize().unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
 /// Upon dropping the `ChunkWriter`, it is necessary 
 /// to update the length fields, and verify if the 
 /// data size is valid
 impl<W> Drop for ChunkWriter<W> 
-where W: Write + Seek
+where W: Write
 {
     fn drop(&mut self) {
         if self.data_state.dirty {
@@ -0,0 +1,65 @@
+use std::str::FromStr;
+
+use crate::StoreErr;
+
+/// The archive formats of a bundle, which contains all files of a batch request and is stored under the id of the batch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BundleFormat {
+    Zip,
+    TarGz,
+}
+
+impl BundleFormat {
+    pub const ALL: [BundleFormat; 2] = [BundleFormat::Zip, BundleFormat::TarGz];
+
+    /// The name of the format in requests and events.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BundleFormat::Zip => "zip",
+            BundleFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BundleFormat::Zip => ".zip",
+            BundleFormat::TarGz => ".tar.gz",
+        }
+    }
+
+    /// Returns the key of the bundle of a batch.
+    pub fn key(&self, batch_id: &str) -> String {
+        batch_id.to_owned() + self.extension()
+    }
+
+    /// Splits the key of a bundle into the id of its batch and its format.
+    pub fn parse_key(key: &str) -> Option<(&str, BundleFormat)> {
+        Self::ALL
+            .iter()
+            .find_map(|format| key.strip_suffix(format.extension()).map(|batch_id| (batch_id, *format)))
+    }
+}
+
+impl FromStr for BundleFormat {
+    type Err = StoreErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| StoreErr::new(&format!("unknown bundle format: {}", s)))
+    }
+}
+
+#[test]
+fn test_parse_key() {
+    assert_eq!(BundleFormat::parse_key("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E.zip"), Some(("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E", BundleFormat::Zip)));
+    assert_eq!(BundleFormat::parse_key("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E.tar.gz"), Some(("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E", BundleFormat::TarGz)));
+    assert_eq!(BundleFormat::parse_key("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E.wav"), None);
+
+    for format in BundleFormat::ALL {
+        assert_eq!(format.name().parse(), Ok(format));
+        assert_eq!(BundleFormat::parse_key(&format.key("batch")), Some(("batch", format)));
+    }
+    assert!("rar".parse::<BundleFormat>().is_err());
+}
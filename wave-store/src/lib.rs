@@ -0,0 +1,19 @@
+//! Storage of the generated wav files.
+//!
+//! The lambdas access the files through the `WaveStore` trait, so the pipeline can run against
+//! the S3 bucket when deployed, or against a local directory during development and in tests.
+
+mod store;
+mod bundle;
+mod s3;
+mod local;
+mod memory;
+mod multipart;
+pub mod time;
+
+pub use store::{backoff, BatchDeleteResult, Checksums, ListPage, ObjectInfo, ObjectUpload, StoreErr, WaveStore, MAX_BATCH_SIZE};
+pub use bundle::BundleFormat;
+pub use s3::S3Store;
+pub use local::LocalStore;
+pub use memory::InMemoryStore;
+pub use multipart::UploadConfig;
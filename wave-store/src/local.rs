@@ -0,0 +1,280 @@
+use std::{fs, io::{self, Write}, path::{Path, PathBuf}};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{BatchDeleteResult, ListPage, ObjectInfo, ObjectUpload, StoreErr, WaveStore};
+
+const DEFAULT_PAGE_SIZE: usize = 1000;
+
+impl From<io::Error> for StoreErr {
+    fn from(err: io::Error) -> Self {
+        StoreErr::new(&err.to_string())
+    }
+}
+
+/// A `WaveStore` which keeps each object as a file in a local directory.
+/// Keys containing `/` are stored in subdirectories, just like they are displayed by S3.
+/// Files starting with a `.` are not listed, they hold uploads in progress.
+///
+/// Intended for development and tests, the files are accessed with blocking calls.
+pub struct LocalStore {
+    root: PathBuf,
+    page_size: usize,
+}
+
+impl LocalStore {
+    /// Creates a store in the directory `root`, which is created when the first object is stored.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        LocalStore { root: root.into(), page_size: DEFAULT_PAGE_SIZE }
+    }
+
+    /// Sets the maximum number of objects returned per page, S3 returns up to 1000.
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Maps a key to its file, rejecting keys that would point outside of the root directory.
+    fn path_of(&self, key: &str) -> Result<PathBuf, StoreErr> {
+        let is_valid = !key.contains('\\')
+            && key.split('/').all(|segment| !segment.is_empty() && segment != "." && segment != "..");
+        if !is_valid {
+            return Err(StoreErr::new(&format!("invalid key: {}", key)));
+        }
+        Ok(key.split('/').fold(self.root.clone(), |path, segment| path.join(segment)))
+    }
+
+    /// Collects the keys of all files below `dir`.
+    fn collect_keys(dir: &Path, prefix: &str, keys: &mut Vec<String>) -> Result<(), StoreErr> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            let key = format!("{}{}", prefix, name);
+            if entry.file_type()?.is_dir() {
+                Self::collect_keys(&entry.path(), &(key + "/"), keys)?;
+            } else {
+                keys.push(key);
+            }
+        }
+        Ok(())
+    }
+
+    fn object_info(key: &str, path: &Path) -> Result<ObjectInfo, StoreErr> {
+        let metadata = fs::metadata(path)?;
+        Ok(ObjectInfo {
+            key: key.to_owned(),
+            size: metadata.len() as i64,
+            last_modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+        })
+    }
+}
+
+/// An upload into a hidden file next to the object, which is renamed once the upload is complete.
+struct LocalUpload {
+    file: fs::File,
+    upload_path: PathBuf,
+    path: PathBuf,
+}
+
+#[async_trait]
+impl ObjectUpload for LocalUpload {
+    async fn write(&mut self, data: &[u8]) -> Result<(), StoreErr> {
+        self.file.write_all(data)?;
+        Ok(())
+    }
+
+    async fn complete(self: Box<Self>) -> Result<(), StoreErr> {
+        self.file.sync_all()?;
+        fs::rename(&self.upload_path, &self.path)?;
+        Ok(())
+    }
+
+    async fn abort(self: Box<Self>) -> Result<(), StoreErr> {
+        fs::remove_file(&self.upload_path)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WaveStore for LocalStore {
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), StoreErr> {
+        let path = self.path_of(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, body)?;
+        Ok(())
+    }
+
+    async fn start_upload(&self, key: &str) -> Result<Box<dyn ObjectUpload + '_>, StoreErr> {
+        let path = self.path_of(key)?;
+        let parent = path.parent().unwrap_or(&self.root);
+        fs::create_dir_all(parent)?;
+
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        let upload_path = parent.join(format!(".{}.upload", file_name));
+        let file = fs::File::create(&upload_path)?;
+        Ok(Box::new(LocalUpload { file, upload_path, path }))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreErr> {
+        match fs::read(self.path_of(key)?) {
+            Ok(body) => Ok(Some(body)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>, StoreErr> {
+        let path = self.path_of(key)?;
+        match path.is_file() {
+            true => Ok(Some(Self::object_info(key, &path)?)),
+            false => Ok(None),
+        }
+    }
+
+    async fn list(&self, prefix: Option<&str>, continuation_token: Option<&str>) -> Result<ListPage, StoreErr> {
+        let mut keys = vec![];
+        if self.root.is_dir() {
+            Self::collect_keys(&self.root, "", &mut keys)?;
+        }
+        keys.sort();
+
+        // the token is the last key of the previous page
+        let mut keys = keys
+            .into_iter()
+            .filter(|key| key.starts_with(prefix.unwrap_or_default()))
+            .skip_while(|key| matches!(continuation_token, Some(token) if key.as_str() <= token));
+
+        let mut page = ListPage::default();
+        for key in keys.by_ref().take(self.page_size) {
+            let path = self.path_of(&key)?;
+            page.objects.push(Self::object_info(&key, &path)?);
+        }
+        if keys.next().is_some() {
+            page.next_token = page.objects.last().map(|object| object.key.clone());
+        }
+
+        Ok(page)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StoreErr> {
+        match fs::remove_file(self.path_of(key)?) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn delete_batch(&self, keys: &[String]) -> Result<BatchDeleteResult, StoreErr> {
+        let mut result = BatchDeleteResult::default();
+        for key in keys {
+            match self.delete(key).await {
+                Ok(_) => result.deleted.push(key.clone()),
+                Err(e) => result.failed.push((key.clone(), e)),
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_put_get_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalStore::new(dir.path());
+
+        store.put("a.wav", vec![1, 2, 3]).await.unwrap();
+        assert_eq!(store.get("a.wav").await.unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(store.head("a.wav").await.unwrap().unwrap().size, 3);
+        assert!(store.head("a.wav").await.unwrap().unwrap().last_modified.is_some());
+
+        store.delete("a.wav").await.unwrap();
+        assert_eq!(store.get("a.wav").await.unwrap(), None);
+        assert_eq!(store.head("a.wav").await.unwrap(), None);
+        assert!(store.delete("a.wav").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalStore::new(dir.path().join("store"));
+
+        for key in ["", "../a.wav", "trash/../../a.wav", "/a.wav", "trash//a.wav", "a\\b.wav"] {
+            assert!(store.put(key, vec![]).await.is_err(), "{} should be invalid", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalStore::new(dir.path()).with_page_size(2);
+        assert_eq!(store.list(None, None).await.unwrap(), ListPage::default());
+
+        for key in ["c.wav", "a.wav", "trash/b.wav", "b.wav", "trash/a.wav"] {
+            store.put(key, vec![0]).await.unwrap();
+        }
+
+        let first = store.list(None, None).await.unwrap();
+        let keys: Vec<&str> = first.objects.iter().map(|object| object.key.as_str()).collect();
+        assert_eq!(keys, vec!["a.wav", "b.wav"]);
+        assert_eq!(first.next_token.as_deref(), Some("b.wav"));
+
+        let all: Vec<String> = store.list_all(None).await.unwrap().into_iter().map(|object| object.key).collect();
+        assert_eq!(all, vec!["a.wav", "b.wav", "c.wav", "trash/a.wav", "trash/b.wav"]);
+
+        let trash = store.list(Some("trash/"), None).await.unwrap();
+        assert_eq!(trash.objects.len(), 2);
+        assert_eq!(trash.next_token, None);
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalStore::new(dir.path());
+
+        let mut upload = store.start_upload("bundles/a.zip").await.unwrap();
+        upload.write(&[1, 2]).await.unwrap();
+        upload.write(&[3]).await.unwrap();
+        // the object only becomes visible after the upload is complete
+        assert_eq!(store.head("bundles/a.zip").await.unwrap(), None);
+        assert_eq!(store.list_all(None).await.unwrap(), vec![]);
+        upload.complete().await.unwrap();
+        assert_eq!(store.get("bundles/a.zip").await.unwrap(), Some(vec![1, 2, 3]));
+
+        let mut upload = store.start_upload("b.zip").await.unwrap();
+        upload.write(&[1]).await.unwrap();
+        upload.abort().await.unwrap();
+        assert_eq!(store.head("b.zip").await.unwrap(), None);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_delete_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = LocalStore::new(dir.path());
+        store.put("a.wav", vec![0]).await.unwrap();
+        store.put("b.wav", vec![0]).await.unwrap();
+
+        let keys = vec!["a.wav".to_owned(), "../b.wav".to_owned(), "missing.wav".to_owned()];
+        let result = store.delete_batch(&keys).await.unwrap();
+
+        assert_eq!(result.deleted, vec!["a.wav", "missing.wav"]);
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(result.failed[0].0, "../b.wav");
+        assert_eq!(store.list_all(None).await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,164 @@
+use std::{collections::{BTreeMap, HashMap}, sync::Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{BatchDeleteResult, ListPage, ObjectInfo, ObjectUpload, StoreErr, WaveStore};
+
+const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// The objects by their key, together with the time they were stored.
+type Objects = BTreeMap<String, (Vec<u8>, DateTime<Utc>)>;
+
+/// A `WaveStore` which keeps all objects in memory.
+/// Intended for tests, or for running the pipeline without persisting any files.
+pub struct InMemoryStore {
+    objects: Mutex<Objects>,
+    tags: Mutex<HashMap<String, HashMap<String, String>>>,
+    page_size: usize,
+}
+
+impl Default for InMemoryStore {
+    fn default() -> Self {
+        InMemoryStore { objects: Mutex::default(), tags: Mutex::default(), page_size: DEFAULT_PAGE_SIZE }
+    }
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum number of objects returned per page, S3 returns up to 1000.
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Stores an object as if it was written at `last_modified`, e.g. to test retention without waiting.
+    pub fn put_with_last_modified(&self, key: &str, body: Vec<u8>, last_modified: DateTime<Utc>) {
+        self.lock().insert(key.to_owned(), (body, last_modified));
+    }
+
+    /// Replaces the tags of an object, like the tags set on an object in S3.
+    pub fn set_tags(&self, key: &str, tags: &[(&str, &str)]) {
+        let tags = tags.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        self.tags.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(key.to_owned(), tags);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Objects> {
+        // a panic while holding the lock can't leave the map in an inconsistent state
+        self.objects.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Collects the written data, and inserts the object once the upload is complete.
+struct InMemoryUpload<'a> {
+    store: &'a InMemoryStore,
+    key: String,
+    body: Vec<u8>,
+}
+
+#[async_trait]
+impl ObjectUpload for InMemoryUpload<'_> {
+    async fn write(&mut self, data: &[u8]) -> Result<(), StoreErr> {
+        self.body.extend_from_slice(data);
+        Ok(())
+    }
+
+    async fn complete(self: Box<Self>) -> Result<(), StoreErr> {
+        self.store.put(&self.key, self.body).await
+    }
+
+    async fn abort(self: Box<Self>) -> Result<(), StoreErr> {
+        Ok(())
+    }
+}
+
+fn object_info(key: &str, body: &[u8], last_modified: DateTime<Utc>) -> ObjectInfo {
+    ObjectInfo { key: key.to_owned(), size: body.len() as i64, last_modified: Some(last_modified) }
+}
+
+#[async_trait]
+impl WaveStore for InMemoryStore {
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), StoreErr> {
+        self.lock().insert(key.to_owned(), (body, Utc::now()));
+        Ok(())
+    }
+
+    async fn start_upload(&self, key: &str) -> Result<Box<dyn ObjectUpload + '_>, StoreErr> {
+        Ok(Box::new(InMemoryUpload { store: self, key: key.to_owned(), body: vec![] }))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreErr> {
+        Ok(self.lock().get(key).map(|(body, _)| body.clone()))
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>, StoreErr> {
+        Ok(self.lock().get(key).map(|(body, last_modified)| object_info(key, body, *last_modified)))
+    }
+
+    async fn get_tags(&self, key: &str) -> Result<HashMap<String, String>, StoreErr> {
+        let tags = self.tags.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(tags.get(key).cloned().unwrap_or_default())
+    }
+
+    async fn list(&self, prefix: Option<&str>, continuation_token: Option<&str>) -> Result<ListPage, StoreErr> {
+        let objects = self.lock();
+
+        // the token is the last key of the previous page
+        let mut matching = objects
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix.unwrap_or_default()))
+            .skip_while(|(key, _)| matches!(continuation_token, Some(token) if key.as_str() <= token));
+
+        let mut page = ListPage::default();
+        for (key, (body, last_modified)) in matching.by_ref().take(self.page_size) {
+            page.objects.push(object_info(key, body, *last_modified));
+        }
+        if matching.next().is_some() {
+            page.next_token = page.objects.last().map(|object| object.key.clone());
+        }
+
+        Ok(page)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StoreErr> {
+        self.lock().remove(key);
+        Ok(())
+    }
+
+    async fn delete_batch(&self, keys: &[String]) -> Result<BatchDeleteResult, StoreErr> {
+        let mut objects = self.lock();
+        for key in keys {
+            objects.remove(key);
+        }
+        Ok(BatchDeleteResult { deleted: keys.to_vec(), failed: vec![] })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_list_pages() {
+        let store = InMemoryStore::new().with_page_size(2);
+        for key in ["c.wav", "a.wav", "trash/a.wav", "b.wav"] {
+            store.put(key, vec![0, 1]).await.unwrap();
+        }
+
+        let first = store.list(None, None).await.unwrap();
+        assert_eq!(first.objects.len(), 2);
+        assert_eq!(first.next_token.as_deref(), Some("b.wav"));
+
+        let second = store.list(None, first.next_token.as_deref()).await.unwrap();
+        let keys: Vec<&str> = second.objects.iter().map(|object| object.key.as_str()).collect();
+        assert_eq!(keys, vec!["c.wav", "trash/a.wav"]);
+        assert_eq!(second.next_token, None);
+
+        store.delete_batch(&["a.wav".to_owned(), "trash/a.wav".to_owned()]).await.unwrap();
+        assert_eq!(store.list_all(Some("trash/")).await.unwrap(), vec![]);
+        assert_eq!(store.head("b.wav").await.unwrap().unwrap().size, 2);
+    }
+}
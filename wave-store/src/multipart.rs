@@ -0,0 +1,482 @@
+//! Uploads large files in parts, several of them at the same time.
+//!
+//! Each part is retried on its own with a jittered backoff, so a transient error doesn't restart the whole upload.
+//! An upload that fails is aborted, so S3 doesn't keep the parts uploaded so far.
+//! Objects too large for a single copy are copied in parts the same way, see `copy_in_parts`.
+
+use std::{future::Future, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use futures_util::{stream::{self, FuturesUnordered}, StreamExt};
+use tokio::task::JoinHandle;
+use tracing::{info, warn, error};
+
+use crate::{backoff, ObjectUpload, StoreErr};
+
+/// S3 doesn't allow more parts in a single upload.
+const MAX_PARTS: i32 = 10000;
+
+/// Size of the parts of a copy. The parts of a copy are never held in memory, so they can be a lot larger than
+/// the parts of an upload. Objects up to the maximum size of 5 TiB fit into `MAX_PARTS` of them.
+pub(crate) const COPY_PART_SIZE: i64 = 1024 * 1024 * 1024;
+
+/// The requests of a multipart upload, as offered by S3.
+/// Kept apart from the `S3Store`, so the uploader can be tested without a bucket.
+#[async_trait]
+pub(crate) trait MultipartApi: Send + Sync {
+    /// Starts an upload and returns its id.
+    async fn create(&self, key: &str) -> Result<String, StoreErr>;
+
+    /// Uploads a part and returns its ETag. Part numbers start at 1.
+    async fn upload_part(&self, key: &str, upload_id: &str, part_number: i32, body: Vec<u8>) -> Result<String, StoreErr>;
+
+    /// Copies the bytes `first..=last` of the object `source` into a part and returns its ETag.
+    async fn copy_part(&self, key: &str, upload_id: &str, part_number: i32, source: &str, first: i64, last: i64)
+    -> Result<String, StoreErr>;
+
+    /// Puts the parts together in the order of their numbers.
+    async fn complete(&self, key: &str, upload_id: &str, parts: Vec<CompletedPart>) -> Result<(), StoreErr>;
+
+    /// Discards all parts of the upload.
+    async fn abort(&self, key: &str, upload_id: &str) -> Result<(), StoreErr>;
+}
+
+/// A part, which was uploaded successfully.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CompletedPart {
+    pub part_number: i32,
+    pub e_tag: String,
+}
+
+/// Determines how files are split up and uploaded.
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    /// Size of all parts but the last one, S3 requires at least 5 MiB.
+    pub part_size: usize,
+    /// Number of parts, which are uploaded at the same time.
+    pub concurrency: usize,
+    /// Number of times a part is retried, before the upload fails.
+    pub max_retries: u32,
+    /// Delay before the first retry of a part, which is doubled for each following retry, see `backoff`.
+    pub base_delay: Duration,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        UploadConfig {
+            part_size: 8 * 1024 * 1024,
+            concurrency: 4,
+            max_retries: 3,
+            base_delay: Duration::from_millis(200),
+        }
+    }
+}
+
+/// An upload, which sends full parts while the next ones are written.
+///
+/// Each part is uploaded in its own task, at most `concurrency` parts at the same time.
+/// Writing waits until one of them is done, if all slots are taken.
+/// The upload is aborted as soon as a part fails for good, or when completing it fails.
+pub(crate) struct MultipartUploader {
+    api: Arc<dyn MultipartApi>,
+    config: UploadConfig,
+    key: String,
+    upload_id: String,
+    buffer: Vec<u8>,
+    next_part_number: i32,
+    in_flight: FuturesUnordered<JoinHandle<Result<CompletedPart, StoreErr>>>,
+    completed: Vec<CompletedPart>,
+    is_aborted: bool,
+}
+
+impl MultipartUploader {
+    pub(crate) async fn start(api: Arc<dyn MultipartApi>, key: &str, config: UploadConfig) -> Result<MultipartUploader, StoreErr> {
+        let upload_id = api.create(key).await?;
+        info!("Started upload {} of {}", upload_id, key);
+        Ok(MultipartUploader {
+            api,
+            config,
+            key: key.to_owned(),
+            upload_id,
+            buffer: vec![],
+            next_part_number: 1,
+            in_flight: FuturesUnordered::new(),
+            completed: vec![],
+            is_aborted: false,
+        })
+    }
+
+    /// Starts uploading a part, after waiting for a free slot.
+    async fn send_part(&mut self, body: Vec<u8>) -> Result<(), StoreErr> {
+        if self.next_part_number > MAX_PARTS {
+            return Err(StoreErr::new(&format!("{} needs more than {} parts, the part size is too small", self.key, MAX_PARTS)));
+        }
+        while self.in_flight.len() >= self.config.concurrency.max(1) {
+            self.wait_for_part().await?;
+        }
+
+        let part_number = self.next_part_number;
+        self.next_part_number += 1;
+        let (api, config) = (self.api.clone(), self.config.clone());
+        let (key, upload_id) = (self.key.clone(), self.upload_id.clone());
+        self.in_flight.push(tokio::spawn(async move {
+            upload_part_with_retries(api.as_ref(), &config, &key, &upload_id, part_number, body).await
+        }));
+        Ok(())
+    }
+
+    async fn wait_for_part(&mut self) -> Result<(), StoreErr> {
+        match self.in_flight.next().await {
+            Some(Ok(part)) => self.completed.push(part?),
+            Some(Err(e)) => return Err(StoreErr::new(&format!("Uploading a part failed: {}", e))),
+            None => (),
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<(), StoreErr> {
+        // the last part may be smaller than the minimum, and an empty object still needs one part
+        if !self.buffer.is_empty() || self.next_part_number == 1 {
+            let last = std::mem::take(&mut self.buffer);
+            self.send_part(last).await?;
+        }
+        while !self.in_flight.is_empty() {
+            self.wait_for_part().await?;
+        }
+
+        let mut parts = std::mem::take(&mut self.completed);
+        parts.sort_by_key(|part| part.part_number);
+        self.api.complete(&self.key, &self.upload_id, parts).await
+    }
+
+    /// Stops the parts still being uploaded and discards the upload.
+    async fn abort_upload(&mut self) -> Result<(), StoreErr> {
+        if self.is_aborted {
+            return Ok(());
+        }
+        self.is_aborted = true;
+        for part in self.in_flight.iter() {
+            part.abort();
+        }
+        self.in_flight.clear();
+        warn!("Aborting upload {} of {}", self.upload_id, self.key);
+        self.api.abort(&self.key, &self.upload_id).await
+    }
+
+    /// Aborts the upload after an error, and returns the error.
+    async fn fail(&mut self, err: StoreErr) -> StoreErr {
+        if let Err(abort_err) = self.abort_upload().await {
+            error!("Unable to abort upload {} of {}: {}", self.upload_id, self.key, abort_err);
+        }
+        err
+    }
+}
+
+#[async_trait]
+impl ObjectUpload for MultipartUploader {
+    async fn write(&mut self, data: &[u8]) -> Result<(), StoreErr> {
+        if self.is_aborted {
+            return Err(StoreErr::new("The upload was aborted"));
+        }
+        self.buffer.extend_from_slice(data);
+        let part_size = self.config.part_size.max(1);
+        while self.buffer.len() >= part_size {
+            let rest = self.buffer.split_off(part_size);
+            let part = std::mem::replace(&mut self.buffer, rest);
+            if let Err(e) = self.send_part(part).await {
+                return Err(self.fail(e).await);
+            }
+        }
+        Ok(())
+    }
+
+    async fn complete(mut self: Box<Self>) -> Result<(), StoreErr> {
+        if self.is_aborted {
+            return Err(StoreErr::new("The upload was aborted"));
+        }
+        match self.finish().await {
+            Ok(()) => Ok(()),
+            Err(e) => Err(self.fail(e).await),
+        }
+    }
+
+    async fn abort(mut self: Box<Self>) -> Result<(), StoreErr> {
+        self.abort_upload().await
+    }
+}
+
+/// Copies the object `from` of `size` bytes into the upload `upload_id` of `to`, which was already started,
+/// and completes it. The parts are copied like the parts of an upload, at most `concurrency` at the same time
+/// and each retried on its own. The upload is aborted when the copy fails.
+pub(crate) async fn copy_in_parts(
+    api: &dyn MultipartApi,
+    config: &UploadConfig,
+    from: &str,
+    to: &str,
+    upload_id: &str,
+    size: i64,
+    part_size: i64)
+-> Result<(), StoreErr> {
+    info!("Copying {} bytes of {} to {} in parts", size, from, to);
+    let result = match part_ranges(size, part_size) {
+        Some(ranges) => {
+            let parts: Vec<_> = ranges
+                .into_iter()
+                .map(|(part_number, first, last)| part_with_retries(config, to, part_number, move || {
+                    api.copy_part(to, upload_id, part_number, from, first, last)
+                }))
+                .collect();
+            stream::iter(parts).buffered(config.concurrency.max(1)).collect::<Vec<_>>().await.into_iter().collect()
+        },
+        None => Err(StoreErr::new(&format!("{} needs more than {} parts, the part size is too small", from, MAX_PARTS))),
+    };
+    let result = match result {
+        Ok(parts) => api.complete(to, upload_id, parts).await,
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        warn!("Aborting the copy {} of {}", upload_id, to);
+        if let Err(abort_err) = api.abort(to, upload_id).await {
+            error!("Unable to abort the copy {} of {}: {}", upload_id, to, abort_err);
+        }
+    }
+    result
+}
+
+/// Splits an object of `size` bytes into the numbers and byte ranges of its parts, `None` if there are too many.
+fn part_ranges(size: i64, part_size: i64) -> Option<Vec<(i32, i64, i64)>> {
+    let part_size = part_size.max(1);
+    let count = (size + part_size - 1) / part_size;
+    if count > MAX_PARTS as i64 {
+        return None;
+    }
+    Some((0..count)
+        .map(|i| (i as i32 + 1, i * part_size, ((i + 1) * part_size).min(size) - 1))
+        .collect())
+}
+
+async fn upload_part_with_retries(
+    api: &dyn MultipartApi,
+    config: &UploadConfig,
+    key: &str,
+    upload_id: &str,
+    part_number: i32,
+    body: Vec<u8>)
+-> Result<CompletedPart, StoreErr> {
+    part_with_retries(config, key, part_number, || api.upload_part(key, upload_id, part_number, body.clone())).await
+}
+
+/// Sends the request of a part until it succeeds, or fails more often than it is retried.
+async fn part_with_retries<F, R>(config: &UploadConfig, key: &str, part_number: i32, request: F) -> Result<CompletedPart, StoreErr>
+where
+    F: Fn() -> R,
+    R: Future<Output = Result<String, StoreErr>>,
+{
+    let mut attempt = 0;
+    loop {
+        match request().await {
+            Ok(e_tag) => return Ok(CompletedPart { part_number, e_tag }),
+            Err(e) if attempt < config.max_retries => {
+                let delay = backoff(config.base_delay, attempt);
+                warn!("Part {} of {} failed, retrying in {:?}: {}", part_number, key, delay, e);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::{BTreeMap, HashMap}, sync::Mutex};
+
+    /// A stand-in for the multipart requests of S3, which keeps the parts in memory
+    /// and fails parts on purpose.
+    #[derive(Default)]
+    struct LocalMultipart {
+        uploads: Mutex<HashMap<String, BTreeMap<i32, Vec<u8>>>>,
+        objects: Mutex<HashMap<String, Vec<u8>>>,
+        aborted: Mutex<Vec<String>>,
+        /// Number of times each part number fails, before it succeeds
+        failures: Mutex<HashMap<i32, u32>>,
+        attempts: Mutex<HashMap<i32, u32>>,
+        /// Number of parts being uploaded right now, and the most there ever were at the same time
+        active: Mutex<(usize, usize)>,
+    }
+
+    impl LocalMultipart {
+        fn failing(part_number: i32, times: u32) -> Self {
+            let api = LocalMultipart::default();
+            api.failures.lock().unwrap().insert(part_number, times);
+            api
+        }
+
+        fn open_uploads(&self) -> usize {
+            self.uploads.lock().unwrap().len()
+        }
+    }
+
+    #[async_trait]
+    impl MultipartApi for LocalMultipart {
+        async fn create(&self, key: &str) -> Result<String, StoreErr> {
+            let upload_id = format!("{}-upload", key);
+            self.uploads.lock().unwrap().insert(upload_id.clone(), BTreeMap::new());
+            Ok(upload_id)
+        }
+
+        async fn upload_part(&self, _key: &str, upload_id: &str, part_number: i32, body: Vec<u8>) -> Result<String, StoreErr> {
+            *self.attempts.lock().unwrap().entry(part_number).or_default() += 1;
+            if let Some(remaining) = self.failures.lock().unwrap().get_mut(&part_number).filter(|remaining| **remaining > 0) {
+                *remaining -= 1;
+                return Err(StoreErr::new("part failed on purpose"));
+            }
+            {
+                let mut active = self.active.lock().unwrap();
+                active.0 += 1;
+                active.1 = active.1.max(active.0);
+            }
+            // lets the other parts run, so they overlap
+            tokio::task::yield_now().await;
+            self.active.lock().unwrap().0 -= 1;
+            let mut uploads = self.uploads.lock().unwrap();
+            let parts = uploads.get_mut(upload_id).ok_or_else(|| StoreErr::new("no such upload"))?;
+            parts.insert(part_number, body);
+            Ok(format!("etag-{}", part_number))
+        }
+
+        async fn copy_part(&self, key: &str, upload_id: &str, part_number: i32, source: &str, first: i64, last: i64)
+        -> Result<String, StoreErr> {
+            let body = match self.objects.lock().unwrap().get(source) {
+                Some(object) => object[first as usize..=last as usize].to_vec(),
+                None => return Err(StoreErr::new("no such object")),
+            };
+            self.upload_part(key, upload_id, part_number, body).await
+        }
+
+        async fn complete(&self, key: &str, upload_id: &str, parts: Vec<CompletedPart>) -> Result<(), StoreErr> {
+            let uploaded = self.uploads.lock().unwrap().remove(upload_id).ok_or_else(|| StoreErr::new("no such upload"))?;
+            let numbers: Vec<i32> = parts.iter().map(|part| part.part_number).collect();
+            if numbers != uploaded.keys().copied().collect::<Vec<i32>>() {
+                return Err(StoreErr::new("parts don't match the uploaded parts"));
+            }
+            self.objects.lock().unwrap().insert(key.to_owned(), uploaded.into_values().flatten().collect());
+            Ok(())
+        }
+
+        async fn abort(&self, _key: &str, upload_id: &str) -> Result<(), StoreErr> {
+            self.uploads.lock().unwrap().remove(upload_id);
+            self.aborted.lock().unwrap().push(upload_id.to_owned());
+            Ok(())
+        }
+    }
+
+    fn test_config() -> UploadConfig {
+        UploadConfig { part_size: 10, concurrency: 3, max_retries: 2, base_delay: Duration::from_millis(1) }
+    }
+
+    fn test_data() -> Vec<u8> {
+        (0..95).collect()
+    }
+
+    async fn upload(api: &Arc<LocalMultipart>, config: &UploadConfig, data: &[u8]) -> Result<(), StoreErr> {
+        let mut upload = Box::new(MultipartUploader::start(api.clone(), "a.wav", config.clone()).await?);
+        // the chunks don't line up with the parts
+        for chunk in data.chunks(7) {
+            upload.write(chunk).await?;
+        }
+        upload.complete().await
+    }
+
+    #[tokio::test]
+    async fn test_upload_in_parts() {
+        let api = Arc::new(LocalMultipart::default());
+        upload(&api, &test_config(), &test_data()).await.unwrap();
+
+        assert_eq!(api.objects.lock().unwrap()["a.wav"], test_data());
+        assert_eq!(api.attempts.lock().unwrap().len(), 10);
+        assert_eq!(api.open_uploads(), 0);
+        let (_, max_active) = *api.active.lock().unwrap();
+        assert!((2..=3).contains(&max_active), "{} parts were uploaded at the same time", max_active);
+    }
+
+    #[tokio::test]
+    async fn test_upload_empty_object() {
+        let api = Arc::new(LocalMultipart::default());
+        upload(&api, &test_config(), &[]).await.unwrap();
+        assert_eq!(api.objects.lock().unwrap()["a.wav"], Vec::<u8>::new());
+    }
+
+    #[tokio::test]
+    async fn test_retry_part() {
+        let api = Arc::new(LocalMultipart::failing(3, 2));
+        upload(&api, &test_config(), &test_data()).await.unwrap();
+
+        assert_eq!(api.objects.lock().unwrap()["a.wav"], test_data());
+        assert_eq!(api.attempts.lock().unwrap()[&3], 3);
+        assert_eq!(api.attempts.lock().unwrap()[&4], 1);
+    }
+
+    #[tokio::test]
+    async fn test_abort_on_failure() {
+        // the part fails once more than it is retried
+        let api = Arc::new(LocalMultipart::failing(3, 3));
+        assert!(upload(&api, &test_config(), &test_data()).await.is_err());
+
+        assert!(api.objects.lock().unwrap().is_empty());
+        assert_eq!(api.open_uploads(), 0);
+        assert_eq!(*api.aborted.lock().unwrap(), vec!["a.wav-upload"]);
+
+        // the last part only fails while completing
+        let api = Arc::new(LocalMultipart::failing(10, 3));
+        assert!(upload(&api, &test_config(), &test_data()).await.is_err());
+        assert_eq!(api.open_uploads(), 0);
+    }
+
+    async fn copy(api: &LocalMultipart, data: &[u8]) -> Result<(), StoreErr> {
+        api.objects.lock().unwrap().insert("a.wav".to_owned(), data.to_vec());
+        let upload_id = api.create("trash/a.wav").await?;
+        copy_in_parts(api, &test_config(), "a.wav", "trash/a.wav", &upload_id, data.len() as i64, 10).await
+    }
+
+    #[tokio::test]
+    async fn test_copy_in_parts() {
+        let api = LocalMultipart::failing(3, 2);
+        copy(&api, &test_data()).await.unwrap();
+        assert_eq!(api.objects.lock().unwrap()["trash/a.wav"], test_data());
+        assert_eq!(api.attempts.lock().unwrap().len(), 10);
+        assert_eq!(api.open_uploads(), 0);
+
+        let api = LocalMultipart::failing(3, 3);
+        assert!(copy(&api, &test_data()).await.is_err());
+        assert!(!api.objects.lock().unwrap().contains_key("trash/a.wav"));
+        assert_eq!(*api.aborted.lock().unwrap(), vec!["trash/a.wav-upload"]);
+    }
+
+    #[test]
+    fn test_part_ranges() {
+        assert_eq!(part_ranges(25, 10), Some(vec![(1, 0, 9), (2, 10, 19), (3, 20, 24)]));
+        assert_eq!(part_ranges(20, 10), Some(vec![(1, 0, 9), (2, 10, 19)]));
+        assert_eq!(part_ranges(5 * 1024_i64.pow(4), COPY_PART_SIZE).map(|ranges| ranges.len()), Some(5120));
+        assert_eq!(part_ranges(MAX_PARTS as i64 * 10 + 1, 10), None);
+    }
+
+    #[tokio::test]
+    async fn test_abort_is_idempotent() {
+        // with a single slot, the failed part is noticed while writing the next one
+        let api = Arc::new(LocalMultipart::failing(1, 3));
+        let config = UploadConfig { concurrency: 1, ..test_config() };
+        let mut upload = Box::new(MultipartUploader::start(api.clone(), "a.wav", config).await.unwrap());
+        let mut result = Ok(());
+        for chunk in test_data().chunks(7) {
+            result = upload.write(chunk).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(result.is_err());
+        upload.abort().await.unwrap();
+        assert_eq!(api.aborted.lock().unwrap().len(), 1);
+    }
+}
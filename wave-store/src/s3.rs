@@ -0,0 +1,308 @@
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+
+use async_trait::async_trait;
+use aws_sdk_s3::{model::{CompletedMultipartUpload, CompletedPart as S3CompletedPart, Delete, ObjectIdentifier}, types::{ByteStream, SdkError}};
+use chrono::{DateTime, Utc};
+
+use crate::{multipart::{self, CompletedPart, MultipartApi, MultipartUploader, COPY_PART_SIZE}, time, BatchDeleteResult, Checksums, ListPage, ObjectInfo, ObjectUpload, StoreErr, UploadConfig, WaveStore, MAX_BATCH_SIZE};
+
+/// Keys of the user-defined metadata, which contain the checksums of an object.
+/// S3 returns them as `x-amz-meta-*` headers, so they are available without asking for the checksum explicitly.
+const SHA256_METADATA: &str = "sha256";
+const CRC32C_METADATA: &str = "crc32c";
+
+/// S3 copies objects up to 5 GiB with a single `CopyObject`, larger objects need to be copied in parts.
+const MAX_COPY_OBJECT_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+
+impl<E, R> From<SdkError<E, R>> for StoreErr
+where SdkError<E, R>: Display
+{
+    fn from(err: SdkError<E, R>) -> Self {
+        StoreErr::new(&err.to_string())
+    }
+}
+
+/// The `WaveStore` used by the lambdas, which keeps the files in an S3 bucket.
+///
+/// Uploads send several parts at the same time and retry each part on its own, see `UploadConfig`.
+pub struct S3Store {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    multipart: Arc<S3Multipart>,
+    upload_config: UploadConfig,
+}
+
+impl S3Store {
+    pub fn new(client: aws_sdk_s3::Client, bucket: &str) -> Self {
+        let multipart = Arc::new(S3Multipart { client: client.clone(), bucket: bucket.to_owned() });
+        S3Store { client, bucket: bucket.to_owned(), multipart, upload_config: UploadConfig::default() }
+    }
+
+    pub fn with_upload_config(mut self, upload_config: UploadConfig) -> Self {
+        self.upload_config = upload_config;
+        self
+    }
+}
+
+/// The multipart requests of the bucket, which are shared by all uploads of an `S3Store`.
+struct S3Multipart {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+}
+
+#[async_trait]
+impl MultipartApi for S3Multipart {
+    async fn create(&self, key: &str) -> Result<String, StoreErr> {
+        let output = self.client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .send().await?;
+        output.upload_id().map(str::to_owned).ok_or_else(|| StoreErr::new("S3 returned no upload id"))
+    }
+
+    async fn upload_part(&self, key: &str, upload_id: &str, part_number: i32, body: Vec<u8>) -> Result<String, StoreErr> {
+        let output = self.client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(body))
+            .send().await?;
+        output.e_tag().map(str::to_owned).ok_or_else(|| StoreErr::new("S3 returned no ETag for the part"))
+    }
+
+    async fn copy_part(&self, key: &str, upload_id: &str, part_number: i32, source: &str, first: i64, last: i64)
+    -> Result<String, StoreErr> {
+        let output = self.client
+            .upload_part_copy()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .copy_source(format!("{}/{}", self.bucket, encode_key(source)))
+            .copy_source_range(format!("bytes={}-{}", first, last))
+            .send().await?;
+        output
+            .copy_part_result()
+            .and_then(|result| result.e_tag())
+            .map(str::to_owned)
+            .ok_or_else(|| StoreErr::new("S3 returned no ETag for the copied part"))
+    }
+
+    async fn complete(&self, key: &str, upload_id: &str, parts: Vec<CompletedPart>) -> Result<(), StoreErr> {
+        let parts = parts
+            .into_iter()
+            .map(|part| S3CompletedPart::builder().e_tag(part.e_tag).part_number(part.part_number).build())
+            .collect();
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+            .send().await?;
+        Ok(())
+    }
+
+    async fn abort(&self, key: &str, upload_id: &str) -> Result<(), StoreErr> {
+        self.client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send().await?;
+        Ok(())
+    }
+}
+
+/// URL encodes a key for the copy source, which S3 expects as `bucket/key`.
+fn encode_key(key: &str) -> String {
+    key.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// A timestamp out of range is treated like a missing one, the cleaner skips objects without a last modification.
+fn to_utc(date_time: &aws_sdk_s3::types::DateTime) -> Option<DateTime<Utc>> {
+    time::from_smithy(date_time).ok()
+}
+
+#[async_trait]
+impl WaveStore for S3Store {
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), StoreErr> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(body))
+            .send().await?;
+        Ok(())
+    }
+
+    /// Both checksums are stored in the metadata of the object.
+    async fn put_with_checksums(&self, key: &str, body: Vec<u8>, checksums: &Checksums) -> Result<(), StoreErr> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(body))
+            .metadata(SHA256_METADATA, &checksums.sha256)
+            .metadata(CRC32C_METADATA, &checksums.crc32c)
+            .send().await?;
+        Ok(())
+    }
+
+    async fn start_upload(&self, key: &str) -> Result<Box<dyn ObjectUpload + '_>, StoreErr> {
+        let upload = MultipartUploader::start(self.multipart.clone(), key, self.upload_config.clone()).await?;
+        Ok(Box::new(upload))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreErr> {
+        match self.client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send().await {
+            Ok(output) => {
+                let body = output.body.collect().await.map_err(|e| StoreErr::new(&e.to_string()))?;
+                Ok(Some(body.into_bytes().to_vec()))
+            },
+            Err(SdkError::ServiceError { err, .. }) if err.is_no_such_key() => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>, StoreErr> {
+        match self.client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send().await {
+            Ok(output) => Ok(Some(ObjectInfo {
+                key: key.to_owned(),
+                size: output.content_length(),
+                last_modified: output.last_modified().and_then(to_utc),
+            })),
+            Err(SdkError::ServiceError { err, .. }) if err.is_not_found() => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_tags(&self, key: &str) -> Result<HashMap<String, String>, StoreErr> {
+        let output = self.client
+            .get_object_tagging()
+            .bucket(&self.bucket)
+            .key(key)
+            .send().await?;
+
+        Ok(output
+            .tag_set()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|tag| Some((tag.key()?.to_owned(), tag.value()?.to_owned())))
+            .collect())
+    }
+
+    /// The copy happens within S3, without downloading the object.
+    /// Objects larger than `MAX_COPY_OBJECT_SIZE` are copied in parts, keeping their metadata.
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StoreErr> {
+        let source = self.client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(from)
+            .send().await?;
+        if source.content_length() <= MAX_COPY_OBJECT_SIZE {
+            self.client
+                .copy_object()
+                .bucket(&self.bucket)
+                .copy_source(format!("{}/{}", self.bucket, encode_key(from)))
+                .key(to)
+                .send().await?;
+            return Ok(());
+        }
+
+        // a multipart upload doesn't take over the metadata of the source like `CopyObject` does
+        let output = self.client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(to)
+            .set_content_type(source.content_type().map(str::to_owned))
+            .set_metadata(source.metadata().cloned())
+            .send().await?;
+        let upload_id = output.upload_id().ok_or_else(|| StoreErr::new("S3 returned no upload id"))?;
+        multipart::copy_in_parts(
+            self.multipart.as_ref(), &self.upload_config, from, to, upload_id, source.content_length(), COPY_PART_SIZE,
+        ).await
+    }
+
+    async fn list(&self, prefix: Option<&str>, continuation_token: Option<&str>) -> Result<ListPage, StoreErr> {
+        let output = self.client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .set_prefix(prefix.map(str::to_owned))
+            .set_continuation_token(continuation_token.map(str::to_owned))
+            .send().await?;
+
+        let objects = output
+            .contents()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|object| Some(ObjectInfo {
+                key: object.key()?.to_owned(),
+                size: object.size(),
+                last_modified: object.last_modified().and_then(to_utc),
+            }))
+            .collect();
+
+        let next_token = match output.is_truncated() {
+            true => output.next_continuation_token().map(str::to_owned),
+            false => None,
+        };
+
+        Ok(ListPage { objects, next_token })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StoreErr> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send().await?;
+        Ok(())
+    }
+
+    async fn delete_batch(&self, keys: &[String]) -> Result<BatchDeleteResult, StoreErr> {
+        let mut result = BatchDeleteResult::default();
+
+        for batch in keys.chunks(MAX_BATCH_SIZE) {
+            let objects = batch.iter().map(|key| ObjectIdentifier::builder().key(key).build()).collect();
+            // in quiet mode, only the keys that couldn't be deleted are returned
+            let delete = Delete::builder().set_objects(Some(objects)).quiet(true).build();
+            let output = self.client
+                .delete_objects()
+                .bucket(&self.bucket)
+                .delete(delete)
+                .send().await?;
+
+            let failed: Vec<(String, StoreErr)> = output
+                .errors()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|error| Some((
+                    error.key()?.to_owned(),
+                    StoreErr::new(&format!("{}: {}", error.code().unwrap_or("Unknown"), error.message().unwrap_or_default())),
+                )))
+                .collect();
+
+            result.deleted.extend(batch.iter().filter(|key| !failed.iter().any(|(failed, _)| failed == *key)).cloned());
+            result.failed.extend(failed);
+        }
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,155 @@
+use std::{collections::HashMap, fmt::Display, error, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+/// The maximum number of keys, which can be deleted with a single request to S3.
+pub const MAX_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, PartialEq)]
+pub struct StoreErr(String);
+
+impl StoreErr {
+    pub fn new(message: &str) -> Self {
+        StoreErr(message.to_owned())
+    }
+}
+
+impl Display for StoreErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for StoreErr {}
+
+/// Exponential backoff, of which a random half is used, so requests throttled together are retried apart.
+pub fn backoff(base_delay: Duration, attempt: u32) -> Duration {
+    let delay = base_delay * 2_u32.pow(attempt);
+    // retries need no real randomness, the nanoseconds differ between requests
+    let random = Utc::now().timestamp_subsec_nanos() as f64 / 1e9;
+    delay.mul_f64(0.5 + random / 2.)
+}
+
+/// Metadata of a stored object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectInfo {
+    pub key: String,
+    /// Size in bytes
+    pub size: i64,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// Checksums of an object, which allow clients to verify a download.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checksums {
+    /// Hex encoded SHA-256 of the object
+    pub sha256: String,
+    /// Base64 encoded big-endian CRC32C of the object, in the format used by S3
+    pub crc32c: String,
+}
+
+/// A single page of a listing.
+/// If `next_token` is set, it can be passed to `WaveStore::list` to get the following page.
+#[derive(Debug, Default, PartialEq)]
+pub struct ListPage {
+    pub objects: Vec<ObjectInfo>,
+    pub next_token: Option<String>,
+}
+
+/// The outcome of a batch delete, which can fail for individual keys.
+#[derive(Debug, Default, PartialEq)]
+pub struct BatchDeleteResult {
+    pub deleted: Vec<String>,
+    pub failed: Vec<(String, StoreErr)>,
+}
+
+/// An object, which is uploaded in several parts, so it never needs to be kept in memory or on disk as a whole.
+///
+/// The object only becomes visible once the upload is completed. 
+/// An upload that fails needs to be aborted, otherwise S3 keeps the parts uploaded so far.
+#[async_trait]
+pub trait ObjectUpload: Send {
+    /// Appends data to the object.
+    async fn write(&mut self, data: &[u8]) -> Result<(), StoreErr>;
+
+    /// Stores the object, replacing an existing object with the same key.
+    async fn complete(self: Box<Self>) -> Result<(), StoreErr>;
+
+    /// Discards the data written so far.
+    async fn abort(self: Box<Self>) -> Result<(), StoreErr>;
+}
+
+/// Access to the wav files created by the sine generator.
+///
+/// The `S3Store` is used by the lambdas, while the `LocalStore` and `InMemoryStore` keep the files
+/// in a directory or in memory, so the pipeline can run without access to AWS.
+#[async_trait]
+pub trait WaveStore: Send + Sync {
+    /// Stores an object, replacing an existing object with the same key.
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), StoreErr>;
+
+    /// Stores an object together with its checksums.
+    /// Stores that don't support checksums ignore them.
+    async fn put_with_checksums(&self, key: &str, body: Vec<u8>, _checksums: &Checksums) -> Result<(), StoreErr> {
+        self.put(key, body).await
+    }
+
+    /// Starts an upload of an object in several parts, see `ObjectUpload`.
+    async fn start_upload(&self, key: &str) -> Result<Box<dyn ObjectUpload + '_>, StoreErr>;
+
+    /// Returns the contents of an object, if it exists.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, StoreErr>;
+
+    /// Returns the metadata of an object, if it exists.
+    async fn head(&self, key: &str) -> Result<Option<ObjectInfo>, StoreErr>;
+
+    /// Returns the tags of an object by their key.
+    /// Stores that don't support tags return no tags.
+    async fn get_tags(&self, _key: &str) -> Result<HashMap<String, String>, StoreErr> {
+        Ok(HashMap::new())
+    }
+
+    /// Copies an object to another key, replacing an existing object there.
+    /// The copy counts as newly written, its `last_modified` is the time of the copy.
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StoreErr> {
+        match self.get(from).await? {
+            Some(body) => self.put(to, body).await,
+            None => Err(StoreErr(format!("unable to copy {}, the object doesn't exist", from))),
+        }
+    }
+
+    /// Returns a page of objects sorted by their key, optionally limited to keys starting with `prefix`.
+    /// Pass the `next_token` of the previous page to continue a listing.
+    async fn list(&self, prefix: Option<&str>, continuation_token: Option<&str>) -> Result<ListPage, StoreErr>;
+
+    /// Deletes an object. Deleting an object that doesn't exist is not an error.
+    async fn delete(&self, key: &str) -> Result<(), StoreErr>;
+
+    /// Deletes several objects, splitting them into batches of at most `MAX_BATCH_SIZE` keys.
+    /// Keys that couldn't be deleted are reported in the result, instead of failing the whole batch.
+    async fn delete_batch(&self, keys: &[String]) -> Result<BatchDeleteResult, StoreErr>;
+
+    /// Walks through all pages of a listing.
+    async fn list_all(&self, prefix: Option<&str>) -> Result<Vec<ObjectInfo>, StoreErr> {
+        let mut objects = vec![];
+        let mut token = None;
+        loop {
+            let page = self.list(prefix, token.as_deref()).await?;
+            objects.extend(page.objects);
+            match page.next_token {
+                Some(next) => token = Some(next),
+                None => return Ok(objects),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_backoff() {
+    for attempt in 0..4 {
+        let delay = backoff(Duration::from_millis(100), attempt);
+        let full = Duration::from_millis(100) * 2_u32.pow(attempt);
+        assert!(delay >= full / 2 && delay <= full, "{:?} is outside of the backoff of attempt {}", delay, attempt);
+    }
+}
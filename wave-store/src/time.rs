@@ -0,0 +1,69 @@
+//! Conversion between the timestamps of the AWS SDK and `chrono`.
+
+use std::{error, fmt::Display};
+
+use aws_sdk_s3::types::DateTime as SmithyDateTime;
+use chrono::{DateTime, LocalResult, TimeZone, Utc};
+
+/// A timestamp that is missing, or can't be represented.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeErr(String);
+
+impl TimeErr {
+    pub fn new(message: &str) -> Self {
+        TimeErr(message.to_owned())
+    }
+}
+
+impl Display for TimeErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for TimeErr {}
+
+/// Converts a timestamp of the SDK, e.g. the last modification of an object.
+/// Both count whole seconds from the epoch and add the nanoseconds on top, also before the epoch,
+/// so no rounding is involved. Fails for timestamps outside of the range of `chrono`.
+pub fn from_smithy(date_time: &SmithyDateTime) -> Result<DateTime<Utc>, TimeErr> {
+    match Utc.timestamp_opt(date_time.secs(), date_time.subsec_nanos()) {
+        LocalResult::Single(date_time) => Ok(date_time),
+        _ => Err(TimeErr(format!("timestamp out of range: {}s {}ns", date_time.secs(), date_time.subsec_nanos()))),
+    }
+}
+
+/// Converts a timestamp for the SDK. A leap second is stored by `chrono` as more than a billion nanoseconds,
+/// which the SDK doesn't allow, so it ends up as the last nanosecond of the second before.
+pub fn to_smithy(date_time: DateTime<Utc>) -> SmithyDateTime {
+    SmithyDateTime::from_secs_and_nanos(date_time.timestamp(), date_time.timestamp_subsec_nanos().min(999_999_999))
+}
+
+#[test]
+fn test_from_smithy() {
+    let cases = [
+        // secs, nanos, expected
+        (0, 0, "1970-01-01T00:00:00Z"),
+        (-1, 500_000_000, "1969-12-31T23:59:59.500Z"),
+        (951_782_400, 0, "2000-02-29T00:00:00Z"),
+        (1_583_020_799, 999_999_999, "2020-02-29T23:59:59.999999999Z"),
+        (1_583_020_800, 0, "2020-03-01T00:00:00Z"),
+        (4_107_542_400, 0, "2100-03-01T00:00:00Z"),
+        // past the range of nanoseconds in an i64
+        (10_413_792_000, 1, "2300-01-01T00:00:00.000000001Z"),
+    ];
+    for (secs, nanos, expected) in cases {
+        let expected = DateTime::parse_from_rfc3339(expected).unwrap().with_timezone(&Utc);
+        let converted = from_smithy(&SmithyDateTime::from_secs_and_nanos(secs, nanos)).unwrap();
+        assert_eq!(converted, expected, "{}s {}ns", secs, nanos);
+        assert_eq!(to_smithy(converted), SmithyDateTime::from_secs_and_nanos(secs, nanos));
+    }
+
+    assert!(from_smithy(&SmithyDateTime::from_secs_and_nanos(i64::MAX, 0)).is_err());
+}
+
+#[test]
+fn test_to_smithy_leap_second() {
+    let leap_second = Utc.ymd(2016, 12, 31).and_hms_nano(23, 59, 59, 1_500_000_000);
+    assert_eq!(to_smithy(leap_second), SmithyDateTime::from_secs_and_nanos(1_483_228_799, 999_999_999));
+}
@@ -0,0 +1,187 @@
+use std::{collections::HashMap, fmt::Display};
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{model::AttributeValue, types::SdkError};
+use cloud_config::Config;
+
+use crate::{attributes, WaveItem, FileRef, RepositoryErr, StatsRepository, StorageStats, WaveRepository};
+
+/// Attributes of a `FileRef`, which are projected into all indexes.
+/// Items without an `object_key` don't have the attribute, `FileRef` falls back to their id.
+const FILE_ATTRIBUTES: [&str; 6] = [
+    attributes::ID, attributes::IS_DOWNLOADED, attributes::OBJECT_KEY, attributes::CONTENT_HASH, attributes::DELETED_AT, attributes::EXPIRES_AT,
+];
+/// The batch and content indexes additionally project the `error` of an item, so the status of a batch can report
+/// rejected files, and files that will never be stored aren't reused.
+const FILE_ATTRIBUTES_WITH_ERROR: [&str; 7] = [
+    attributes::ID, attributes::IS_DOWNLOADED, attributes::OBJECT_KEY, attributes::CONTENT_HASH, attributes::DELETED_AT, attributes::EXPIRES_AT, attributes::ERROR,
+];
+
+impl<E, R> From<SdkError<E, R>> for RepositoryErr
+where SdkError<E, R>: Display
+{
+    fn from(err: SdkError<E, R>) -> Self {
+        RepositoryErr::new(&err.to_string())
+    }
+}
+
+/// The `WaveRepository` used by the lambdas, which reads and writes the WaveTable in dynamoDB.
+pub struct DynamoRepository {
+    client: aws_sdk_dynamodb::Client,
+    table_name: String,
+    date_index: String,
+    content_index: String,
+    batch_index: String,
+    object_key_index: String,
+}
+
+impl DynamoRepository {
+    pub fn new(client: aws_sdk_dynamodb::Client, config: &Config) -> Self {
+        DynamoRepository {
+            client,
+            table_name: config.table_name.clone(),
+            date_index: config.global_index.clone(),
+            content_index: config.content_index.clone(),
+            batch_index: config.batch_index.clone(),
+            object_key_index: config.object_key_index.clone(),
+        }
+    }
+
+    /// Queries an index for all items whose partition key has a certain value, reading only the `projection`.
+    ///
+    /// A single query returns at most 1 MB of items, so the query is repeated from the `last_evaluated_key`
+    /// until all pages are read. If `downloaded_only` is set, items which were not downloaded are filtered out by dynamoDB.
+    async fn query_index(
+        &self,
+        index_name: &str,
+        partition_key: &str,
+        value: &str,
+        projection: &[&str],
+        downloaded_only: bool)
+    -> Result<Vec<FileRef>, RepositoryErr> {
+        // the names are passed as placeholders, since some of them (e.g. `error`) are reserved words
+        let projection_expression = projection.iter().map(|name| format!("#{}", name)).collect::<Vec<String>>().join(", ");
+
+        let mut files = vec![];
+        let mut start_key = None;
+        loop {
+            let mut query = self.client
+                .query()
+                .table_name(&self.table_name)
+                .index_name(index_name)
+                .key_condition_expression("#pk = :value")
+                .expression_attribute_names("#pk", partition_key)
+                .expression_attribute_values(":value", AttributeValue::S(value.to_owned()))
+                .projection_expression(&projection_expression)
+                .set_exclusive_start_key(start_key);
+            for name in projection {
+                query = query.expression_attribute_names(format!("#{}", name), *name);
+            }
+            if downloaded_only {
+                query = query
+                    .filter_expression("#downloaded = :downloaded")
+                    .expression_attribute_names("#downloaded", attributes::IS_DOWNLOADED)
+                    .expression_attribute_values(":downloaded", AttributeValue::Bool(true));
+            }
+            let output = query.send().await?;
+
+            for item in output.items.unwrap_or_default() {
+                files.push(FileRef::try_from(&item)?);
+            }
+            match output.last_evaluated_key {
+                Some(key) if !key.is_empty() => start_key = Some(key),
+                _ => return Ok(files),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl WaveRepository for DynamoRepository {
+    async fn put(&self, item: WaveItem) -> Result<(), RepositoryErr> {
+        let item: HashMap<String, AttributeValue> = item.into();
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(item))
+            .send().await?;
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<WaveItem>, RepositoryErr> {
+        let output = self.client
+            .get_item()
+            .table_name(&self.table_name)
+            .key(attributes::ID, AttributeValue::S(id.to_owned()))
+            .send().await?;
+
+        output.item.map(WaveItem::try_from).transpose()
+    }
+
+    async fn query_by_date(&self, date: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        self.query_index(&self.date_index, attributes::DATE, date, &FILE_ATTRIBUTES, false).await
+    }
+
+    async fn query_downloaded_by_date(&self, date: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        self.query_index(&self.date_index, attributes::DATE, date, &FILE_ATTRIBUTES, true).await
+    }
+
+    async fn query_by_content_hash(&self, content_hash: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        self.query_index(&self.content_index, attributes::CONTENT_HASH, content_hash, &FILE_ATTRIBUTES_WITH_ERROR, false).await
+    }
+
+    async fn query_by_object_key(&self, object_key: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        self.query_index(&self.object_key_index, attributes::OBJECT_KEY, object_key, &FILE_ATTRIBUTES, false).await
+    }
+
+    async fn query_by_batch(&self, batch_id: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        // the index has no sort key, ids are ULIDs so sorting them restores the order of the request
+        let mut files = self.query_index(&self.batch_index, attributes::BATCH_ID, batch_id, &FILE_ATTRIBUTES_WITH_ERROR, false).await?;
+        files.sort_by(|lhs, rhs| lhs.id.cmp(&rhs.id));
+        Ok(files)
+    }
+
+    async fn mark_deleted(&self, id: &str, deleted_at: i64) -> Result<(), RepositoryErr> {
+        // only updates the attribute, so a concurrent download isn't overwritten, and doesn't create missing items
+        let result = self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key(attributes::ID, AttributeValue::S(id.to_owned()))
+            .update_expression("SET #deleted_at = :deleted_at")
+            .condition_expression("attribute_exists(#id)")
+            .expression_attribute_names("#deleted_at", attributes::DELETED_AT)
+            .expression_attribute_names("#id", attributes::ID)
+            .expression_attribute_values(":deleted_at", AttributeValue::N(deleted_at.to_string()))
+            .send().await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(SdkError::ServiceError { err, .. }) if err.is_conditional_check_failed_exception() => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// The `StatsRepository` used by the bucket cleaner, which writes the stats table in dynamoDB.
+pub struct DynamoStatsRepository {
+    client: aws_sdk_dynamodb::Client,
+    table_name: String,
+}
+
+impl DynamoStatsRepository {
+    pub fn new(client: aws_sdk_dynamodb::Client, config: &Config) -> Self {
+        DynamoStatsRepository { client, table_name: config.stats_table.clone() }
+    }
+}
+
+#[async_trait]
+impl StatsRepository for DynamoStatsRepository {
+    async fn put_stats(&self, stats: StorageStats) -> Result<(), RepositoryErr> {
+        let item: HashMap<String, AttributeValue> = stats.into();
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(item))
+            .send().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+
+use aws_sdk_dynamodb::model::AttributeValue;
+use chrono::{DateTime, Utc, Datelike, Timelike, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, Number};
+use sine_generator::data_formats::{WavSpec, WavData};
+
+use crate::RepositoryErr;
+
+/// Names of the attributes of an item in the WaveTable.
+pub mod attributes {
+    pub const ID: &str = "id";
+    pub const REQUEST_ID: &str = "request_id";
+    pub const IS_DOWNLOADED: &str = "is_downloaded";
+    pub const SPECS: &str = "specs";
+    pub const WAV_SPEC: &str = "wav_spec";
+    pub const WAV_DATA: &str = "wav_data";
+    pub const DATE: &str = "date";
+    pub const TIME: &str = "time";
+    pub const CREATED_AT: &str = "created_at";
+    pub const CREATED_AT_MS: &str = "created_at_ms";
+    pub const EXPIRES_AT: &str = "expires_at";
+    pub const CONTENT_HASH: &str = "content_hash";
+    pub const OBJECT_KEY: &str = "object_key";
+    pub const BATCH_ID: &str = "batch_id";
+    pub const SHA256: &str = "sha256";
+    pub const CRC32C: &str = "crc32c";
+    pub const ERROR: &str = "error";
+    pub const DELETED_AT: &str = "deleted_at";
+    pub const NUMBER_OF_CHANNELS: &str = "number_of_channels";
+    pub const SAMPLE_RATE: &str = "sample_rate";
+    pub const BITS_PER_SAMPLE: &str = "bits_per_sample";
+}
+
+/// Extension of the files in the bucket
+pub const FILE_EXTENSION: &str = ".wav";
+
+/// The point in time an item was created, in the formats stored in the database.
+///
+/// `date` and `time` are the keys of the date index, `created_at` is a RFC 3339 timestamp
+/// and `created_at_ms` the milliseconds since the unix epoch, which can be used in range queries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreationTime {
+    pub date: String,
+    pub time: String,
+    pub created_at: String,
+    pub created_at_ms: i64,
+}
+
+impl From<DateTime<Utc>> for CreationTime {
+    fn from(now: DateTime<Utc>) -> Self {
+        let date = format!("{}-{:02}-{:02}", now.year(), now.month(), now.day());
+        let time = format!("{:02}:{:02}:{:02}", now.hour(), now.minute(), now.second());
+        let created_at = now.to_rfc3339_opts(SecondsFormat::Millis, true);
+        let created_at_ms = now.timestamp_millis();
+        CreationTime { date, time, created_at, created_at_ms }
+    }
+}
+
+/// An item of the WaveTable, describing a single request and the file created for it.
+///
+/// Attributes which were added over time are optional, since older items don't contain them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WaveItem {
+    pub id: String,
+    pub request_id: String,
+    pub is_downloaded: bool,
+    pub wav_spec: WavSpec,
+    pub wav_data: WavData,
+    pub date: String,
+    pub time: String,
+    pub created_at: Option<String>,
+    pub created_at_ms: Option<i64>,
+    pub expires_at: Option<i64>,
+    pub content_hash: Option<String>,
+    pub object_key: Option<String>,
+    /// Id of the batch request the item was created by, items of single requests don't belong to a batch.
+    pub batch_id: Option<String>,
+    /// Checksums of the file, set by the sine generator once the file is rendered.
+    /// `sha256` is hex encoded, `crc32c` is base64 encoded like the checksum stored by S3.
+    pub sha256: Option<String>,
+    pub crc32c: Option<String>,
+    /// Reason why the file couldn't be created, set by the sine generator instead of rendering the file.
+    pub error: Option<String>,
+    /// Time the file was removed from the bucket, in seconds since the epoch. Set by the bucket cleaner.
+    pub deleted_at: Option<i64>,
+}
+
+impl WaveItem {
+    pub fn new(id: &str,
+            request_id: &str,
+            wav_spec: WavSpec,
+            wav_data: WavData,
+            created: CreationTime,
+            expires_at: i64,
+            (content_hash, object_key): (&str, &str)) -> Self {
+        WaveItem {
+            id: id.to_owned(),
+            request_id: request_id.to_owned(),
+            is_downloaded: false,
+            wav_spec,
+            wav_data,
+            date: created.date,
+            time: created.time,
+            created_at: Some(created.created_at),
+            created_at_ms: Some(created.created_at_ms),
+            expires_at: Some(expires_at),
+            content_hash: Some(content_hash.to_owned()),
+            object_key: Some(object_key.to_owned()),
+            batch_id: None,
+            sha256: None,
+            crc32c: None,
+            error: None,
+            deleted_at: None,
+        }
+    }
+
+    /// Returns the key of the file in the bucket.
+    /// Items without an `object_key` attribute are stored under their id.
+    pub fn object_key(&self) -> String {
+        match &self.object_key {
+            Some(key) => key.clone(),
+            None => self.id.clone() + FILE_EXTENSION,
+        }
+    }
+
+    pub fn file_ref(&self) -> FileRef {
+        FileRef {
+            id: self.id.clone(),
+            is_downloaded: self.is_downloaded,
+            object_key: self.object_key(),
+            content_hash: self.content_hash.clone(),
+            error: self.error.clone(),
+            deleted_at: self.deleted_at,
+            expires_at: self.expires_at,
+        }
+    }
+}
+
+impl From<WaveItem> for HashMap<String, AttributeValue> {
+    fn from(item: WaveItem) -> Self {
+        let specs = HashMap::from([
+            (attributes::WAV_SPEC.to_owned(), serialize_attribute(&item.wav_spec)),
+            (attributes::WAV_DATA.to_owned(), serialize_attribute(&item.wav_data)),
+        ]);
+
+        let mut map = HashMap::from([
+            (attributes::ID.to_owned(), AttributeValue::S(item.id)),
+            (attributes::REQUEST_ID.to_owned(), AttributeValue::S(item.request_id)),
+            (attributes::IS_DOWNLOADED.to_owned(), AttributeValue::Bool(item.is_downloaded)),
+            (attributes::SPECS.to_owned(), AttributeValue::M(specs)),
+            (attributes::DATE.to_owned(), AttributeValue::S(item.date)),
+            (attributes::TIME.to_owned(), AttributeValue::S(item.time)),
+            // the spec is also stored as separate attributes, since it is not encoded in the id
+            (attributes::NUMBER_OF_CHANNELS.to_owned(), AttributeValue::N(item.wav_spec.number_of_channels.to_string())),
+            (attributes::SAMPLE_RATE.to_owned(), AttributeValue::N(item.wav_spec.sample_rate.to_string())),
+            (attributes::BITS_PER_SAMPLE.to_owned(), AttributeValue::N(item.wav_spec.bits_per_sample.to_string())),
+        ]);
+
+        let optional = [
+            (attributes::CREATED_AT, item.created_at.map(AttributeValue::S)),
+            (attributes::CREATED_AT_MS, item.created_at_ms.map(|ms| AttributeValue::N(ms.to_string()))),
+            (attributes::EXPIRES_AT, item.expires_at.map(|secs| AttributeValue::N(secs.to_string()))),
+            (attributes::CONTENT_HASH, item.content_hash.map(AttributeValue::S)),
+            (attributes::OBJECT_KEY, item.object_key.map(AttributeValue::S)),
+            (attributes::BATCH_ID, item.batch_id.map(AttributeValue::S)),
+            (attributes::SHA256, item.sha256.map(AttributeValue::S)),
+            (attributes::CRC32C, item.crc32c.map(AttributeValue::S)),
+            (attributes::ERROR, item.error.map(AttributeValue::S)),
+            (attributes::DELETED_AT, item.deleted_at.map(|secs| AttributeValue::N(secs.to_string()))),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                map.insert(name.to_owned(), value);
+            }
+        }
+
+        map
+    }
+}
+
+impl TryFrom<HashMap<String, AttributeValue>> for WaveItem {
+    type Error = RepositoryErr;
+
+    fn try_from(mut item: HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
+        let mut specs = match item.remove(attributes::SPECS) {
+            Some(AttributeValue::M(specs)) => specs,
+            _ => return Err(RepositoryErr::invalid_item(attributes::SPECS)),
+        };
+
+        Ok(WaveItem {
+            id: required_string(&item, attributes::ID)?,
+            request_id: required_string(&item, attributes::REQUEST_ID)?,
+            is_downloaded: bool_or_false(&item, attributes::IS_DOWNLOADED),
+            wav_spec: deserialize_attribute(specs.remove(attributes::WAV_SPEC), attributes::WAV_SPEC)?,
+            wav_data: deserialize_attribute(specs.remove(attributes::WAV_DATA), attributes::WAV_DATA)?,
+            date: required_string(&item, attributes::DATE)?,
+            time: required_string(&item, attributes::TIME)?,
+            created_at: optional_string(&item, attributes::CREATED_AT),
+            created_at_ms: optional_number(&item, attributes::CREATED_AT_MS),
+            expires_at: optional_number(&item, attributes::EXPIRES_AT),
+            content_hash: optional_string(&item, attributes::CONTENT_HASH),
+            object_key: optional_string(&item, attributes::OBJECT_KEY),
+            batch_id: optional_string(&item, attributes::BATCH_ID),
+            sha256: optional_string(&item, attributes::SHA256),
+            crc32c: optional_string(&item, attributes::CRC32C),
+            error: optional_string(&item, attributes::ERROR),
+            deleted_at: optional_number(&item, attributes::DELETED_AT),
+        })
+    }
+}
+
+/// The part of an item, which describes the file it points to.
+/// Contains the attributes projected into the indexes of the WaveTable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileRef {
+    pub id: String,
+    pub is_downloaded: bool,
+    pub object_key: String,
+    pub content_hash: Option<String>,
+    /// Only projected into the batch and content indexes.
+    pub error: Option<String>,
+    pub deleted_at: Option<i64>,
+    /// Time after which the file gets deleted, in seconds since the epoch.
+    pub expires_at: Option<i64>,
+}
+
+impl TryFrom<&HashMap<String, AttributeValue>> for FileRef {
+    type Error = RepositoryErr;
+
+    fn try_from(item: &HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
+        let id = required_string(item, attributes::ID)?;
+        let object_key = optional_string(item, attributes::OBJECT_KEY).unwrap_or_else(|| id.clone() + FILE_EXTENSION);
+        Ok(FileRef {
+            id,
+            is_downloaded: bool_or_false(item, attributes::IS_DOWNLOADED),
+            object_key,
+            content_hash: optional_string(item, attributes::CONTENT_HASH),
+            error: optional_string(item, attributes::ERROR),
+            deleted_at: optional_number(item, attributes::DELETED_AT),
+            expires_at: optional_number(item, attributes::EXPIRES_AT),
+        })
+    }
+}
+
+fn required_string(item: &HashMap<String, AttributeValue>, name: &'static str) -> Result<String, RepositoryErr> {
+    optional_string(item, name).ok_or_else(|| RepositoryErr::invalid_item(name))
+}
+
+fn optional_string(item: &HashMap<String, AttributeValue>, name: &str) -> Option<String> {
+    item.get(name).and_then(|value| value.as_s().ok()).cloned()
+}
+
+fn optional_number(item: &HashMap<String, AttributeValue>, name: &str) -> Option<i64> {
+    item.get(name).and_then(|value| value.as_n().ok()).and_then(|n| n.parse().ok())
+}
+
+fn bool_or_false(item: &HashMap<String, AttributeValue>, name: &str) -> bool {
+    matches!(item.get(name), Some(AttributeValue::Bool(true)))
+}
+
+fn serialize_attribute<T: serde::Serialize>(value: &T) -> AttributeValue {
+    // the wave formats only contain numbers and arrays, so serializing can't fail
+    value_to_attribute(serde_json::to_value(value).unwrap_or(Value::Null))
+}
+
+fn deserialize_attribute<T: serde::de::DeserializeOwned>(value: Option<AttributeValue>, name: &'static str) -> Result<T, RepositoryErr> {
+    let value = value.ok_or_else(|| RepositoryErr::invalid_item(name))?;
+    serde_json::from_value(attribute_to_value(value)).map_err(|_| RepositoryErr::invalid_item(name))
+}
+
+// found on aws examples on https://github.com/awslabs/aws-sdk-rust/blob/main/examples/dynamodb/src/bin/movies.rs
+// saved my life
+pub fn value_to_attribute(value: Value) -> AttributeValue {
+    match value {
+        Value::Null => AttributeValue::Null(true),
+        Value::Bool(b) => AttributeValue::Bool(b),
+        Value::Number(n) => AttributeValue::N(n.to_string()),
+        Value::String(s) => AttributeValue::S(s),
+        Value::Array(a) => AttributeValue::L(a.into_iter().map(value_to_attribute).collect()),
+        Value::Object(o) => {
+            AttributeValue::M(o.into_iter().map(|(k, v)| (k, value_to_attribute(v))).collect())
+        }
+    }
+}
+
+/// The inverse of `value_to_attribute`.
+/// Numbers are converted to integers if possible, and to floats otherwise.
+/// Attribute types without a json representation (binary and sets) are converted to `Value::Null`.
+pub fn attribute_to_value(attribute: AttributeValue) -> Value {
+    match attribute {
+        AttributeValue::Bool(b) => Value::Bool(b),
+        AttributeValue::N(n) => parse_number(&n).map(Value::Number).unwrap_or(Value::Null),
+        AttributeValue::S(s) => Value::String(s),
+        AttributeValue::L(l) => Value::Array(l.into_iter().map(attribute_to_value).collect()),
+        AttributeValue::M(m) => Value::Object(m.into_iter().map(|(k, v)| (k, attribute_to_value(v))).collect()),
+        _ => Value::Null,
+    }
+}
+
+fn parse_number(n: &str) -> Option<Number> {
+    if let Ok(i) = n.parse::<i64>() {
+        return Some(Number::from(i));
+    }
+    if let Ok(u) = n.parse::<u64>() {
+        return Some(Number::from(u));
+    }
+    n.parse::<f64>().ok().and_then(Number::from_f64)
+}
+
+#[cfg(test)]
+fn test_item() -> WaveItem {
+    use chrono::TimeZone;
+
+    let spec = WavSpec::new(2, 44100, 16).unwrap();
+    let data = WavData { frequencies: vec![440, 660], duration: 10, volume: 0.9 };
+    let created = CreationTime::from(Utc.ymd(2022, 7, 27).and_hms(12, 0, 0));
+    WaveItem::new("01GB6X3KQ8ZJ1V2WJZ4N4T2S9E", "567fab82-770a-44ef-8aab-d434a0b07a33", spec, data, created, 1659096000, ("abc", "01GB6X3KQ8ZJ1V2WJZ4N4T2S9E.wav"))
+}
+
+#[test]
+fn test_creation_time() {
+    use chrono::TimeZone;
+
+    let cases = [
+        (Utc.ymd(2022, 7, 26).and_hms_milli(23, 59, 59, 999), "2022-07-26", "23:59:59", "2022-07-26T23:59:59.999Z"),
+        (Utc.ymd(2022, 7, 27).and_hms(0, 0, 0), "2022-07-27", "00:00:00", "2022-07-27T00:00:00.000Z"),
+        (Utc.ymd(2022, 7, 27).and_hms(0, 30, 0), "2022-07-27", "00:30:00", "2022-07-27T00:30:00.000Z"),
+        (Utc.ymd(2022, 7, 27).and_hms(11, 59, 59), "2022-07-27", "11:59:59", "2022-07-27T11:59:59.000Z"),
+        (Utc.ymd(2022, 7, 27).and_hms(12, 0, 0), "2022-07-27", "12:00:00", "2022-07-27T12:00:00.000Z"),
+        (Utc.ymd(2022, 7, 27).and_hms(12, 30, 0), "2022-07-27", "12:30:00", "2022-07-27T12:30:00.000Z"),
+        (Utc.ymd(2022, 7, 27).and_hms(13, 0, 0), "2022-07-27", "13:00:00", "2022-07-27T13:00:00.000Z"),
+    ];
+
+    for (now, date, time, created_at) in cases {
+        let created = CreationTime::from(now);
+        assert_eq!(created.date, date);
+        assert_eq!(created.time, time);
+        assert_eq!(created.created_at, created_at);
+        assert_eq!(created.created_at_ms, now.timestamp_millis());
+    }
+}
+
+#[test]
+fn test_item_round_trip() {
+    let mut item = test_item();
+    item.batch_id = Some("01GB6X3KQ8ZJ1V2WJZ4N4T2S9D".to_owned());
+    item.sha256 = Some("a8a0f3e2c3c4d19fa4c1d3d1c7ab2f8e6c6e4e5b3b3d1c8f1b2a3c4d5e6f7a8b".to_owned());
+    item.crc32c = Some("yZRlqg==".to_owned());
+    item.error = Some("request exceeds the render budget".to_owned());
+    item.deleted_at = Some(1659268800);
+    let attributes: HashMap<String, AttributeValue> = item.clone().into();
+
+    assert_eq!(attributes[attributes::SAMPLE_RATE], AttributeValue::N("44100".to_owned()));
+    assert_eq!(WaveItem::try_from(attributes).unwrap(), item);
+}
+
+#[test]
+fn test_legacy_item() {
+    let specs = value_to_attribute(serde_json::json!({
+        "wav_spec": { "number_of_channels": 2, "sample_rate": 23000, "bits_per_sample": 16 },
+        "wav_data": { "frequencies": [440], "duration": 2, "volume": 1 }
+    }));
+    let attributes = HashMap::from([
+        (attributes::ID.to_owned(), AttributeValue::S("567fab82_2_23000_16".to_owned())),
+        (attributes::REQUEST_ID.to_owned(), AttributeValue::S("567fab82-770a-44ef-8aab-d434a0b07a33".to_owned())),
+        (attributes::IS_DOWNLOADED.to_owned(), AttributeValue::Bool(true)),
+        (attributes::SPECS.to_owned(), specs),
+        (attributes::DATE.to_owned(), AttributeValue::S("2022-07-26".to_owned())),
+        (attributes::TIME.to_owned(), AttributeValue::S("23:00:00".to_owned())),
+    ]);
+
+    let item = WaveItem::try_from(attributes).unwrap();
+    assert!(item.is_downloaded);
+    assert_eq!(item.wav_data.volume, 1.);
+    assert_eq!(item.expires_at, None);
+    assert_eq!(item.batch_id, None);
+    assert_eq!(item.sha256, None);
+    assert_eq!(item.error, None);
+    assert_eq!(item.deleted_at, None);
+    assert_eq!(item.object_key(), "567fab82_2_23000_16.wav");
+}
+
+#[test]
+fn test_invalid_item() {
+    let mut attributes: HashMap<String, AttributeValue> = test_item().into();
+    attributes.remove(attributes::SPECS);
+    assert!(WaveItem::try_from(attributes).is_err());
+}
+
+#[test]
+fn test_attribute_to_value() {
+    let value = serde_json::json!({ "a": [1, -2, 0.5], "b": "text", "c": true, "d": null });
+    assert_eq!(attribute_to_value(value_to_attribute(value.clone())), value);
+}
@@ -0,0 +1,17 @@
+//! The data model of the WaveTable, shared by all lambdas reading or writing it.
+//!
+//! Contains the typed representation of an item, its conversion from and to dynamoDB attributes,
+//! and the `WaveRepository` trait, which abstracts over the table itself.
+//! The stats table of the bucket cleaner is accessed through the `StatsRepository`.
+
+mod item;
+mod repository;
+mod dynamo;
+mod memory;
+mod stats;
+
+pub use item::{attributes, attribute_to_value, value_to_attribute, CreationTime, FileRef, WaveItem, FILE_EXTENSION};
+pub use repository::{RepositoryErr, WaveRepository};
+pub use stats::{stats_attributes, InMemoryStatsRepository, StatsRepository, StorageStats};
+pub use dynamo::{DynamoRepository, DynamoStatsRepository};
+pub use memory::InMemoryRepository;
@@ -0,0 +1,136 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
+
+use crate::{WaveItem, FileRef, RepositoryErr, WaveRepository};
+
+/// A `WaveRepository` which keeps all items in memory.
+/// Intended for tests, or for running the lambdas without access to dynamoDB.
+#[derive(Default)]
+pub struct InMemoryRepository {
+    items: Mutex<HashMap<String, WaveItem>>,
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of all items, sorted by their id.
+    pub fn items(&self) -> Vec<WaveItem> {
+        let mut items: Vec<WaveItem> = self.lock().values().cloned().collect();
+        items.sort_by(|lhs, rhs| lhs.id.cmp(&rhs.id));
+        items
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, WaveItem>> {
+        // a panic while holding the lock can't leave the map in an inconsistent state
+        self.items.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn query<P: Fn(&WaveItem) -> bool>(&self, predicate: P) -> Vec<FileRef> {
+        let mut files: Vec<FileRef> = self.lock().values().filter(|item| predicate(item)).map(WaveItem::file_ref).collect();
+        files.sort_by(|lhs, rhs| lhs.id.cmp(&rhs.id));
+        files
+    }
+}
+
+#[async_trait]
+impl WaveRepository for InMemoryRepository {
+    async fn put(&self, item: WaveItem) -> Result<(), RepositoryErr> {
+        self.lock().insert(item.id.clone(), item);
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<WaveItem>, RepositoryErr> {
+        Ok(self.lock().get(id).cloned())
+    }
+
+    async fn query_by_date(&self, date: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        Ok(self.query(|item| item.date == date))
+    }
+
+    async fn query_by_content_hash(&self, content_hash: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        Ok(self.query(|item| item.content_hash.as_deref() == Some(content_hash)))
+    }
+
+    async fn query_by_object_key(&self, object_key: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        Ok(self.query(|item| item.object_key.as_deref() == Some(object_key)))
+    }
+
+    async fn query_by_batch(&self, batch_id: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        Ok(self.query(|item| item.batch_id.as_deref() == Some(batch_id)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_item(id: &str, date: &str, content_hash: &str) -> WaveItem {
+        use chrono::{TimeZone, Utc};
+        use sine_generator::data_formats::{WavSpec, WavData};
+
+        let spec = WavSpec::new(1, 8000, 8).unwrap();
+        let data = WavData { frequencies: vec![440], duration: 2, volume: 1. };
+        let mut created = crate::CreationTime::from(Utc.ymd(2022, 7, 27).and_hms(12, 0, 0));
+        created.date = date.to_owned();
+        WaveItem::new(id, "request", spec, data, created, 0, (content_hash, &format!("{}.wav", id)))
+    }
+
+    #[tokio::test]
+    async fn test_put_and_get() {
+        let repository = InMemoryRepository::new();
+        let item = test_item("a", "2022-07-27", "hash");
+
+        repository.put(item.clone()).await.unwrap();
+        assert_eq!(repository.get("a").await.unwrap(), Some(item));
+        assert_eq!(repository.get("b").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_queries() {
+        let repository = InMemoryRepository::new();
+        repository.put(test_item("a", "2022-07-26", "first")).await.unwrap();
+        repository.put(test_item("b", "2022-07-27", "first")).await.unwrap();
+        let mut batch_item = test_item("c", "2022-07-27", "second");
+        batch_item.batch_id = Some("batch".to_owned());
+        repository.put(batch_item).await.unwrap();
+
+        let by_date: Vec<String> = repository.query_by_date("2022-07-27").await.unwrap().into_iter().map(|file| file.id).collect();
+        assert_eq!(by_date, vec!["b", "c"]);
+
+        let mut downloaded = repository.get("c").await.unwrap().unwrap();
+        downloaded.is_downloaded = true;
+        repository.put(downloaded).await.unwrap();
+        let downloaded: Vec<String> = repository.query_downloaded_by_date("2022-07-27").await.unwrap().into_iter().map(|file| file.id).collect();
+        assert_eq!(downloaded, vec!["c"]);
+
+        let by_hash: Vec<String> = repository.query_by_content_hash("first").await.unwrap().into_iter().map(|file| file.object_key).collect();
+        assert_eq!(by_hash, vec!["a.wav", "b.wav"]);
+
+        let by_batch: Vec<String> = repository.query_by_batch("batch").await.unwrap().into_iter().map(|file| file.id).collect();
+        assert_eq!(by_batch, vec!["c"]);
+
+        // items of identical requests point to the file of the first one
+        let mut reused = test_item("d", "2022-07-28", "first");
+        reused.object_key = Some("a.wav".to_owned());
+        repository.put(reused).await.unwrap();
+        let by_object_key: Vec<String> = repository.query_by_object_key("a.wav").await.unwrap().into_iter().map(|file| file.id).collect();
+        assert_eq!(by_object_key, vec!["a", "d"]);
+    }
+
+    #[tokio::test]
+    async fn test_mark_deleted() {
+        let repository = InMemoryRepository::new();
+        repository.put(test_item("a", "2022-07-27", "hash")).await.unwrap();
+
+        repository.mark_deleted("a", 1659268800).await.unwrap();
+        assert_eq!(repository.get("a").await.unwrap().unwrap().deleted_at, Some(1659268800));
+        assert_eq!(repository.query_by_date("2022-07-27").await.unwrap()[0].deleted_at, Some(1659268800));
+
+        // missing items are not created
+        repository.mark_deleted("b", 1659268800).await.unwrap();
+        assert_eq!(repository.items().len(), 1);
+    }
+}
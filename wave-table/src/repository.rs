@@ -0,0 +1,74 @@
+use std::{fmt::Display, error};
+
+use async_trait::async_trait;
+
+use crate::{WaveItem, FileRef, FILE_EXTENSION};
+
+#[derive(Debug, PartialEq)]
+pub struct RepositoryErr(String);
+
+impl RepositoryErr {
+    pub fn new(message: &str) -> Self {
+        RepositoryErr(message.to_owned())
+    }
+
+    /// An item is missing a required attribute, or the attribute has an unexpected type.
+    pub(crate) fn invalid_item(attribute: &str) -> Self {
+        RepositoryErr(format!("item contains no valid attribute '{}'", attribute))
+    }
+}
+
+impl Display for RepositoryErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for RepositoryErr {}
+
+/// Access to the items of the WaveTable.
+///
+/// The `DynamoRepository` is used by the lambdas, while the `InMemoryRepository` can be used in tests.
+#[async_trait]
+pub trait WaveRepository: Send + Sync {
+    /// Inserts an item, replacing an existing item with the same id.
+    async fn put(&self, item: WaveItem) -> Result<(), RepositoryErr>;
+
+    /// Returns the item with the given id, if it exists.
+    async fn get(&self, id: &str) -> Result<Option<WaveItem>, RepositoryErr>;
+
+    /// Returns the item, which created the file stored under `object_key`.
+    /// Files are stored under the id of the item that created them, other items with the same content point to it.
+    async fn get_by_object_key(&self, object_key: &str) -> Result<Option<WaveItem>, RepositoryErr> {
+        self.get(object_key.strip_suffix(FILE_EXTENSION).unwrap_or(object_key)).await
+    }
+
+    /// Returns the files of all items created at a certain date.
+    /// The date needs to be in the form of "yyyy-mm-dd".
+    async fn query_by_date(&self, date: &str) -> Result<Vec<FileRef>, RepositoryErr>;
+
+    /// Returns the files of all items created at a certain date, which were downloaded.
+    /// Repositories that can filter while reading should override it, instead of returning all items of the day.
+    async fn query_downloaded_by_date(&self, date: &str) -> Result<Vec<FileRef>, RepositoryErr> {
+        Ok(self.query_by_date(date).await?.into_iter().filter(|file| file.is_downloaded).collect())
+    }
+
+    /// Returns the files of all items, which were created from requests with the given content hash.
+    async fn query_by_content_hash(&self, content_hash: &str) -> Result<Vec<FileRef>, RepositoryErr>;
+
+    /// Returns the files of all items pointing to the file stored under `object_key`, the item that created it
+    /// as well as the items of identical requests reusing it. Items without an `object_key` attribute are missing.
+    async fn query_by_object_key(&self, object_key: &str) -> Result<Vec<FileRef>, RepositoryErr>;
+
+    /// Returns the files of all items, which were created by the batch request with the given id, sorted by their id.
+    async fn query_by_batch(&self, batch_id: &str) -> Result<Vec<FileRef>, RepositoryErr>;
+
+    /// Records that the file of the item was removed from the bucket at `deleted_at`, in seconds since the epoch.
+    /// Items that don't exist are ignored. Repositories that can update single attributes should override it.
+    async fn mark_deleted(&self, id: &str, deleted_at: i64) -> Result<(), RepositoryErr> {
+        match self.get(id).await? {
+            Some(item) => self.put(WaveItem { deleted_at: Some(deleted_at), ..item }).await,
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! The stats table, which keeps a summary of the bucket for each run of the bucket cleaner,
+//! so the storage can be charted over time.
+
+use std::{collections::{BTreeMap, HashMap}, sync::Mutex};
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::model::AttributeValue;
+use serde::Serialize;
+
+use crate::RepositoryErr;
+
+/// Names of the attributes of a stats item.
+pub mod stats_attributes {
+    pub const DATE: &str = "date";
+    pub const CREATED_AT: &str = "created_at";
+    pub const OBJECT_COUNT: &str = "object_count";
+    pub const TOTAL_BYTES: &str = "total_bytes";
+    pub const BYTES_BY_AGE: &str = "bytes_by_age";
+    pub const BYTES_BY_FORMAT: &str = "bytes_by_format";
+    pub const BYTES_BY_SAMPLE_RATE: &str = "bytes_by_sample_rate";
+    pub const RECLAIMED_BYTES: &str = "reclaimed_bytes";
+}
+
+/// The storage used by the bucket after a run of the cleaner.
+///
+/// `date` is the partition key of the stats table and `created_at` the sort key,
+/// so all runs of a day can be queried in order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StorageStats {
+    pub date: String,
+    /// Time of the run as RFC 3339 timestamp.
+    pub created_at: String,
+    pub object_count: i64,
+    pub total_bytes: i64,
+    /// Bytes by the age of the objects, e.g. `"2-7d"`.
+    pub bytes_by_age: BTreeMap<String, i64>,
+    /// Bytes by the extension of the keys, e.g. `"wav"` or `"zip"`.
+    pub bytes_by_format: BTreeMap<String, i64>,
+    /// Bytes by the sample rate encoded in legacy keys, keys without a sample rate are counted as `"unknown"`.
+    pub bytes_by_sample_rate: BTreeMap<String, i64>,
+    /// Bytes of the files deleted during the run.
+    pub reclaimed_bytes: i64,
+}
+
+fn bytes_to_attribute(bytes: BTreeMap<String, i64>) -> AttributeValue {
+    AttributeValue::M(bytes.into_iter().map(|(name, bytes)| (name, AttributeValue::N(bytes.to_string()))).collect())
+}
+
+impl From<StorageStats> for HashMap<String, AttributeValue> {
+    fn from(stats: StorageStats) -> Self {
+        HashMap::from([
+            (stats_attributes::DATE.to_owned(), AttributeValue::S(stats.date)),
+            (stats_attributes::CREATED_AT.to_owned(), AttributeValue::S(stats.created_at)),
+            (stats_attributes::OBJECT_COUNT.to_owned(), AttributeValue::N(stats.object_count.to_string())),
+            (stats_attributes::TOTAL_BYTES.to_owned(), AttributeValue::N(stats.total_bytes.to_string())),
+            (stats_attributes::BYTES_BY_AGE.to_owned(), bytes_to_attribute(stats.bytes_by_age)),
+            (stats_attributes::BYTES_BY_FORMAT.to_owned(), bytes_to_attribute(stats.bytes_by_format)),
+            (stats_attributes::BYTES_BY_SAMPLE_RATE.to_owned(), bytes_to_attribute(stats.bytes_by_sample_rate)),
+            (stats_attributes::RECLAIMED_BYTES.to_owned(), AttributeValue::N(stats.reclaimed_bytes.to_string())),
+        ])
+    }
+}
+
+/// Access to the stats table.
+#[async_trait]
+pub trait StatsRepository: Send + Sync {
+    /// Stores the stats of a run, replacing stats with the same date and time.
+    async fn put_stats(&self, stats: StorageStats) -> Result<(), RepositoryErr>;
+}
+
+/// A `StatsRepository` which keeps the stats in memory.
+/// Intended for tests, or for running the cleaner without access to dynamoDB.
+#[derive(Default)]
+pub struct InMemoryStatsRepository {
+    stats: Mutex<Vec<StorageStats>>,
+}
+
+impl InMemoryStatsRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of the stats in the order they were stored.
+    pub fn stats(&self) -> Vec<StorageStats> {
+        self.stats.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+#[async_trait]
+impl StatsRepository for InMemoryStatsRepository {
+    async fn put_stats(&self, stats: StorageStats) -> Result<(), RepositoryErr> {
+        let mut all = self.stats.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        all.retain(|other| other.date != stats.date || other.created_at != stats.created_at);
+        all.push(stats);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_stats_to_attributes() {
+    let stats = StorageStats {
+        date: "2022-07-27".to_owned(),
+        created_at: "2022-07-27T12:00:00.000Z".to_owned(),
+        object_count: 2,
+        total_bytes: 300,
+        bytes_by_age: BTreeMap::from([("0-1d".to_owned(), 300)]),
+        ..Default::default()
+    };
+    let item: HashMap<String, AttributeValue> = stats.into();
+    assert_eq!(item[stats_attributes::DATE], AttributeValue::S("2022-07-27".to_owned()));
+    assert_eq!(item[stats_attributes::TOTAL_BYTES], AttributeValue::N("300".to_owned()));
+    assert_eq!(item[stats_attributes::BYTES_BY_AGE], AttributeValue::M(HashMap::from([("0-1d".to_owned(), AttributeValue::N("300".to_owned()))])));
+    assert_eq!(item[stats_attributes::BYTES_BY_FORMAT], AttributeValue::M(HashMap::new()));
+}